@@ -0,0 +1,13 @@
+; Prints every input byte on its own line until the input ends.
+.start:
+    in      .end
+    st      [CHAR]
+    print   [STR]
+    jmp     .start
+.end:
+    stop
+
+.STR:
+    .byte   $1
+.CHAR:
+    .byte   $0   ; will be overwritten
@@ -0,0 +1,80 @@
+use std::{
+    env,
+    error::Error,
+    fs,
+    ops::RangeInclusive,
+    path::PathBuf,
+};
+
+use assembler::{codegen, disasm, instr::InstructionSet};
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // Get CLI arguments or print usage when they are invalid
+    let args = match Args::from_env() {
+        Some(args) => args,
+        None => {
+            println!("<input> argument missing!");
+            println!();
+            println!("Usage:");
+            println!("  disassemble <input> [-o <output>] [--isa <file>]");
+            println!("              [--bank-window <start>-<end>]");
+            println!();
+            println!("If no output is given, the source code is printed. `--isa` uses");
+            println!("the instructions described in <file> instead of the ones of the");
+            println!("SHiT CPU. Banks after bank 0 are disassembled at their bank window,");
+            println!("$80 to $df unless `--bank-window` moves it, like `--bank-window 40-7f`.");
+            std::process::exit(1);
+        }
+    };
+
+    let bin = fs::read(&args.input)?;
+    if bin.len() > 256 * 256 {
+        return Err("binary has more than 256 memory banks".into());
+    }
+
+    let isa = match &args.isa {
+        Some(path) => InstructionSet::load(path)?,
+        None => InstructionSet::builtin().clone(),
+    };
+
+    let src = disasm::disassemble_with_window(&bin, &isa, args.bank_window.clone());
+    match args.output {
+        Some(path) => fs::write(path, src)?,
+        None => print!("{}", src),
+    }
+
+    Ok(())
+}
+
+/// The command line arguments.
+struct Args {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    isa: Option<PathBuf>,
+    bank_window: RangeInclusive<u8>,
+}
+
+impl Args {
+    /// Parses the arguments passed to this program. Returns `None` if they are
+    /// invalid.
+    fn from_env() -> Option<Self> {
+        let mut input = None;
+        let mut output = None;
+        let mut isa = None;
+        let mut bank_window = codegen::DEFAULT_BANK_WINDOW;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => output = Some(PathBuf::from(args.next()?)),
+                "--isa" => isa = Some(PathBuf::from(args.next()?)),
+                "--bank-window" => bank_window = codegen::parse_window(&args.next()?)?,
+                _ if input.is_none() => input = Some(PathBuf::from(arg)),
+                _ => return None,
+            }
+        }
+
+        Some(Self { input: input?, output, isa, bank_window })
+    }
+}
@@ -0,0 +1,321 @@
+//! Turns a parsed program into the binary the emulator can run.
+//!
+//! This works in two passes: the first one lays out all lines to find the
+//! address of every label, the second one encodes all instructions and
+//! directives with the labels resolved to their addresses.
+//!
+//! Programs larger than the address space are split into memory banks of 256
+//! bytes with `.bank` directives. In the binary, every bank follows the
+//! previous one. The content of every bank but bank 0 has to be inside the
+//! bank window, since the CPU can't see it anywhere else.
+
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use crate::{
+    diag::Diag,
+    instr::Arg,
+    parse::{Directive, Line, Program},
+    span::{Span, Spanned},
+};
+
+
+/// The number of bytes the CPU can address, which is also the size of a
+/// memory bank.
+const ADDRESS_SPACE_SIZE: usize = 256;
+
+/// The addresses the selected bank is visible at, unless the emulator is
+/// configured otherwise. Matches the default of the emulator.
+pub const DEFAULT_BANK_WINDOW: RangeInclusive<u8> = 0x80..=0xdf;
+
+/// Parses a bank window like `40-7f`, the same way the emulator does. The
+/// window may neither be empty nor contain address 0, where programs start.
+pub fn parse_window(s: &str) -> Option<RangeInclusive<u8>> {
+    let (start, end) = s.split_once('-')?;
+    let start = u8::from_str_radix(start, 16).ok()?;
+    let end = u8::from_str_radix(end, 16).ok()?;
+    if start == 0 || start > end {
+        return None;
+    }
+    Some(start..=end)
+}
+
+/// The bank and address of a label.
+type Location = (u8, usize);
+
+/// The result of assembling a program.
+#[derive(Debug, Clone)]
+pub struct Output {
+    /// The binary which can be loaded by the emulator.
+    pub bin: Vec<u8>,
+
+    /// All labels with their bank and address, sorted by bank and address.
+    /// Labels in different banks may have the same address.
+    pub labels: Vec<(String, u8, u8)>,
+
+    /// Where every instruction and byte directive was placed, sorted by
+    /// address.
+    pub lines: Vec<SourceLine>,
+}
+
+/// The address of an instruction or byte directive and the line it was
+/// assembled from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLine {
+    pub bank: u8,
+    pub addr: u8,
+
+    /// The 1-based line number.
+    pub line: usize,
+
+    /// Whether the line is a byte directive instead of an instruction.
+    pub data: bool,
+}
+
+impl Output {
+    /// Returns the contents of the symbol file for this program. Each line
+    /// contains the hex address and the name of a label, like `02 start`.
+    /// Labels in other banks than bank 0 are prefixed with their bank, like
+    /// `01:80 draw`, since they may share addresses with each other.
+    pub fn symbol_file(&self) -> String {
+        self.labels
+            .iter()
+            .map(|(name, bank, addr)| match bank {
+                0 => format!("{:02x} {}\n", addr, name),
+                _ => format!("{:02x}:{:02x} {}\n", bank, addr, name),
+            })
+            .collect()
+    }
+
+    /// Returns the contents of the source map for this program. The first
+    /// line names the source file, like `file asm/magic-1.s`. All other lines
+    /// contain the hex address of an instruction or byte and the line number
+    /// it was assembled from, like `02 4`. Bytes are marked with `data`, like
+    /// `11 15 data`.
+    ///
+    /// Only lines in bank 0 are included, since the emulator can't tell the
+    /// banks apart by address.
+    pub fn source_map_file(&self, source_path: &str) -> String {
+        let mut out = format!("file {}\n", source_path);
+        for l in self.lines.iter().filter(|l| l.bank == 0) {
+            let marker = if l.data { " data" } else { "" };
+            out.push_str(&format!("{:02x} {}{}\n", l.addr, l.line, marker));
+        }
+
+        out
+    }
+}
+
+/// Assembles the given program into its binary representation.
+///
+/// `src` needs to be the source code the program was parsed from. If any
+/// errors occur, the errors are printed and `Err(())` is returned.
+pub fn assemble(src: &str, program: &Program) -> Result<Output, ()> {
+    assemble_with_window(src, program, DEFAULT_BANK_WINDOW)
+}
+
+/// Like `assemble`, but the lines of banks other than bank 0 have to be
+/// inside `window` instead of `DEFAULT_BANK_WINDOW`.
+pub fn assemble_with_window(
+    src: &str,
+    program: &Program,
+    window: RangeInclusive<u8>,
+) -> Result<Output, ()> {
+    let mut diags = Vec::new();
+
+    let labels = layout(src, program, &window, &mut diags);
+    let (bin, lines) = encode(src, program, &labels, &mut diags);
+
+    if diags.is_empty() {
+        // Labels pointing past the end of the address space can't be used
+        // anyway, so they are not part of the output.
+        let mut labels = labels
+            .into_iter()
+            .filter(|(_, (_, addr))| *addr < ADDRESS_SPACE_SIZE)
+            .map(|(name, (bank, addr))| (name, bank, addr as u8))
+            .collect::<Vec<_>>();
+        labels.sort_by(|a, b| (a.1, a.2, &a.0).cmp(&(b.1, b.2, &b.0)));
+
+        Ok(Output { bin, labels, lines })
+    } else {
+        for (diag, line_span) in diags {
+            let line = &src[line_span.lo..line_span.hi];
+            diag.emit(line, line_number(src, line_span));
+        }
+        Err(())
+    }
+}
+
+/// The first pass: calculates the bank and address of every label.
+///
+/// Errors are pushed to `diags`, paired with the span of the line they refer
+/// to.
+fn layout(
+    src: &str,
+    program: &Program,
+    window: &RangeInclusive<u8>,
+    diags: &mut Vec<(Diag, Span)>,
+) -> HashMap<String, Location> {
+    let mut labels: HashMap<String, (Location, usize)> = HashMap::new();
+    let mut bank = 0;
+    let mut addr = 0;
+    let mut overflowed = false;
+
+    for (line_idx, line) in program.lines.iter().enumerate() {
+        let len = match &line.data {
+            Line::Label(name) => {
+                if let Some(&(_, first_idx)) = labels.get(&name.data) {
+                    let first_line = &program.lines[first_idx];
+                    let msg = format!("label '{}' is defined multiple times", name.data);
+                    let diag = Diag::span_error(name.span, msg).add_note(format!(
+                        "first defined in line {}",
+                        line_number(src, first_line.span) + 1,
+                    ));
+                    diags.push((diag, line.span));
+                } else {
+                    labels.insert(name.data.clone(), ((bank, addr), line_idx));
+                }
+
+                0
+            }
+            Line::Directive(Directive::Byte(_)) => 1,
+            Line::Directive(Directive::Bank { bank: next, addr: start }) => {
+                if *next > bank {
+                    bank = *next;
+                    addr = *start as usize;
+                    overflowed = false;
+                } else {
+                    let msg = "banks have to be in increasing order";
+                    let diag = Diag::span_error(Span::new(0, line.span.len()), msg)
+                        .add_note(format!("the lines before are in bank {}", bank));
+                    diags.push((diag, line.span));
+                }
+
+                0
+            }
+            Line::Instruction(instr) => instr.len() as usize,
+        };
+
+        let start = addr;
+        addr += len;
+
+        // Only report the first line that doesn't fit anymore. All following
+        // lines don't fit either, obviously. Other banks are only visible in
+        // the bank window, which also ends before the end of the address
+        // space.
+        let outside = start < *window.start() as usize || addr > *window.end() as usize + 1;
+        if bank > 0 && len > 0 && outside && !overflowed {
+            overflowed = true;
+            let msg = "bank does not fit into the bank window";
+            let diag = Diag::span_error(Span::new(0, line.span.len()), msg)
+                .add_note(format!(
+                    "bank {} is only visible at ${:02x} to ${:02x}",
+                    bank,
+                    window.start(),
+                    window.end(),
+                ))
+                .add_note(format!("this line is at ${:02x} to ${:02x}", start, addr - 1));
+            diags.push((diag, line.span));
+        } else if addr > ADDRESS_SPACE_SIZE && !overflowed {
+            overflowed = true;
+            let msg = "program does not fit into the address space";
+            let diag = Diag::span_error(Span::new(0, line.span.len()), msg)
+                .add_note(format!("only {} bytes can be addressed", ADDRESS_SPACE_SIZE))
+                .add_note(format!("this line ends at byte {}", addr));
+            diags.push((diag, line.span));
+        }
+    }
+
+    labels.into_iter().map(|(name, (location, _))| (name, location)).collect()
+}
+
+/// The second pass: encodes all lines into bytes. Also returns the address
+/// and line number of every instruction and byte, see `Output::lines`.
+///
+/// Errors are pushed to `diags`, paired with the span of the line they refer
+/// to.
+fn encode(
+    src: &str,
+    program: &Program,
+    labels: &HashMap<String, Location>,
+    diags: &mut Vec<(Diag, Span)>,
+) -> (Vec<u8>, Vec<SourceLine>) {
+    let mut out = Vec::new();
+    let mut lines = Vec::new();
+    let mut bank = 0;
+
+    for line in &program.lines {
+        // If the bank doesn't fit, `layout` already reported an error
+        let addr = out.len() - bank as usize * ADDRESS_SPACE_SIZE;
+        let placed = !matches!(line.data, Line::Label(_) | Line::Directive(Directive::Bank { .. }));
+        if placed && addr < ADDRESS_SPACE_SIZE {
+            lines.push(SourceLine {
+                bank,
+                addr: addr as u8,
+                line: line_number(src, line.span) + 1,
+                data: matches!(line.data, Line::Directive(_)),
+            });
+        }
+
+        match &line.data {
+            Line::Label(_) => {}
+            Line::Directive(Directive::Byte(v)) => out.push(*v),
+
+            // Banks in the wrong order were reported by `layout`
+            Line::Directive(Directive::Bank { bank: next, addr: start }) => {
+                if *next > bank {
+                    bank = *next;
+                    out.resize(bank as usize * ADDRESS_SPACE_SIZE + *start as usize, 0);
+                }
+            }
+            Line::Instruction(instr) => {
+                out.push(instr.opcode);
+                for arg in &instr.args {
+                    out.push(resolve(arg, labels, bank).unwrap_or_else(|diag| {
+                        diags.push((diag, line.span));
+                        0
+                    }));
+                }
+            }
+        }
+    }
+
+    (out, lines)
+}
+
+/// Returns the value of the given argument in a line in `bank`. Two banks
+/// other than bank 0 are never visible at the same time, so their lines
+/// can't use each other's labels.
+fn resolve(arg: &Arg, labels: &HashMap<String, Location>, bank: u8) -> Result<u8, Diag> {
+    match arg {
+        Arg::Value(v) => Ok(*v),
+        Arg::Label(Spanned { data: name, span }) => match labels.get(name) {
+            Some(&(_, addr)) if addr >= ADDRESS_SPACE_SIZE => {
+                let msg = format!("label '{}' points outside of the address space", name);
+                Err(Diag::span_error(*span, msg)
+                    .add_note(format!("the label is at address {}", addr)))
+            }
+            Some(&(label_bank, addr)) if [0, bank].contains(&label_bank) || bank == 0 => {
+                Ok(addr as u8)
+            }
+            Some(&(label_bank, _)) => {
+                let msg = format!("label '{}' is in another bank", name);
+                Err(Diag::span_error(*span, msg)
+                    .add_note(format!(
+                        "the label is in bank {}, this line in bank {}",
+                        label_bank,
+                        bank,
+                    ))
+                    .add_note("only labels in bank 0 and the same bank are visible"))
+            }
+            None => {
+                let msg = format!("undefined label '{}'", name);
+                Err(Diag::span_error(*span, msg))
+            }
+        },
+    }
+}
+
+/// Returns the 0-based number of the line the given span starts in.
+fn line_number(src: &str, span: Span) -> usize {
+    src[..span.lo].matches('\n').count()
+}
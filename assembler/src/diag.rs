@@ -13,7 +13,6 @@ pub struct Diag {
 
 impl Diag {
     /// Creates a new error diag with the given message.
-    #[allow(dead_code)] // TODO: remove once used
     pub fn error(msg: impl Into<String>) -> Self {
         Self {
             msg: msg.into(),
@@ -37,14 +36,28 @@ impl Diag {
         self
     }
 
+    /// Returns the error message.
+    pub fn message(&self) -> &str {
+        &self.msg
+    }
+
+    /// Returns the notes added to this diagnostic.
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    /// Returns the span this diagnostic points to, if any.
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
     /// Print the diagnostic on the terminal.
     ///
     /// - `line` needs to be the line the span in this diagnostic points to.
     /// - `line_number` is the 0-based number of the line the error originated
-    /// in.
+    ///   in.
     pub fn emit(self, line: &str, line_number: usize) {
         use term_painter::{ToStyle, Color};
-        use std::iter;
 
         // Print error message
         println!(
@@ -57,12 +70,12 @@ impl Diag {
         // Format line number (in our program it's 0-based, but humans like
         // it 1-based)
         let num = (line_number + 1).to_string();
-        let num_placeholder = iter::repeat(' ').take(num.len()).collect::<String>();
+        let num_placeholder = " ".repeat(num.len());
 
         // If a span was provided, underline the span in source code
         if let Some(span) = self.span {
-            let before_underline = iter::repeat(' ').take(span.lo).collect::<String>();
-            let underline = iter::repeat('^').take(span.len()).collect::<String>();
+            let before_underline = " ".repeat(span.lo);
+            let underline = "^".repeat(span.len());
 
             println!(
                 "{} {} {}",
@@ -89,6 +102,6 @@ impl Diag {
             );
         }
 
-        println!("");
+        println!();
     }
 }
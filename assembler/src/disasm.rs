@@ -0,0 +1,177 @@
+//! Turns a binary back into source code the assembler accepts.
+//!
+//! The disassembler follows the control flow from address 0 to find out which
+//! bytes are executed as instructions. All other bytes are emitted as `.byte`
+//! directives. Jump targets and addresses of memory operands get labels, as
+//! long as they point to the start of an emitted line.
+//!
+//! Binaries larger than 256 bytes contain more than one memory bank. Only
+//! bank 0 is disassembled, since it's unknown where the code in the other
+//! banks starts. The bytes of their bank window are emitted as `.byte`
+//! directives after a `.bank` directive. All other bytes of these banks are
+//! never visible to the CPU and are left out.
+
+use std::{collections::BTreeMap, ops::RangeInclusive};
+
+use shit_isa::{InstructionDef, InstructionSet, OperandKind};
+
+use crate::codegen::DEFAULT_BANK_WINDOW;
+
+
+/// A single line of the disassembled program.
+enum Item<'a> {
+    /// An instruction that is executed, with its arguments.
+    Instruction(&'a InstructionDef, &'a [u8]),
+
+    /// A byte that is not executed as code.
+    Byte(u8),
+}
+
+/// Disassembles the given binary with the instructions of the SHiT CPU.
+/// Assembling the returned source code results in exactly the same binary
+/// again, unless banks after bank 0 contain bytes outside the bank window.
+pub fn disassemble(bin: &[u8]) -> String {
+    disassemble_with(bin, InstructionSet::builtin())
+}
+
+/// Like `disassemble`, but with the instructions described by `isa`.
+///
+/// Panics if the binary has more than 256 banks.
+pub fn disassemble_with(bin: &[u8], isa: &InstructionSet) -> String {
+    disassemble_with_window(bin, isa, DEFAULT_BANK_WINDOW)
+}
+
+/// Like `disassemble_with`, but the banks after bank 0 are visible in
+/// `window` instead of `DEFAULT_BANK_WINDOW`, as for
+/// `codegen::assemble_with_window`.
+///
+/// Panics if the binary has more than 256 banks.
+pub fn disassemble_with_window(
+    bin: &[u8],
+    isa: &InstructionSet,
+    window: RangeInclusive<u8>,
+) -> String {
+    assert!(bin.len() <= 256 * 256, "binary has more than 256 banks");
+    let (bin, banks) = bin.split_at(bin.len().min(256));
+    let code = find_code(bin, isa);
+
+    // Split the binary into items. Instructions that start in the middle of
+    // another instruction can't be represented, so we just skip those.
+    let mut items = BTreeMap::new();
+    let mut addr = 0;
+    while addr < bin.len() {
+        match isa.decode(&bin[addr..]) {
+            Ok((def, args)) if code[addr] => {
+                items.insert(addr, Item::Instruction(def, args));
+                addr += def.len() as usize;
+            }
+            _ => {
+                items.insert(addr, Item::Byte(bin[addr]));
+                addr += 1;
+            }
+        }
+    }
+
+    // Find all addresses that should get a label. Jump targets get `code_`
+    // labels, all other addresses `data_` labels.
+    let mut labels = BTreeMap::new();
+    for item in items.values() {
+        if let Item::Instruction(def, args) = item {
+            for (kind, &arg) in def.operands().iter().zip(args.iter()) {
+                let addr = arg as usize;
+                if !items.contains_key(&addr) {
+                    continue;
+                }
+
+                match kind {
+                    OperandKind::Target => {
+                        labels.insert(addr, format!("code_{:02x}", addr));
+                    }
+                    OperandKind::Address | OperandKind::Indirect => {
+                        labels.entry(addr).or_insert_with(|| format!("data_{:02x}", addr));
+                    }
+                    OperandKind::Immediate => {}
+                }
+            }
+        }
+    }
+
+    // Print everything
+    let mut out = String::new();
+    for (addr, item) in &items {
+        if let Some(label) = labels.get(addr) {
+            out.push_str(&format!(".{}:\n", label));
+        }
+
+        match item {
+            Item::Instruction(def, args) => {
+                let args = def.operands()
+                    .iter()
+                    .zip(args.iter())
+                    .map(|(kind, &arg)| match (kind, labels.get(&(arg as usize))) {
+                        (OperandKind::Immediate, _) => format!("${:02x}", arg),
+                        (OperandKind::Address, Some(label)) => format!("[{}]", label),
+                        (OperandKind::Address, None) => format!("[${:02x}]", arg),
+                        (OperandKind::Target, Some(label)) => format!(".{}", label),
+                        (OperandKind::Target, None) => format!("${:02x}", arg),
+                        (OperandKind::Indirect, Some(label)) => format!("[[{}]]", label),
+                        (OperandKind::Indirect, None) => format!("[[${:02x}]]", arg),
+                    })
+                    .collect::<Vec<_>>();
+
+                let line = format!("    {:<8}{}", def.mnemonic(), args.join(" "));
+                out.push_str(line.trim_end());
+                out.push('\n');
+            }
+            Item::Byte(v) => out.push_str(&format!("    .byte   ${:02x}\n", v)),
+        }
+    }
+
+    // The `.bank` directive fills everything up to its address with zeros,
+    // so only the last bank needs its trailing zeros.
+    let (start, end) = window.into_inner();
+    let count = banks.len().div_ceil(256);
+    for (i, bank) in banks.chunks(256).enumerate() {
+        let addr = bank.len().min(start as usize);
+        let window = &bank[addr..bank.len().min(end as usize + 1)];
+        let used = match window.iter().rposition(|&v| v != 0) {
+            _ if i + 1 == count => window.len(),
+            Some(last) => last + 1,
+            None => 0,
+        };
+
+        out.push_str(&format!("\n.bank ${:02x} ${:02x}\n", i + 1, addr));
+        for v in &window[..used] {
+            out.push_str(&format!("    .byte   ${:02x}\n", v));
+        }
+    }
+
+    out
+}
+
+/// Returns which addresses are the start of an instruction that can be
+/// reached from address 0.
+fn find_code(bin: &[u8], isa: &InstructionSet) -> Vec<bool> {
+    let mut code = vec![false; bin.len()];
+    let mut todo = vec![0];
+
+    while let Some(addr) = todo.pop() {
+        if addr >= bin.len() || code[addr] {
+            continue;
+        }
+
+        let (def, args) = match isa.decode(&bin[addr..]) {
+            Ok(decoded) => decoded,
+            Err(_) => continue,
+        };
+        code[addr] = true;
+
+        // Addresses wrap around at the end of the address space. Targets
+        // only known at runtime, like a jump through a pointer or a return,
+        // are not followed.
+        let next = (addr + def.len() as usize) % 256;
+        todo.extend(def.successors(args, next as u8).into_iter().map(usize::from));
+    }
+
+    code
+}
@@ -0,0 +1,16 @@
+//! Assembler and disassembler for the SHiT CPU.
+//!
+//! A program is parsed with `parse::parse` and turned into a binary with
+//! `codegen::assemble`. `disasm::disassemble` turns a binary back into
+//! source code.
+
+// Errors are printed as diagnostics right away, so callers only need to know
+// whether something failed.
+#![allow(clippy::result_unit_err)]
+
+pub mod codegen;
+pub mod diag;
+pub mod disasm;
+pub mod instr;
+pub mod parse;
+pub mod span;
@@ -2,37 +2,98 @@ use std::{
     env,
     error::Error,
     fs,
+    ops::RangeInclusive,
+    path::PathBuf,
 };
 
-mod diag;
-mod span;
-mod instr;
-mod parse;
+use assembler::{codegen, instr::InstructionSet, parse};
 
 
-
-fn main() -> Result<(), Box<Error>> {
-    // Get CLI argument or print error when no argument was passed
-    let path = match env::args().nth(1) {
-        Some(s) => s,
+fn main() -> Result<(), Box<dyn Error>> {
+    // Get CLI arguments or print usage when they are invalid
+    let args = match Args::from_env() {
+        Some(args) => args,
         None => {
             println!("<input> argument missing!");
-            println!("");
+            println!();
             println!("Usage:");
-            println!("  assembler <input>");
+            println!("  assembler <input> [-o <output>] [-g] [--isa <file>]");
+            println!("            [--bank-window <start>-<end>]");
+            println!();
+            println!("If no output is given, the input path with the extension");
+            println!("`.bin` is used. With `-g`, a symbol file with the extension");
+            println!("`.sym` and a source map with the extension `.map` are written");
+            println!("next to the output. `--isa` uses the instructions described in");
+            println!("<file> instead of the ones of the SHiT CPU. Lines in banks after");
+            println!("bank 0 have to be in the bank window at $80 to $df, unless it is");
+            println!("moved with `--bank-window`, like `--bank-window 40-7f`.");
             std::process::exit(1);
         }
     };
 
     // Try to load the file
-    let src = fs::read_to_string(path)?;
+    let src = fs::read_to_string(&args.input)?;
+
+    let isa = match &args.isa {
+        Some(path) => InstructionSet::load(path)?,
+        None => InstructionSet::builtin().clone(),
+    };
 
     // Try to parse the file
-    let program = parse::parse(&src).map_err(|_| "failed to parse file")?;
+    let program = parse::parse_with(&src, &isa).map_err(|_| "failed to parse file")?;
+
+    // Try to generate the binary
+    let output = codegen::assemble_with_window(&src, &program, args.bank_window.clone())
+        .map_err(|_| "failed to assemble file")?;
 
-    for line in program.lines {
-        println!("{:?}", line);
+    fs::write(&args.output, &output.bin)?;
+    if args.debug_info {
+        fs::write(args.output.with_extension("sym"), output.symbol_file())?;
+
+        // The source map is used from other directories, so the path to the
+        // source has to be absolute.
+        let source = fs::canonicalize(&args.input)?;
+        let map = output.source_map_file(&source.to_string_lossy());
+        fs::write(args.output.with_extension("map"), map)?;
     }
 
     Ok(())
 }
+
+/// The command line arguments.
+struct Args {
+    input: PathBuf,
+    output: PathBuf,
+    debug_info: bool,
+    isa: Option<PathBuf>,
+    bank_window: RangeInclusive<u8>,
+}
+
+impl Args {
+    /// Parses the arguments passed to this program. Returns `None` if they are
+    /// invalid.
+    fn from_env() -> Option<Self> {
+        let mut input = None;
+        let mut output = None;
+        let mut debug_info = false;
+        let mut isa = None;
+        let mut bank_window = codegen::DEFAULT_BANK_WINDOW;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => output = Some(PathBuf::from(args.next()?)),
+                "-g" => debug_info = true,
+                "--isa" => isa = Some(PathBuf::from(args.next()?)),
+                "--bank-window" => bank_window = codegen::parse_window(&args.next()?)?,
+                _ if input.is_none() => input = Some(PathBuf::from(arg)),
+                _ => return None,
+            }
+        }
+
+        let input = input?;
+        let output = output.unwrap_or_else(|| input.with_extension("bin"));
+
+        Some(Self { input, output, debug_info, isa, bank_window })
+    }
+}
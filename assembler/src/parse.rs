@@ -6,7 +6,7 @@
 
 use crate::{
     diag::Diag,
-    instr::Instruction,
+    instr::{Arg, Instruction, InstructionDef, InstructionSet, OperandKind},
     span::{Span, Spanned},
 };
 
@@ -20,8 +20,8 @@ pub struct Program {
 /// A single line of the program.
 #[derive(Debug, Clone)]
 pub enum Line {
-    /// For example: `.foo:`
-    Label(String),
+    /// For example: `.foo:`. The span covers the whole label definition.
+    Label(Spanned<String>),
 
     /// For example: `.byte`
     Directive(Directive),
@@ -36,14 +36,23 @@ pub enum Directive {
     /// Tell the assembler to put this exact byte in this position of the
     /// assembled binary.
     Byte(u8),
+
+    /// Tell the assembler to put all following lines into the given memory
+    /// bank, starting at the given address. For example: `.bank $01 $80`
+    Bank { bank: u8, addr: u8 },
 }
 
-/// Parse a string into a program.
+/// Parse a string into a program using the instructions of the SHiT CPU.
 ///
 /// If any errors occur, the errors are printed an `Err(())` is returned. Empty
 /// lines (including comment only lines) are not represented in the returned
 /// program.
 pub fn parse(input: &str) -> Result<Program, ()> {
+    parse_with(input, InstructionSet::builtin())
+}
+
+/// Like `parse`, but with the instructions described by `isa`.
+pub fn parse_with(input: &str, isa: &InstructionSet) -> Result<Program, ()> {
     /// Get the span of the string `line` in a larger buffer `input`
     fn line_span(input: &str, line: &str) -> Span {
         let start = line.as_ptr() as usize - input.as_ptr() as usize;
@@ -59,7 +68,7 @@ pub fn parse(input: &str) -> Result<Program, ()> {
         .enumerate()
         .filter_map(|(line_number, line)| {
             tokenize(line)
-                .and_then(parse_line)
+                .and_then(|tokens| parse_line(tokens, isa))
                 .unwrap_or_else(|e| {
                     // Print errors and convert them into `None`.
                     e.emit(line, line_number);
@@ -86,16 +95,12 @@ pub fn parse(input: &str) -> Result<Program, ()> {
 /// Convert a line into a list of tokens.
 ///
 /// If the line is illformed, the first error is returned as `Err()`.
-fn tokenize(line: &str) -> Result<Vec<Spanned<Token>>, Diag> {
+pub fn tokenize(line: &str) -> Result<Vec<Spanned<Token<'_>>>, Diag> {
     let mut chars = line.char_indices().peekable();
     let mut tokens = Vec::new();
 
-    loop {
-        // If we reached the end of the line, we can stop.
-        let (start, c) = match chars.next() {
-            Some((i, c)) => (i, c),
-            None => break,
-        };
+    // If we reached the end of the line, we can stop.
+    while let Some((start, c)) = chars.next() {
 
         let token = match c {
             '.' => Token::Dot,
@@ -107,7 +112,7 @@ fn tokenize(line: &str) -> Result<Vec<Spanned<Token>>, Diag> {
             '$' => {
                 // Find the end of the literal
                 let mut end = start + c.len_utf8();
-                while chars.peek().map(|(_, c)| c.is_digit(16)).unwrap_or(false) {
+                while chars.peek().map(|(_, c)| c.is_ascii_hexdigit()).unwrap_or(false) {
                     let (i, c) = chars.next().unwrap();
                     end = i + c.len_utf8();
                 }
@@ -178,8 +183,11 @@ macro_rules! expect_token {
                 return Err(Diag::span_error(*span, msg));
             }
             None => {
+                // Point right behind the last token, which is where the line
+                // ends (apart from comments and whitespace).
                 let msg = concat!("unexpected end of line, expected ", $expected_str);
-                let span = Span::new($tokens[$idx - 1].span.hi, $tokens[$idx - 1].span.hi + 1);
+                let end = $tokens.last().map_or(0, |t| t.span.hi);
+                let span = Span::new(end, end + 1);
                 return Err(Diag::span_error(span, msg));
             }
         }
@@ -201,11 +209,14 @@ macro_rules! expect_eol {
 }
 
 
-/// Parses a single line from tokens into a `Line`. Empty lines are returned as
-/// `Ok(None)`.
+/// Parses a single line from tokens into a `Line`. Instructions are looked up
+/// in `isa`. Empty lines are returned as `Ok(None)`.
 ///
 /// If the line is illformed, the first error is returned as `Err()`.
-fn parse_line(tokens: Vec<Spanned<Token>>) -> Result<Option<Line>, Diag> {
+pub fn parse_line(
+    tokens: Vec<Spanned<Token>>,
+    isa: &InstructionSet,
+) -> Result<Option<Line>, Diag> {
     if tokens.is_empty() {
         return Ok(None);
     }
@@ -223,14 +234,17 @@ fn parse_line(tokens: Vec<Spanned<Token>>) -> Result<Option<Line>, Diag> {
             if colon_next {
                 // Make sure we reached the end of the line
                 expect_eol!(tokens[3], " after label");
-                Line::Label(name.to_owned())
+                Line::Label(Spanned {
+                    data: name.to_owned(),
+                    span: Span::new(tokens[0].span.lo, tokens[2].span.hi),
+                })
             } else {
                 Line::Directive(parse_directive(name, &tokens)?)
             }
         }
 
         // An instruction
-        Token::Ident(name) => Line::Instruction(parse_instruction(name, &tokens)?),
+        Token::Ident(name) => Line::Instruction(parse_instruction(name, &tokens, isa)?),
 
         // Everything else is illegal at the beginning of the line.
         token => {
@@ -247,9 +261,196 @@ fn parse_line(tokens: Vec<Spanned<Token>>) -> Result<Option<Line>, Diag> {
 
 /// Parses a single instruction from the given tokens. The first token needs to
 /// be an ident! The first error encountered is returned.
-fn parse_instruction(_name: &str, _tokens: &[Spanned<Token>]) -> Result<Instruction, Diag> {
-    // TODO
-    Ok(Instruction::Nop)
+fn parse_instruction(
+    name: &str,
+    tokens: &[Spanned<Token>],
+    isa: &InstructionSet,
+) -> Result<Instruction, Diag> {
+    let candidates = isa.by_mnemonic(name);
+    if candidates.is_empty() {
+        let msg = format!("invalid instruction name '{}'", name);
+        return Err(Diag::span_error(tokens[0].span, msg));
+    }
+
+    // Parse all operands first. Whether their number and kinds fit the
+    // instruction is checked afterwards. Their kinds also decide which of the
+    // opcodes with this name is meant.
+    let mut operands = Vec::new();
+    let mut idx = 1;
+    while idx < tokens.len() {
+        let (operand, next) = parse_operand(tokens, idx)?;
+        operands.push(operand);
+        idx = next;
+    }
+
+    let (def, args) = check_operands(name, tokens, operands, &candidates)?;
+    Ok(Instruction { opcode: def.opcode(), args })
+}
+
+/// An operand as written in the source. Whether it's a valid operand for the
+/// instruction is only decided later.
+#[derive(Debug)]
+enum Operand {
+    /// For example `$61` or `.end`
+    Immediate(Arg),
+
+    /// For example `[$1f]` or `[CHAR]`
+    Address(Arg),
+
+    /// For example `[[$1f]]` or `[[PTR]]`
+    Indirect(Arg),
+}
+
+impl Operand {
+    /// Returns whether this operand can be used where an operand of `kind` is
+    /// expected.
+    fn fits(&self, kind: OperandKind) -> bool {
+        matches!(
+            (self, kind),
+            (Operand::Immediate(_), OperandKind::Immediate)
+                | (Operand::Immediate(_), OperandKind::Target)
+                | (Operand::Address(_), OperandKind::Address)
+                | (Operand::Indirect(_), OperandKind::Indirect)
+        )
+    }
+
+    /// Returns the argument of this operand.
+    fn into_arg(self) -> Arg {
+        match self {
+            Operand::Immediate(arg) | Operand::Address(arg) | Operand::Indirect(arg) => arg,
+        }
+    }
+}
+
+/// Parses the operand starting at token `idx`. Returns the operand and the
+/// index of the first token after it.
+fn parse_operand(
+    tokens: &[Spanned<Token>],
+    idx: usize,
+) -> Result<(Spanned<Operand>, usize), Diag> {
+    let (operand, next) = match &*tokens[idx] {
+        // An immediate value: `$61`
+        Token::Literal(v) => (Operand::Immediate(Arg::Value(*v)), idx + 1),
+
+        // A label used as value: `.end`
+        Token::Dot => {
+            let name = expect_token!(tokens[idx + 1]; "ident"; Token::Ident(s) => *s);
+            let label = Spanned {
+                data: name.to_owned(),
+                span: Span::new(tokens[idx].span.lo, tokens[idx + 1].span.hi),
+            };
+            (Operand::Immediate(Arg::Label(label)), idx + 2)
+        }
+
+        // An address: `[$1f]` or `[CHAR]`, or a pointer: `[[PTR]]`
+        Token::BracketOpen => {
+            let indirect = matches!(tokens.get(idx + 1).map(|t| &t.data), Some(Token::BracketOpen));
+            let start = if indirect { idx + 2 } else { idx + 1 };
+            let arg = match tokens.get(start) {
+                Some(Spanned { data: Token::Literal(v), .. }) => Arg::Value(*v),
+                Some(Spanned { data: Token::Ident(s), span }) => Arg::Label(Spanned {
+                    data: (*s).to_owned(),
+                    span: *span,
+                }),
+                Some(Spanned { data: invalid, span }) => {
+                    let msg = format!("unexpected '{:?}' token, expected literal or ident", invalid);
+                    return Err(Diag::span_error(*span, msg));
+                }
+                None => {
+                    let msg = "unexpected end of line, expected literal or ident";
+                    let span = Span::new(tokens[start - 1].span.hi, tokens[start - 1].span.hi + 1);
+                    return Err(Diag::span_error(span, msg));
+                }
+            };
+            expect_token!(tokens[start + 1]; "']'"; Token::BracketClose => ());
+            if indirect {
+                expect_token!(tokens[start + 2]; "']'"; Token::BracketClose => ());
+                (Operand::Indirect(arg), start + 3)
+            } else {
+                (Operand::Address(arg), start + 2)
+            }
+        }
+
+        token => {
+            let msg = format!("unexpected '{:?}' token, expected operand", token);
+            let diag = Diag::span_error(tokens[idx].span, msg)
+                .add_note("operands are written as `$lit`, `[ADDR]`, `[$lit]`, `[[PTR]]` or `.label`");
+
+            return Err(diag);
+        }
+    };
+
+    let span = Span::new(tokens[idx].span.lo, tokens[next - 1].span.hi);
+    Ok((Spanned { data: operand, span }, next))
+}
+
+/// Picks the instruction out of `candidates` (which all share the mnemonic
+/// `name`) whose operands match the given operands in number and kind.
+/// Returns the instruction and the arguments of all operands.
+fn check_operands<'a>(
+    name: &str,
+    tokens: &[Spanned<Token>],
+    operands: Vec<Spanned<Operand>>,
+    candidates: &[&'a InstructionDef],
+) -> Result<(&'a InstructionDef, Vec<Arg>), Diag> {
+    let forms = || {
+        let usages = candidates.iter().map(|def| {
+            let placeholders = def.operands().iter().map(|k| k.placeholder()).collect::<Vec<_>>();
+            format!("`{}`", [&[name][..], &placeholders].concat().join(" "))
+        }).collect::<Vec<_>>();
+        usages.join(" or ")
+    };
+
+    let same_len = candidates
+        .iter()
+        .filter(|def| def.operands().len() == operands.len())
+        .collect::<Vec<_>>();
+    if same_len.is_empty() {
+        // Mnemonics may be overloaded with different numbers of operands, so
+        // all accepted forms are listed.
+        let msg = format!(
+            "`{}` doesn't take {} operand(s), only {}",
+            name,
+            operands.len(),
+            forms(),
+        );
+
+        // Point at the operands no form takes anymore or at the end of the
+        // line if operands are missing.
+        let expected = candidates
+            .iter()
+            .map(|def| def.operands().len())
+            .filter(|&len| len < operands.len())
+            .max();
+        let span = match expected {
+            Some(expected) => {
+                Span::new(operands[expected].span.lo, operands.last().unwrap().span.hi)
+            }
+            None => {
+                let end = tokens.last().unwrap().span.hi;
+                Span::new(end, end + 1)
+            }
+        };
+
+        return Err(Diag::span_error(span, msg));
+    }
+
+    let fits = |def: &InstructionDef| {
+        operands.iter().zip(def.operands()).all(|(operand, &kind)| operand.fits(kind))
+    };
+    if let Some(&&def) = same_len.iter().find(|def| fits(def)) {
+        let args = operands.into_iter().map(|operand| operand.data.into_arg()).collect();
+        return Ok((def, args));
+    }
+
+    // Point at the first operand that doesn't fit the first instruction
+    let (operand, kind) = operands
+        .iter()
+        .zip(same_len[0].operands())
+        .find(|(operand, &kind)| !operand.fits(kind))
+        .expect("some operand doesn't fit");
+    let msg = format!("expected operand like `{}`", kind.placeholder());
+    Err(Diag::span_error(operand.span, msg).add_note(format!("usage: {}", forms())))
 }
 
 /// Parses the given tokens as directive. The first token needs to be '.' and
@@ -264,6 +465,13 @@ fn parse_directive(name: &str, tokens: &[Spanned<Token>]) -> Result<Directive, D
 
             Ok(Directive::Byte(v))
         }
+        "bank" => {
+            let bank = expect_token!(tokens[2]; "literal"; Token::Literal(v) => *v);
+            let addr = expect_token!(tokens[3]; "literal"; Token::Literal(v) => *v);
+            expect_eol!(tokens[4], "");
+
+            Ok(Directive::Bank { bank, addr })
+        }
         invalid => {
             let msg = format!("invalid directive name '{}'", invalid);
             Err(Diag::span_error(tokens[1].span, msg))
@@ -278,7 +486,7 @@ fn is_ident_start(c: char) -> bool {
 
 /// Returns `true` if the character is a valid identifier character.
 fn is_ident_char(c: char) -> bool {
-    c.is_alphanumeric()
+    c == '_' || c.is_alphanumeric()
 }
 
 /// A token in the input.
@@ -23,6 +23,11 @@ impl Span {
     pub fn len(&self) -> usize {
         self.hi - self.lo
     }
+
+    /// Returns `true` if this span doesn't span any bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 
@@ -0,0 +1,107 @@
+//! Checks programs split into memory banks with `.bank`.
+
+use assembler::{codegen, disasm, instr::InstructionSet, parse};
+
+
+/// Assembles the given source code. Returns `None` on errors.
+fn assemble(src: &str) -> Option<codegen::Output> {
+    let program = parse::parse(src).expect("failed to parse");
+    codegen::assemble(src, &program).ok()
+}
+
+const PROGRAM: &str = "
+    sti $01 [$fb]
+    call .f
+    stop
+
+.bank $01 $80
+.f:
+    ld [x]
+    ret
+.x:
+    .byte $2a
+
+.bank $03 $80
+.g:
+    jmp .g
+";
+
+#[test]
+fn layout() {
+    let output = assemble(PROGRAM).expect("failed to assemble");
+    assert_eq!(output.bin.len(), 3 * 256 + 0x82);
+    assert_eq!(output.bin[..6], [0x13, 0x01, 0xfb, 0x62, 0x80, 0x50]);
+    assert_eq!(output.bin[0x180..0x184], [0x10, 0x83, 0x63, 0x2a]);
+    assert_eq!(output.bin[0x380..], [0x20, 0x80]);
+    assert!(output.bin[6..0x180].iter().all(|&b| b == 0));
+
+    assert_eq!(output.labels, [
+        ("f".to_owned(), 1, 0x80),
+        ("x".to_owned(), 1, 0x83),
+        ("g".to_owned(), 3, 0x80),
+    ]);
+    assert_eq!(output.symbol_file(), "01:80 f\n01:83 x\n03:80 g\n");
+
+    // Only bank 0 is part of the source map
+    assert_eq!(output.lines.iter().map(|l| l.bank).collect::<Vec<_>>(), [0, 0, 0, 1, 1, 1, 3]);
+    assert_eq!(output.source_map_file("p.s").lines().count(), 4);
+}
+
+#[test]
+fn labels_of_other_banks() {
+    // Bank 0 can use labels of all banks, but bank 1 can't use bank 2
+    assert!(assemble(".bank $01 $80\n.a:\n.bank $02 $80\njmp .a\n").is_none());
+    assert!(assemble(".bank $01 $80\njmp .b\n.bank $02 $80\n.b:\n").is_none());
+    assert!(assemble("jmp .a\n.bank $01 $80\n.a:\njmp .b\n.bank $02 $80\n.b:\n").is_none());
+    let src = "jmp .a\njmp .b\n.c:\n.bank $01 $80\n.a:\njmp .c\n.bank $02 $80\n.b:\n";
+    assert!(assemble(src).is_some());
+}
+
+#[test]
+fn invalid_banks() {
+    assert!(assemble(".bank $00 $80\n").is_none());
+    assert!(assemble(".bank $02 $80\n.bank $01 $80\n").is_none());
+
+    // Banks other than bank 0 have to fit into the bank window
+    assert!(assemble(".bank $01 $df\n.byte $01\n").is_some());
+    assert!(assemble(".bank $01 $df\n.byte $01\n.byte $02\n").is_none());
+    assert!(assemble(".bank $01 $7f\n.byte $01\n").is_none());
+    assert!(assemble(".bank $01 $ff\n.byte $01\n").is_none());
+    assert!(assemble(".bank $01 $10\n.bank $02 $80\nstop\n").is_some());
+
+    assert!(parse::parse(".bank $01\n").is_err());
+    assert!(parse::parse(".bank $01 $80 $00\n").is_err());
+}
+
+#[test]
+fn roundtrip() {
+    let bin = assemble(PROGRAM).expect("failed to assemble").bin;
+    let src = disasm::disassemble(&bin);
+    assert!(src.contains(".bank $03 $80"), "disassembly:\n{}", src);
+    assert_eq!(assemble(&src).expect("failed to assemble").bin, bin, "disassembly:\n{}", src);
+}
+
+#[test]
+fn custom_window() {
+    let src = ".bank $01 $40\n.byte $01\n";
+    let program = parse::parse(src).expect("failed to parse");
+    assert!(codegen::assemble(src, &program).is_err());
+    let bin = codegen::assemble_with_window(src, &program, 0x40..=0x7f).unwrap().bin;
+    assert_eq!(bin.len(), 0x141);
+}
+
+#[test]
+fn roundtrip_custom_window() {
+    let src = PROGRAM.replace("$80", "$c0");
+    let assemble = |src: &str| {
+        let program = parse::parse(src).expect("failed to parse");
+        codegen::assemble_with_window(src, &program, 0xc0..=0xdf).ok().map(|output| output.bin)
+    };
+    let bin = assemble(&src).expect("failed to assemble");
+
+    let isa = InstructionSet::builtin();
+    let src = disasm::disassemble_with_window(&bin, isa, 0xc0..=0xdf);
+    assert!(src.contains(".bank $01 $c0"), "disassembly:\n{}", src);
+    assert!(src.contains(".bank $03 $c0"), "disassembly:\n{}", src);
+    assert_eq!(assemble(&src), Some(bin), "disassembly:\n{}", src);
+}
@@ -0,0 +1,76 @@
+//! Checks the errors reported while generating binaries and the output files
+//! of the assembler binary.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+use assembler::{codegen, parse};
+
+
+/// Assembles the given source code. Returns `None` on errors.
+fn assemble(src: &str) -> Option<Vec<u8>> {
+    let program = parse::parse(src).expect("failed to parse");
+    codegen::assemble(src, &program).ok().map(|output| output.bin)
+}
+
+/// Writes `src` to a fresh directory and runs the assembler binary on it
+/// with `args`. Returns the directory, the source path and what the
+/// assembler printed.
+fn run_assembler(name: &str, src: &str, args: &[&str]) -> (PathBuf, PathBuf, String) {
+    let dir = env::temp_dir().join(format!("assembler-test-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("program.s");
+    fs::write(&input, src).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_assembler"))
+        .arg(&input)
+        .args(args)
+        .output()
+        .expect("failed to run the assembler");
+    (dir, input, String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn labels() {
+    assert_eq!(assemble(".a:\njmp .a\nld [b]\n.b:\n.byte $2a\n").unwrap(), [
+        0x20, 0x00, 0x10, 0x04, 0x2a,
+    ]);
+    assert_eq!(assemble(".a:\n.a:\nstop\n"), None);
+    assert_eq!(assemble("jmp .b\n"), None);
+    assert_eq!(assemble("ld [b]\n"), None);
+}
+
+#[test]
+fn address_space() {
+    // 128 two byte instructions fill the address space exactly
+    let full = "ldi $00\n".repeat(128);
+    assert_eq!(assemble(&full).map(|bin| bin.len()), Some(256));
+    assert_eq!(assemble(&format!("{}stop\n", full)), None);
+
+    // A label right after the end can be defined, but not used
+    assert!(assemble(&format!("{}.end:\n", full)).is_some());
+    assert_eq!(assemble(&format!("{}.end:\njmp .end\n", full)), None);
+}
+
+#[test]
+fn only_first_overflow_is_reported() {
+    let src = format!("{}stop\nstop\nstop\n", "ldi $00\n".repeat(128));
+    let (dir, _, stdout) = run_assembler("overflow", &src, &[]);
+    assert_eq!(stdout.matches("program does not fit into the address space").count(), 1);
+    assert!(stdout.contains("this line ends at byte 257"), "{}", stdout);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn output_path() {
+    // Without `-o`, the binary is written next to the input
+    let (dir, input, _) = run_assembler("output", "ldi $2a\nstop\n", &["-g"]);
+    assert_eq!(fs::read(input.with_extension("bin")).unwrap(), [0x11, 0x2a, 0x50]);
+    assert!(input.with_extension("sym").exists());
+    assert!(input.with_extension("map").exists());
+
+    let output = dir.join("other.bin");
+    run_assembler("output", "stop\n", &["-o", output.to_str().unwrap()]);
+    assert_eq!(fs::read(&output).unwrap(), [0x50]);
+    assert!(!output.with_extension("sym").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
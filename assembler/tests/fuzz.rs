@@ -0,0 +1,85 @@
+//! Property tests feeding random programs and random text to the assembler.
+
+use assembler::{
+    codegen, disasm,
+    instr::{InstructionSet, Opcode, OperandKind},
+    parse,
+};
+use proptest::prelude::*;
+use shit_isa::Instruction;
+
+
+/// Generates any valid instruction with literal arguments.
+fn instruction() -> impl Strategy<Value = Instruction> {
+    (0..Opcode::ALL.len(), any::<[u8; 2]>()).prop_map(|(idx, args)| {
+        let opcode = Opcode::ALL[idx];
+        let args = args.iter().cloned().take(opcode.operands().len());
+        Instruction::from_args(opcode, args).unwrap()
+    })
+}
+
+/// Writes `instr` as a line of source code.
+fn source_line(instr: &Instruction) -> String {
+    let mut line = instr.opcode().mnemonic().to_owned();
+    for (kind, arg) in instr.opcode().operands().iter().zip(instr.args()) {
+        match kind {
+            OperandKind::Address => line.push_str(&format!(" [${:02x}]", arg)),
+            OperandKind::Indirect => line.push_str(&format!(" [[${:02x}]]", arg)),
+            OperandKind::Immediate | OperandKind::Target => line.push_str(&format!(" ${:02x}", arg)),
+        }
+    }
+    line
+}
+
+/// Assembles the given source code, panicking on errors.
+fn assemble(src: &str) -> Vec<u8> {
+    let program = parse::parse(src).expect("failed to parse");
+    codegen::assemble(src, &program).expect("failed to assemble").bin
+}
+
+/// Generates lines made of characters that appear in assembly code, plus a
+/// few that don't.
+fn line() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "[ .:\\[\\]$;a-z0-9_]{0,30}",
+        "(\\.?[a-z]{1,5}:? ?(\\[?\\$?[0-9a-f]{0,4}\\]? ?)*)(;.*)?",
+        any::<String>(),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn assemble_and_disassemble(instrs in prop::collection::vec(instruction(), 0..80)) {
+        let src = instrs.iter().map(|i| source_line(i) + "\n").collect::<String>();
+        let mut expected = vec![];
+        for instr in &instrs {
+            instr.encode(&mut expected);
+        }
+
+        let bin = assemble(&src);
+        prop_assert_eq!(&bin, &expected, "source:\n{}", src);
+
+        let disassembly = disasm::disassemble(&bin);
+        prop_assert_eq!(assemble(&disassembly), bin, "disassembly:\n{}", disassembly);
+    }
+
+    #[test]
+    fn parse_never_panics(line in line()) {
+        match parse::tokenize(&line) {
+            Ok(tokens) => {
+                for token in &tokens {
+                    prop_assert!(token.span.lo < token.span.hi && token.span.hi <= line.len());
+                }
+                if let Err(diag) = parse::parse_line(tokens, InstructionSet::builtin()) {
+                    // Errors at the end of the line point right behind it
+                    let span = diag.span().unwrap();
+                    prop_assert!(span.lo <= span.hi && span.hi <= line.len() + 1);
+                }
+            }
+            Err(diag) => {
+                let span = diag.span().unwrap();
+                prop_assert!(span.lo < span.hi && span.hi <= line.len());
+            }
+        }
+    }
+}
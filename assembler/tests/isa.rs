@@ -0,0 +1,76 @@
+//! Checks assembling and disassembling with an instruction set described in
+//! a file.
+
+use assembler::{codegen, disasm, instr::InstructionSet, parse};
+
+
+/// A tiny instruction set with an accumulator, loops and subroutines.
+const ISA: &str = r#"
+[[instruction]]
+opcode = 0x01
+mnemonic = "load"
+operands = ["address"]
+semantics = ["mov acc, [a0]"]
+
+[[instruction]]
+opcode = 0x02
+mnemonic = "load"
+operands = ["immediate"]
+semantics = ["mov acc, a0"]
+
+[[instruction]]
+opcode = 0x03
+mnemonic = "dec"
+semantics = ["sub acc, $01"]
+
+[[instruction]]
+opcode = 0x04
+mnemonic = "bnz"
+operands = ["target"]
+semantics = ["jnz a0"]
+
+[[instruction]]
+opcode = 0x05
+mnemonic = "jsr"
+operands = ["target"]
+extension = "stack"
+semantics = ["push pc", "jmp a0"]
+
+[[instruction]]
+opcode = 0x06
+mnemonic = "rts"
+extension = "stack"
+semantics = ["pop pc"]
+
+[[instruction]]
+opcode = 0xff
+mnemonic = "hlt"
+semantics = ["halt"]
+"#;
+
+/// Assembles the given source code, panicking on errors.
+fn assemble(src: &str, isa: &InstructionSet) -> Vec<u8> {
+    let program = parse::parse_with(src, isa).expect("failed to parse");
+    codegen::assemble(src, &program).expect("failed to assemble").bin
+}
+
+#[test]
+fn assemble_custom_instructions() {
+    let isa = InstructionSet::parse(ISA).unwrap();
+    let src = "jsr .f\nhlt\n.f:\nload $03\n.loop:\ndec\nbnz .loop\nload [X]\nrts\n.X:\n.byte $2a\n";
+    let bin = assemble(src, &isa);
+    assert_eq!(bin, [0x05, 0x03, 0xff, 0x02, 0x03, 0x03, 0x04, 0x05, 0x01, 0x0b, 0x06, 0x2a]);
+
+    let disassembly = disasm::disassemble_with(&bin, &isa);
+    assert!(disassembly.contains("bnz     .code_05"), "disassembly:\n{}", disassembly);
+    assert!(disassembly.contains(".byte   $2a"), "disassembly:\n{}", disassembly);
+    assert_eq!(assemble(&disassembly, &isa), bin, "disassembly:\n{}", disassembly);
+}
+
+#[test]
+fn builtin_instructions_are_unknown() {
+    let isa = InstructionSet::parse(ISA).unwrap();
+    assert!(parse::parse_with("ldi $01\n", &isa).is_err());
+    assert!(parse::parse_with("load $01 $02\n", &isa).is_err());
+    assert!(parse::parse("load $01\n").is_err());
+}
@@ -0,0 +1,57 @@
+//! Checks the errors reported while parsing instructions.
+
+use assembler::{diag::Diag, instr::InstructionSet, parse};
+
+
+/// Parses a single line that is expected to be invalid. Returns the error
+/// and the part of the line its span points to, which is empty for spans
+/// behind the end of the line.
+fn error(line: &str) -> (Diag, String) {
+    let diag = parse::tokenize(line)
+        .and_then(|tokens| parse::parse_line(tokens, InstructionSet::builtin()))
+        .expect_err("line parsed without errors");
+    let span = diag.span().expect("error without span");
+    let at = line.get(span.lo..span.hi).unwrap_or("").to_owned();
+    (diag, at)
+}
+
+#[test]
+fn unknown_mnemonic() {
+    let (diag, at) = error("    foo $01");
+    assert_eq!(diag.message(), "invalid instruction name 'foo'");
+    assert_eq!(at, "foo");
+}
+
+#[test]
+fn wrong_operand_count() {
+    let (diag, at) = error("ldi $01 $02 $03");
+    assert_eq!(diag.message(), "`ldi` doesn't take 3 operand(s), only `ldi $value`");
+    assert_eq!(at, "$02 $03");
+
+    // Missing operands are expected at the end of the line
+    let (diag, at) = error("sti $01");
+    assert_eq!(diag.message(), "`sti` doesn't take 1 operand(s), only `sti $value [ADDR]`");
+    assert_eq!(diag.span().unwrap().lo, 7);
+    assert_eq!(at, "");
+
+    // Overloaded mnemonics list all of their forms
+    let (diag, at) = error("jmp .a .b");
+    assert_eq!(
+        diag.message(),
+        "`jmp` doesn't take 2 operand(s), only `jmp .label` or `jmp [ADDR]`",
+    );
+    assert_eq!(at, ".b");
+}
+
+#[test]
+fn wrong_operand_kind() {
+    let (diag, at) = error("sti $01 $02");
+    assert_eq!(diag.message(), "expected operand like `[ADDR]`");
+    assert_eq!(diag.notes(), ["usage: `sti $value [ADDR]`"]);
+    assert_eq!(at, "$02");
+
+    let (diag, at) = error("ld $10");
+    assert_eq!(diag.message(), "expected operand like `[ADDR]`");
+    assert_eq!(diag.notes(), ["usage: `ld [ADDR]` or `ld [[ADDR]]`"]);
+    assert_eq!(at, "$10");
+}
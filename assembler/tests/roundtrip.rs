@@ -0,0 +1,117 @@
+//! Makes sure that disassembling a binary and assembling the result again
+//! gives back the original binary.
+
+use assembler::{codegen, disasm, instr::Opcode, parse};
+
+
+/// Assembles the given source code, panicking on errors.
+fn assemble(src: &str) -> Vec<u8> {
+    let program = parse::parse(src).expect("failed to parse");
+    codegen::assemble(src, &program).expect("failed to assemble").bin
+}
+
+/// Disassembles and reassembles `bin` and checks the result is the same.
+fn assert_roundtrip(bin: &[u8]) {
+    let src = disasm::disassemble(bin);
+    assert_eq!(assemble(&src), bin, "disassembly:\n{}", src);
+}
+
+#[test]
+fn sample_programs() {
+    let bin = assemble(include_str!("../asm/magic-1.s"));
+    assert_eq!(bin, include_bytes!("../../programs/magic-1.bin"));
+    assert_roundtrip(&bin);
+
+    assert_roundtrip(include_bytes!("../../programs/simple.bin"));
+}
+
+#[test]
+fn every_opcode() {
+    // Every opcode in a row, each followed by arguments pointing into the
+    // program. The jumps go to the next instruction, so all of them are
+    // reachable.
+    let mut bin = vec![];
+    let last = [Opcode::Jz, Opcode::JmpPtr, Opcode::Ret, Opcode::Stop];
+    for opcode in Opcode::ALL.iter().filter(|op| !last.contains(op)) {
+        let next = bin.len() as u8 + opcode.len();
+        bin.push(opcode.to_byte());
+        for _ in 1..opcode.len() {
+            bin.push(next);
+        }
+    }
+
+    // The targets of the jump through a pointer and of `ret` aren't known,
+    // so we branch around each of them to reach the `stop`.
+    let ret = bin.len() as u8 + 4;
+    let stop = ret + 3;
+    bin.extend_from_slice(&[Opcode::Jz.to_byte(), ret, Opcode::JmpPtr.to_byte(), ret]);
+    bin.extend_from_slice(&[Opcode::Jz.to_byte(), stop, Opcode::Ret.to_byte()]);
+    bin.push(Opcode::Stop.to_byte());
+
+    let src = disasm::disassemble(&bin);
+    for opcode in Opcode::ALL.iter() {
+        let found = src.lines().any(|l| l.split_whitespace().next() == Some(opcode.mnemonic()));
+        assert!(found, "`{}` missing in disassembly:\n{}", opcode.mnemonic(), src);
+    }
+    assert_roundtrip(&bin);
+}
+
+#[test]
+fn every_byte() {
+    let bin = (0..=255).collect::<Vec<u8>>();
+    assert_roundtrip(&bin);
+
+    let bin = (0..=255).rev().collect::<Vec<u8>>();
+    assert_roundtrip(&bin);
+}
+
+#[test]
+fn unreachable_bytes_are_data() {
+    // `jmp` over a valid `ldi` instruction
+    let bin = [0x20, 0x04, 0x11, 0x05, 0x50];
+    let src = disasm::disassemble(&bin);
+    assert!(!src.contains("ldi"), "disassembly:\n{}", src);
+    assert_roundtrip(&bin);
+}
+
+#[test]
+fn jump_into_instruction() {
+    // The `jz` jumps to the argument of the `ldi`, which is a `stop`
+    let bin = [0x11, 0x50, 0x21, 0x01, 0x50];
+    assert_roundtrip(&bin);
+}
+
+#[test]
+fn instruction_at_end() {
+    // The `sti` at the end is missing its last argument
+    let bin = [0x00, 0x13, 0x01];
+    assert_roundtrip(&bin);
+}
+
+#[test]
+fn logical_instructions() {
+    let src = "ldi $5c\nor [CHAR]\nori $03\nxor [CHAR]\nxori $ff\nnot\nstop\n.CHAR:\n.byte $f0\n";
+    let bin = assemble(src);
+    assert_eq!(bin, [0x11, 0x5c, 0x38, 0x0c, 0x39, 0x03, 0x3a, 0x0c, 0x3b, 0xff, 0x3c, 0x50, 0xf0]);
+    assert_roundtrip(&bin);
+}
+
+#[test]
+fn indirect_operands() {
+    let src = "ld [[PTR]]\nst [[$07]]\njmp [PTR]\n.PTR:\n.byte $00\n";
+    let bin = assemble(src);
+    assert_eq!(bin, [0x15, 0x06, 0x16, 0x07, 0x25, 0x06, 0x00]);
+    assert_roundtrip(&bin);
+
+    // The operands decide which opcode is meant
+    assert!(parse::parse("ld $07\n").is_err());
+    assert!(parse::parse("jmp [[$07]]\n").is_err());
+}
+
+#[test]
+fn stack_instructions() {
+    let src = "call .f\nstop\n.f:\npush\npop\nret\n";
+    let bin = assemble(src);
+    assert_eq!(bin, [0x62, 0x03, 0x50, 0x60, 0x61, 0x63]);
+    assert_roundtrip(&bin);
+}
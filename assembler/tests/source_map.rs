@@ -0,0 +1,23 @@
+//! Checks the source map written with `-g`.
+
+use assembler::{codegen, parse};
+
+
+#[test]
+fn source_map() {
+    let src = include_str!("../asm/magic-1.s");
+    let program = parse::parse(src).expect("failed to parse");
+    let output = codegen::assemble(src, &program).expect("failed to assemble");
+
+    // 9 instructions and 2 bytes, labels don't get entries
+    assert_eq!(output.lines.len(), 11);
+    assert!(output.lines.windows(2).all(|w| w[0].addr < w[1].addr));
+    assert_eq!(output.lines.iter().filter(|l| l.data).count(), 2);
+
+    let map = output.source_map_file("magic-1.s");
+    let mut lines = map.lines();
+    assert_eq!(lines.next(), Some("file magic-1.s"));
+    assert_eq!(lines.next(), Some("00 2"));
+    assert_eq!(lines.next(), Some("02 4"));
+    assert_eq!(lines.last(), Some("12 17 data"));
+}
@@ -0,0 +1,60 @@
+//! Compares the speed of the machine with and without the decode cache.
+//!
+//! Run with `cargo bench --bench engine`.
+
+use std::time::{Duration, Instant};
+
+use shit_cpu_emu::{Machine, Status};
+
+
+/// The number of instructions executed per measurement.
+const STEPS: usize = 20_000_000;
+
+/// Counts a 16 bit number up forever:
+///
+/// ```text
+/// .loop:
+///     ld      [LO]
+///     addi    $01
+///     st      [LO]
+///     jz      .carry
+///     jmp     .loop
+/// .carry:
+///     ld      [HI]
+///     addi    $01
+///     st      [HI]
+///     jmp     .loop
+/// .LO:
+///     .byte   $00
+/// .HI:
+///     .byte   $00
+/// ```
+const COUNTER: &[u8] = &[
+    0x10, 0x12, 0x31, 0x01, 0x12, 0x12, 0x21, 0x0a, 0x20, 0x00,
+    0x10, 0x13, 0x31, 0x01, 0x12, 0x13, 0x20, 0x00, 0x00, 0x00,
+];
+
+/// Runs the counter for `STEPS` instructions and returns the time it took.
+fn measure(decode_cache: bool) -> Duration {
+    let mut machine = Machine::from_program(COUNTER);
+    machine.set_decode_cache(decode_cache);
+
+    let start = Instant::now();
+    assert_eq!(machine.run_for(STEPS), Ok(Status::Running));
+    start.elapsed()
+}
+
+fn main() {
+    // Warm up
+    measure(false);
+    measure(true);
+
+    let best = |decode_cache| (0..5).map(|_| measure(decode_cache)).min().unwrap();
+    let plain = best(false);
+    let cached = best(true);
+
+    let per_second = |d: Duration| STEPS as f64 / d.as_secs_f64() / 1e6;
+    println!("interpreter:   {:>8.2?}  {:>7.1} M instructions/s", plain, per_second(plain));
+    println!("decode cache:  {:>8.2?}  {:>7.1} M instructions/s", cached, per_second(cached));
+    println!("speedup:       {:.2}x", plain.as_secs_f64() / cached.as_secs_f64());
+}
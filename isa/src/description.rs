@@ -0,0 +1,586 @@
+//! Instruction sets described in a file instead of in code.
+//!
+//! A description is a TOML file with one `[[instruction]]` table per opcode:
+//!
+//! ```toml
+//! [[instruction]]
+//! opcode = 0x31
+//! mnemonic = "addi"
+//! operands = ["immediate"]
+//! semantics = ["add acc, a0"]
+//! ```
+//!
+//! `operands` lists the kinds of the arguments: `immediate`, `address`,
+//! `target` or `indirect`. An instruction is one byte for the opcode plus one
+//! byte per operand long. The optional `length` is checked against that. The
+//! optional `extension` (`carry`, `indirect` or `stack`) makes the opcode
+//! illegal unless the extension is enabled.
+//!
+//! `semantics` lists the micro-operations the instruction executes one after
+//! another, see `MicroOp`. They work on these values:
+//!
+//! - `acc` and `sp`, the registers of the machine
+//! - `pc`, the address of the next instruction. Writing it jumps.
+//! - `t`, a temporary register which is 0 at the start of every instruction
+//! - `a0` and `a1`, the arguments of the instruction
+//! - `$2a`, a constant
+//! - `[v]`, the byte in memory at the address `v`, like `[a0]` or `[[a0]]`
+//!
+//! The built-in instruction set is described in `shit.toml` in this crate.
+
+use std::{fs, io, path::Path, sync::OnceLock};
+
+use serde::Deserialize;
+
+use crate::{
+    instr::{DecodeError, Instruction},
+    opcode::{Extension, OperandKind},
+};
+
+
+/// The most operands an instruction can have.
+const MAX_OPERANDS: usize = 2;
+
+/// A register micro-operations can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    Acc,
+    Sp,
+
+    /// The address of the next instruction.
+    Pc,
+
+    /// A temporary register for intermediate values.
+    Tmp,
+}
+
+/// A value a micro-operation reads or writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Register(Register),
+
+    /// The argument with the given index.
+    Arg(usize),
+
+    Const(u8),
+
+    /// The byte in memory at the address given by the inner value.
+    Memory(Box<Value>),
+}
+
+impl Value {
+    /// Parses a value like `acc`, `a0`, `$2a` or `[a0]`. The instruction has
+    /// `operands` arguments.
+    fn parse(s: &str, operands: usize) -> Result<Self, String> {
+        if let Some(inner) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            return Ok(Value::Memory(Box::new(Value::parse(inner.trim(), operands)?)));
+        }
+
+        let arg = s.strip_prefix('a').and_then(|i| i.parse::<usize>().ok());
+        match (s, arg) {
+            ("acc", _) => Ok(Value::Register(Register::Acc)),
+            ("sp", _) => Ok(Value::Register(Register::Sp)),
+            ("pc", _) => Ok(Value::Register(Register::Pc)),
+            ("t", _) => Ok(Value::Register(Register::Tmp)),
+            (_, Some(idx)) if idx < operands => Ok(Value::Arg(idx)),
+            (_, Some(_)) => Err(format!("`{}` used, but there are only {} operand(s)", s, operands)),
+            _ => match s.strip_prefix('$').map(|hex| u8::from_str_radix(hex, 16)) {
+                Some(Ok(v)) => Ok(Value::Const(v)),
+                Some(Err(_)) => Err(format!("invalid constant `{}`", s)),
+                None => Err(format!("invalid value `{}`", s)),
+            },
+        }
+    }
+
+    /// Returns whether micro-operations can write to this value.
+    pub fn is_writable(&self) -> bool {
+        matches!(self, Value::Register(_) | Value::Memory(_))
+    }
+
+    /// Returns the value if it only depends on the arguments.
+    fn constant(&self, args: &[u8]) -> Option<u8> {
+        match *self {
+            Value::Arg(idx) => args.get(idx).cloned(),
+            Value::Const(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// An operation combining two values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+}
+
+/// An operation changing a single value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Shl,
+    Shr,
+}
+
+/// When a jump is taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Always,
+
+    /// `acc` is zero.
+    Zero,
+
+    /// `acc` is not zero.
+    NotZero,
+
+    /// The carry flag is set.
+    Carry,
+
+    /// The carry flag is not set.
+    NoCarry,
+}
+
+/// A single step of executing an instruction, written like an instruction
+/// with its operands separated by commas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MicroOp {
+    /// `mov dst, src`: copies `src` to `dst`.
+    Mov { dst: Value, src: Value },
+
+    /// `add dst, src`, `sub`, `and`, `or` and `xor`: combines `dst` with
+    /// `src` and stores the result in `dst`. `add` and `sub` set the carry
+    /// flag to whether the result overflowed.
+    Binary { op: BinaryOp, dst: Value, src: Value },
+
+    /// `not dst`, `shl dst` and `shr dst`. The shifts set the carry flag to
+    /// the bit shifted out.
+    Unary { op: UnaryOp, dst: Value },
+
+    /// `jmp target`, `jz`, `jnz`, `jc` and `jnc`: jumps to `target` if the
+    /// condition holds.
+    Jump { cond: Condition, target: Value },
+
+    /// `push src`: pushes `src` onto the stack of the stack extension.
+    Push { src: Value },
+
+    /// `pop dst`: pops the top of the stack into `dst`.
+    Pop { dst: Value },
+
+    /// `print src`: prints the string at address `src`. Its first byte is
+    /// the length.
+    Print { src: Value },
+
+    /// `in dst, eof`: reads a byte of input into `dst`, or jumps to `eof` if
+    /// there is no more input.
+    In { dst: Value, eof: Value },
+
+    /// `halt`: halts the machine. The program counter stays at the
+    /// instruction.
+    Halt,
+}
+
+impl MicroOp {
+    /// Parses a micro-operation like `add acc, [a0]`. The instruction has
+    /// `operands` arguments.
+    fn parse(s: &str, operands: usize) -> Result<Self, String> {
+        let s = s.trim();
+        let (name, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let values = rest
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(|v| Value::parse(v, operands))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let binary = |op, dst: &Value, src: &Value| {
+            MicroOp::Binary { op, dst: dst.clone(), src: src.clone() }
+        };
+        let op = match (name, &values[..]) {
+            ("mov", [dst, src]) => MicroOp::Mov { dst: dst.clone(), src: src.clone() },
+            ("add", [dst, src]) => binary(BinaryOp::Add, dst, src),
+            ("sub", [dst, src]) => binary(BinaryOp::Sub, dst, src),
+            ("and", [dst, src]) => binary(BinaryOp::And, dst, src),
+            ("or", [dst, src]) => binary(BinaryOp::Or, dst, src),
+            ("xor", [dst, src]) => binary(BinaryOp::Xor, dst, src),
+            ("not", [dst]) => MicroOp::Unary { op: UnaryOp::Not, dst: dst.clone() },
+            ("shl", [dst]) => MicroOp::Unary { op: UnaryOp::Shl, dst: dst.clone() },
+            ("shr", [dst]) => MicroOp::Unary { op: UnaryOp::Shr, dst: dst.clone() },
+            ("jmp", [target]) => MicroOp::Jump { cond: Condition::Always, target: target.clone() },
+            ("jz", [target]) => MicroOp::Jump { cond: Condition::Zero, target: target.clone() },
+            ("jnz", [target]) => MicroOp::Jump { cond: Condition::NotZero, target: target.clone() },
+            ("jc", [target]) => MicroOp::Jump { cond: Condition::Carry, target: target.clone() },
+            ("jnc", [target]) => MicroOp::Jump { cond: Condition::NoCarry, target: target.clone() },
+            ("push", [src]) => MicroOp::Push { src: src.clone() },
+            ("pop", [dst]) => MicroOp::Pop { dst: dst.clone() },
+            ("print", [src]) => MicroOp::Print { src: src.clone() },
+            ("in", [dst, eof]) => MicroOp::In { dst: dst.clone(), eof: eof.clone() },
+            ("halt", []) => MicroOp::Halt,
+            _ => return Err(format!("invalid micro-operation `{}`", s)),
+        };
+
+        let dst = match &op {
+            MicroOp::Mov { dst, .. }
+            | MicroOp::Binary { dst, .. }
+            | MicroOp::Unary { dst, .. }
+            | MicroOp::Pop { dst }
+            | MicroOp::In { dst, .. } => Some(dst),
+            _ => None,
+        };
+        if dst.is_some_and(|dst| !dst.is_writable()) {
+            return Err(format!("`{}` writes to a value that can't be written", s));
+        }
+
+        Ok(op)
+    }
+}
+
+/// The description of a single opcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionDef {
+    opcode: u8,
+    mnemonic: String,
+    operands: Vec<OperandKind>,
+    extension: Option<Extension>,
+    semantics: Vec<MicroOp>,
+}
+
+impl InstructionDef {
+    /// Returns the byte of the opcode.
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    /// Returns the name of the instruction as written in the source code.
+    pub fn mnemonic(&self) -> &str {
+        &self.mnemonic
+    }
+
+    /// Returns the kinds of the arguments in the order they are written and
+    /// encoded.
+    pub fn operands(&self) -> &[OperandKind] {
+        &self.operands
+    }
+
+    /// Returns the extension this opcode belongs to, or `None` if it is part
+    /// of the base instruction set.
+    pub fn extension(&self) -> Option<Extension> {
+        self.extension
+    }
+
+    /// Returns what the instruction does.
+    pub fn semantics(&self) -> &[MicroOp] {
+        &self.semantics
+    }
+
+    /// Returns the number of bytes this instruction (with its arguments)
+    /// occupies.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u8 {
+        1 + self.operands.len() as u8
+    }
+
+    /// Returns the instruction of the SHiT CPU that does exactly what the
+    /// micro-operations do with the given arguments, or `None` if there is no
+    /// such instruction. The emulator executes these natively.
+    pub fn to_instruction(&self, args: &[u8]) -> Option<Instruction> {
+        use self::Instruction::*;
+        use crate::description::MicroOp as Op;
+
+        let acc = |v: &Value| *v == Value::Register(Register::Acc);
+        let pc = |v: &Value| *v == Value::Register(Register::Pc);
+        let arg = |v: &Value| v.constant(args);
+        let mem = |v: &Value| match v {
+            Value::Memory(addr) => addr.constant(args),
+            _ => None,
+        };
+        let ptr = |v: &Value| match v {
+            Value::Memory(addr) => mem(addr),
+            _ => None,
+        };
+
+        let instr = match &self.semantics[..] {
+            [] => Nop,
+            [Op::Mov { dst, src }] if acc(dst) => mem(src)
+                .map(|src| Ld { src })
+                .or_else(|| arg(src).map(|v| Ldi { v }))
+                .or_else(|| ptr(src).map(|ptr| LdPtr { ptr }))?,
+            [Op::Mov { dst, src }] if acc(src) => mem(dst)
+                .map(|dst| St { dst })
+                .or_else(|| ptr(dst).map(|ptr| StPtr { ptr }))?,
+            [Op::Mov { dst, src }] => {
+                let dst = mem(dst)?;
+                arg(src)
+                    .map(|v| Sti { v, dst })
+                    .or_else(|| mem(src).map(|src| Mov { src, dst }))?
+            }
+            [Op::Jump { cond: Condition::Always, target }] => arg(target)
+                .map(|target| Jmp { target })
+                .or_else(|| mem(target).map(|ptr| JmpPtr { ptr }))?,
+            [Op::Jump { cond, target }] => {
+                let target = arg(target)?;
+                match cond {
+                    Condition::Always => unreachable!("matched above"),
+                    Condition::Zero => Jz { target },
+                    Condition::NotZero => Jnz { target },
+                    Condition::Carry => Jc { target },
+                    Condition::NoCarry => Jnc { target },
+                }
+            }
+            [Op::Binary { op, dst, src }] if acc(dst) => match (op, mem(src), arg(src)) {
+                (BinaryOp::Add, Some(src), _) => Add { src },
+                (BinaryOp::Add, _, Some(v)) => Addi { v },
+                (BinaryOp::Sub, Some(src), _) => Sub { src },
+                (BinaryOp::Sub, _, Some(v)) => Subi { v },
+                (BinaryOp::And, Some(src), _) => And { src },
+                (BinaryOp::And, _, Some(v)) => Andi { v },
+                (BinaryOp::Or, Some(src), _) => Or { src },
+                (BinaryOp::Or, _, Some(v)) => Ori { v },
+                (BinaryOp::Xor, Some(src), _) => Xor { src },
+                (BinaryOp::Xor, _, Some(v)) => Xori { v },
+                _ => return None,
+            },
+            [Op::Unary { op, dst }] if acc(dst) => match op {
+                UnaryOp::Not => Not,
+                UnaryOp::Shl => Shl,
+                UnaryOp::Shr => Shr,
+            },
+            [Op::Print { src }] => Print { src: arg(src)? },
+            [Op::In { dst, eof }] if acc(dst) => In { eof: arg(eof)? },
+            [Op::Halt] => Stop,
+            [Op::Push { src }] if acc(src) => Push,
+            [Op::Pop { dst }] if acc(dst) => Pop,
+            [Op::Push { src }, Op::Jump { cond: Condition::Always, target }] if pc(src) => {
+                Call { target: arg(target)? }
+            }
+            [Op::Pop { dst }] if pc(dst) => Ret,
+            _ => return None,
+        };
+
+        Some(instr)
+    }
+
+    /// Returns whether the instruction may or may not jump, depending on the
+    /// state of the machine.
+    pub fn is_conditional_branch(&self) -> bool {
+        self.semantics.iter().any(|op| {
+            matches!(op, MicroOp::Jump { cond, .. } if *cond != Condition::Always)
+                || matches!(op, MicroOp::In { .. })
+        })
+    }
+
+    /// Returns the addresses execution can continue at after this
+    /// instruction, as far as they are known without executing it. `next` is
+    /// the address of the following instruction.
+    ///
+    /// Jumps to addresses read from memory or registers are unknown. An
+    /// instruction pushing `pc` is a call, which returns to `next` at some
+    /// point.
+    pub fn successors(&self, args: &[u8], next: u8) -> Vec<u8> {
+        let mut successors = vec![];
+        let mut falls_through = true;
+        for op in &self.semantics {
+            match op {
+                MicroOp::Jump { cond, target } => {
+                    successors.extend(target.constant(args));
+                    if *cond == Condition::Always {
+                        falls_through = false;
+                        break;
+                    }
+                }
+                MicroOp::In { eof, .. } => successors.extend(eof.constant(args)),
+                MicroOp::Mov { dst: Value::Register(Register::Pc), src } => {
+                    successors.extend(src.constant(args));
+                    falls_through = false;
+                    break;
+                }
+                MicroOp::Binary { dst: Value::Register(Register::Pc), .. }
+                | MicroOp::Unary { dst: Value::Register(Register::Pc), .. }
+                | MicroOp::Pop { dst: Value::Register(Register::Pc) }
+                | MicroOp::Halt => {
+                    falls_through = false;
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let calls = self.semantics.iter().any(|op| {
+            matches!(op, MicroOp::Push { src: Value::Register(Register::Pc) })
+        });
+        if falls_through || calls {
+            successors.push(next);
+        }
+        successors
+    }
+}
+
+/// An instruction set: all opcodes with their operands and semantics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionSet {
+    /// The description of every opcode, indexed by its byte.
+    defs: Vec<Option<InstructionDef>>,
+}
+
+impl InstructionSet {
+    /// Returns the instruction set of the SHiT CPU.
+    pub fn builtin() -> &'static Self {
+        static BUILTIN: OnceLock<InstructionSet> = OnceLock::new();
+        BUILTIN.get_or_init(|| {
+            Self::parse(include_str!("../shit.toml")).expect("built-in instruction set is invalid")
+        })
+    }
+
+    /// Parses a description as explained in the module documentation.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let raw = toml::from_str::<RawSet>(src).map_err(|e| e.to_string())?;
+
+        let mut defs = vec![None; 256];
+        for instr in raw.instruction {
+            let def = instr.into_def()?;
+            let slot = &mut defs[def.opcode as usize];
+            if slot.is_some() {
+                return Err(format!("opcode ${:02x} is described twice", def.opcode));
+            }
+            *slot = Some(def);
+        }
+
+        // The assembler picks the opcode by the mnemonic and the operands
+        let set = Self { defs };
+        for def in set.instructions() {
+            let ambiguous = set.instructions().any(|other| {
+                other.opcode < def.opcode
+                    && other.mnemonic == def.mnemonic
+                    && other.operands == def.operands
+            });
+            if ambiguous {
+                return Err(format!(
+                    "`{}` with the same operands is described more than once",
+                    def.mnemonic,
+                ));
+            }
+        }
+
+        Ok(set)
+    }
+
+    /// Loads and parses the description at the given path.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let src = fs::read_to_string(path)?;
+        Self::parse(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns all described instructions, ordered by their opcode.
+    pub fn instructions(&self) -> impl Iterator<Item = &InstructionDef> {
+        self.defs.iter().flatten()
+    }
+
+    /// Returns the instruction with the given opcode.
+    pub fn get(&self, opcode: u8) -> Option<&InstructionDef> {
+        self.defs[opcode as usize].as_ref()
+    }
+
+    /// Returns all instructions with the given mnemonic. They differ in
+    /// their operands.
+    pub fn by_mnemonic(&self, mnemonic: &str) -> Vec<&InstructionDef> {
+        self.instructions().filter(|def| def.mnemonic == mnemonic).collect()
+    }
+
+    /// Decodes the instruction at the start of `bytes`. Returns its
+    /// description and arguments. Additional bytes after the instruction are
+    /// ignored.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Result<(&InstructionDef, &'a [u8]), DecodeError> {
+        let def = match bytes.first() {
+            Some(&byte) => self.get(byte).ok_or(DecodeError::IllegalOpcode(byte))?,
+            None => return Err(DecodeError::Truncated),
+        };
+
+        let args = bytes.get(1..def.len() as usize).ok_or(DecodeError::Truncated)?;
+        Ok((def, args))
+    }
+}
+
+
+/// An instruction set as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSet {
+    #[serde(default)]
+    instruction: Vec<RawInstruction>,
+}
+
+/// An instruction as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawInstruction {
+    opcode: u8,
+    mnemonic: String,
+    #[serde(default)]
+    operands: Vec<String>,
+    length: Option<u8>,
+    extension: Option<String>,
+    #[serde(default)]
+    semantics: Vec<String>,
+}
+
+impl RawInstruction {
+    /// Checks the instruction and converts it.
+    fn into_def(self) -> Result<InstructionDef, String> {
+        let opcode = self.opcode;
+        let context = |msg: String| format!("opcode ${:02x}: {}", opcode, msg);
+
+        // The assembler has to recognize the mnemonic as an identifier
+        let mut chars = self.mnemonic.chars();
+        let valid = chars.next().is_some_and(|c| c == '_' || c.is_alphabetic())
+            && chars.all(|c| c == '_' || c.is_alphanumeric());
+        if !valid {
+            return Err(context(format!("invalid mnemonic `{}`", self.mnemonic)));
+        }
+
+        let operands = self
+            .operands
+            .iter()
+            .map(|kind| match kind.as_str() {
+                "immediate" => Ok(OperandKind::Immediate),
+                "address" => Ok(OperandKind::Address),
+                "target" => Ok(OperandKind::Target),
+                "indirect" => Ok(OperandKind::Indirect),
+                _ => Err(context(format!("invalid operand kind `{}`", kind))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if operands.len() > MAX_OPERANDS {
+            return Err(context(format!("at most {} operands are allowed", MAX_OPERANDS)));
+        }
+        if let Some(length) = self.length {
+            if length as usize != 1 + operands.len() {
+                let expected = 1 + operands.len();
+                let msg = format!("length is {}, but the operands make it {}", length, expected);
+                return Err(context(msg));
+            }
+        }
+
+        let extension = match self.extension.as_deref() {
+            None => None,
+            Some("carry") => Some(Extension::Carry),
+            Some("indirect") => Some(Extension::Indirect),
+            Some("stack") => Some(Extension::Stack),
+            Some(other) => return Err(context(format!("unknown extension `{}`", other))),
+        };
+
+        let semantics = self
+            .semantics
+            .iter()
+            .map(|op| MicroOp::parse(op, operands.len()).map_err(context))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(InstructionDef {
+            opcode,
+            mnemonic: self.mnemonic,
+            operands,
+            extension,
+            semantics,
+        })
+    }
+}
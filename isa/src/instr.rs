@@ -0,0 +1,277 @@
+//! Defines full instructions and how they are encoded into bytes.
+
+use std::{error::Error, fmt};
+
+use crate::opcode::Opcode;
+
+
+/// A full instruction, including arguments.
+///
+/// The arguments are of type `A`, which is `u8` for instructions as they
+/// appear in memory. The assembler uses its own type to allow labels as
+/// arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction<A = u8> {
+    // $0_
+    Nop,
+
+    // $1_ (data transfer)
+    Ld { src: A },
+    Ldi { v: A },
+    St { dst: A },
+    Sti { v: A, dst: A },
+    Mov { src: A, dst: A },
+
+    /// Loads the byte `ptr` points to. Part of the indirect extension.
+    LdPtr { ptr: A },
+
+    /// Stores the accumulator where `ptr` points to. Part of the indirect
+    /// extension.
+    StPtr { ptr: A },
+
+    // $2_ (control flow)
+    Jmp { target: A },
+    Jz { target: A },
+
+    /// Jumps if the carry flag is set. Part of the carry extension.
+    Jc { target: A },
+
+    /// Jumps if the carry flag is not set. Part of the carry extension.
+    Jnc { target: A },
+
+    /// Jumps if the accumulator is not zero. Part of the carry extension.
+    Jnz { target: A },
+
+    /// Jumps to the address stored at `ptr`. Part of the indirect extension.
+    JmpPtr { ptr: A },
+
+    // $3_ (arithmetic)
+    Add { src: A },
+    Addi { v: A },
+    Sub { src: A },
+    Subi { v: A },
+    Shr,
+    Shl,
+    And { src: A },
+    Andi { v: A },
+    Or { src: A },
+    Ori { v: A },
+    Xor { src: A },
+    Xori { v: A },
+    Not,
+
+    // $4_ (input/output)
+    Print { src: A },
+
+    /// Reads a byte into the accumulator. If there is no more input, the
+    /// accumulator is unchanged and execution continues at `eof`.
+    In { eof: A },
+
+    // $5_
+    Stop,
+
+    // $6_ (stack, part of the stack extension)
+
+    /// Pushes the accumulator.
+    Push,
+
+    /// Pops the top of the stack into the accumulator.
+    Pop,
+
+    /// Pushes the address of the next instruction and jumps to `target`.
+    Call { target: A },
+
+    /// Pops an address and jumps there.
+    Ret,
+}
+
+impl<A> Instruction<A> {
+    /// Creates an instruction from its opcode and arguments. The arguments
+    /// have to be in the order they are encoded. Returns `None` if the number
+    /// of arguments is wrong.
+    pub fn from_args(opcode: Opcode, args: impl IntoIterator<Item = A>) -> Option<Self> {
+        let mut args = args.into_iter();
+        let mut next = || args.next();
+
+        let instr = match opcode {
+            Opcode::Nop => Instruction::Nop,
+            Opcode::Ld => Instruction::Ld { src: next()? },
+            Opcode::Ldi => Instruction::Ldi { v: next()? },
+            Opcode::St => Instruction::St { dst: next()? },
+            Opcode::Sti => Instruction::Sti { v: next()?, dst: next()? },
+            Opcode::Mov => Instruction::Mov { src: next()?, dst: next()? },
+            Opcode::LdPtr => Instruction::LdPtr { ptr: next()? },
+            Opcode::StPtr => Instruction::StPtr { ptr: next()? },
+            Opcode::Jmp => Instruction::Jmp { target: next()? },
+            Opcode::Jz => Instruction::Jz { target: next()? },
+            Opcode::Jc => Instruction::Jc { target: next()? },
+            Opcode::Jnc => Instruction::Jnc { target: next()? },
+            Opcode::Jnz => Instruction::Jnz { target: next()? },
+            Opcode::JmpPtr => Instruction::JmpPtr { ptr: next()? },
+            Opcode::Add => Instruction::Add { src: next()? },
+            Opcode::Addi => Instruction::Addi { v: next()? },
+            Opcode::Sub => Instruction::Sub { src: next()? },
+            Opcode::Subi => Instruction::Subi { v: next()? },
+            Opcode::Shr => Instruction::Shr,
+            Opcode::Shl => Instruction::Shl,
+            Opcode::And => Instruction::And { src: next()? },
+            Opcode::Andi => Instruction::Andi { v: next()? },
+            Opcode::Or => Instruction::Or { src: next()? },
+            Opcode::Ori => Instruction::Ori { v: next()? },
+            Opcode::Xor => Instruction::Xor { src: next()? },
+            Opcode::Xori => Instruction::Xori { v: next()? },
+            Opcode::Not => Instruction::Not,
+            Opcode::Print => Instruction::Print { src: next()? },
+            Opcode::In => Instruction::In { eof: next()? },
+            Opcode::Stop => Instruction::Stop,
+            Opcode::Push => Instruction::Push,
+            Opcode::Pop => Instruction::Pop,
+            Opcode::Call => Instruction::Call { target: next()? },
+            Opcode::Ret => Instruction::Ret,
+        };
+
+        match next() {
+            None => Some(instr),
+            Some(_) => None,
+        }
+    }
+
+    /// Returns the opcode of this instruction. This basically just removes
+    /// information about the arguments.
+    pub fn opcode(&self) -> Opcode {
+        match *self {
+            Instruction::Nop => Opcode::Nop,
+            Instruction::Ld { .. } => Opcode::Ld,
+            Instruction::Ldi { .. } => Opcode::Ldi,
+            Instruction::St { .. } => Opcode::St,
+            Instruction::Sti { .. } => Opcode::Sti,
+            Instruction::Mov { .. } => Opcode::Mov,
+            Instruction::LdPtr { .. } => Opcode::LdPtr,
+            Instruction::StPtr { .. } => Opcode::StPtr,
+            Instruction::Jmp { .. } => Opcode::Jmp,
+            Instruction::Jz { .. } => Opcode::Jz,
+            Instruction::Jc { .. } => Opcode::Jc,
+            Instruction::Jnc { .. } => Opcode::Jnc,
+            Instruction::Jnz { .. } => Opcode::Jnz,
+            Instruction::JmpPtr { .. } => Opcode::JmpPtr,
+            Instruction::Add { .. } => Opcode::Add,
+            Instruction::Addi { .. } => Opcode::Addi,
+            Instruction::Sub { .. } => Opcode::Sub,
+            Instruction::Subi { .. } => Opcode::Subi,
+            Instruction::Shr => Opcode::Shr,
+            Instruction::Shl => Opcode::Shl,
+            Instruction::And { .. } => Opcode::And,
+            Instruction::Andi { .. } => Opcode::Andi,
+            Instruction::Or { .. } => Opcode::Or,
+            Instruction::Ori { .. } => Opcode::Ori,
+            Instruction::Xor { .. } => Opcode::Xor,
+            Instruction::Xori { .. } => Opcode::Xori,
+            Instruction::Not => Opcode::Not,
+            Instruction::Print { .. } => Opcode::Print,
+            Instruction::In { .. } => Opcode::In,
+            Instruction::Stop => Opcode::Stop,
+            Instruction::Push => Opcode::Push,
+            Instruction::Pop => Opcode::Pop,
+            Instruction::Call { .. } => Opcode::Call,
+            Instruction::Ret => Opcode::Ret,
+        }
+    }
+
+    /// Returns all arguments of this instruction in the order they are
+    /// encoded.
+    pub fn args(&self) -> Vec<&A> {
+        match self {
+            Instruction::Nop
+            | Instruction::Shr
+            | Instruction::Shl
+            | Instruction::Not
+            | Instruction::Stop
+            | Instruction::Push
+            | Instruction::Pop
+            | Instruction::Ret => vec![],
+
+            Instruction::Ld { src }
+            | Instruction::Add { src }
+            | Instruction::Sub { src }
+            | Instruction::And { src }
+            | Instruction::Or { src }
+            | Instruction::Xor { src }
+            | Instruction::Print { src } => vec![src],
+
+            Instruction::Ldi { v }
+            | Instruction::Addi { v }
+            | Instruction::Subi { v }
+            | Instruction::Andi { v }
+            | Instruction::Ori { v }
+            | Instruction::Xori { v } => vec![v],
+
+            Instruction::St { dst } => vec![dst],
+            Instruction::Sti { v, dst } => vec![v, dst],
+            Instruction::Mov { src, dst } => vec![src, dst],
+
+            Instruction::LdPtr { ptr }
+            | Instruction::StPtr { ptr }
+            | Instruction::JmpPtr { ptr } => vec![ptr],
+
+            Instruction::Jmp { target }
+            | Instruction::Jz { target }
+            | Instruction::Jc { target }
+            | Instruction::Jnc { target }
+            | Instruction::Jnz { target }
+            | Instruction::Call { target } => vec![target],
+
+            Instruction::In { eof } => vec![eof],
+        }
+    }
+
+    /// Converts all arguments with the given function, for example to
+    /// resolve labels.
+    pub fn map<B>(&self, f: impl FnMut(&A) -> B) -> Instruction<B> {
+        let args = self.args().into_iter().map(f).collect::<Vec<_>>();
+        Instruction::from_args(self.opcode(), args)
+            .expect("number of arguments doesn't change")
+    }
+}
+
+impl Instruction<u8> {
+    /// Decodes the instruction at the start of `bytes`. Additional bytes after
+    /// the instruction are ignored.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let opcode = match bytes.first() {
+            Some(&byte) => Opcode::from_byte(byte).ok_or(DecodeError::IllegalOpcode(byte))?,
+            None => return Err(DecodeError::Truncated),
+        };
+
+        let args = bytes.get(1..opcode.len() as usize).ok_or(DecodeError::Truncated)?;
+        Ok(Instruction::from_args(opcode, args.iter().cloned())
+            .expect("slice has the correct length"))
+    }
+
+    /// Encodes this instruction and appends the bytes to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.opcode().to_byte());
+        out.extend(self.args().into_iter().cloned());
+    }
+}
+
+
+/// An error when decoding an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The first byte is not a valid opcode.
+    IllegalOpcode(u8),
+
+    /// The bytes end before the instruction does.
+    Truncated,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::IllegalOpcode(byte) => write!(f, "illegal opcode {:02x}", byte),
+            DecodeError::Truncated => write!(f, "instruction is truncated"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
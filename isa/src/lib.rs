@@ -0,0 +1,17 @@
+//! The instruction set of the SHiT CPU.
+//!
+//! This crate is shared by the emulator and the assembler, so that opcodes,
+//! mnemonics and the encoding of instructions are only defined once. Other
+//! instruction sets can be described in a file, see `InstructionSet`.
+
+mod description;
+mod instr;
+mod opcode;
+
+pub use crate::{
+    description::{
+        BinaryOp, Condition, InstructionDef, InstructionSet, MicroOp, Register, UnaryOp, Value,
+    },
+    instr::{DecodeError, Instruction},
+    opcode::{Extension, Opcode, OperandKind},
+};
@@ -0,0 +1,274 @@
+//! Defines all opcodes and their operands.
+
+
+/// The kind of argument an instruction expects in a specific position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandKind {
+    /// A value that is used as is, like `$61`.
+    Immediate,
+
+    /// A value that is an address in memory, like `[CHAR]`.
+    Address,
+
+    /// A value that is used as jump target, like `.end`. This is written like
+    /// an immediate value.
+    Target,
+
+    /// The address of a pointer to the accessed byte, like `[[PTR]]`.
+    Indirect,
+}
+
+impl OperandKind {
+    /// Returns how this operand is written in the assembler syntax, for use
+    /// in error messages.
+    pub fn placeholder(self) -> &'static str {
+        match self {
+            OperandKind::Immediate => "$value",
+            OperandKind::Address => "[ADDR]",
+            OperandKind::Target => ".label",
+            OperandKind::Indirect => "[[ADDR]]",
+        }
+    }
+}
+
+
+/// An optional extension of the instruction set. Its opcodes are only
+/// executed by machines that have the extension enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Extension {
+    /// The carry flag, set by `add`, `sub`, `shl` and `shr`, and the jumps
+    /// `jc`, `jnc` and `jnz`.
+    Carry,
+
+    /// Loads, stores and jumps through pointers in memory.
+    Indirect,
+
+    /// A stack pointer with `push`, `pop`, `call` and `ret`.
+    Stack,
+}
+
+
+/// Represents an instruction without the arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    // $0_
+    Nop,
+
+    // $1_ (data transfer)
+    Ld,
+    Ldi,
+    St,
+    Sti,
+    Mov,
+    LdPtr,
+    StPtr,
+
+    // $2_ (control flow)
+    Jmp,
+    Jz,
+    Jc,
+    Jnc,
+    Jnz,
+    JmpPtr,
+
+    // $3_ (arithmetic)
+    Add,
+    Addi,
+    Sub,
+    Subi,
+    Shr,
+    Shl,
+    And,
+    Andi,
+    Or,
+    Ori,
+    Xor,
+    Xori,
+    Not,
+
+    // $4_ (input/output)
+    Print,
+    In,
+
+    // $5_
+    Stop,
+
+    // $6_ (stack)
+    Push,
+    Pop,
+    Call,
+    Ret,
+}
+
+impl Opcode {
+    /// All opcodes, ordered by their byte.
+    pub const ALL: [Opcode; 34] = [
+        Opcode::Nop,
+        Opcode::Ld,
+        Opcode::Ldi,
+        Opcode::St,
+        Opcode::Sti,
+        Opcode::Mov,
+        Opcode::LdPtr,
+        Opcode::StPtr,
+        Opcode::Jmp,
+        Opcode::Jz,
+        Opcode::Jc,
+        Opcode::Jnc,
+        Opcode::Jnz,
+        Opcode::JmpPtr,
+        Opcode::Add,
+        Opcode::Addi,
+        Opcode::Sub,
+        Opcode::Subi,
+        Opcode::Shr,
+        Opcode::Shl,
+        Opcode::And,
+        Opcode::Andi,
+        Opcode::Or,
+        Opcode::Ori,
+        Opcode::Xor,
+        Opcode::Xori,
+        Opcode::Not,
+        Opcode::Print,
+        Opcode::In,
+        Opcode::Stop,
+        Opcode::Push,
+        Opcode::Pop,
+        Opcode::Call,
+        Opcode::Ret,
+    ];
+
+    /// Returns the opcode with the given byte or `None` if there is no such
+    /// opcode.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|op| op.to_byte() == byte)
+    }
+
+    /// Returns all opcodes with the given mnemonic. Opcodes can share a
+    /// mnemonic if their operands differ, like `ld [ADDR]` and `ld [[ADDR]]`.
+    pub fn from_mnemonic(mnemonic: &str) -> Vec<Self> {
+        Self::ALL.iter().cloned().filter(|op| op.mnemonic() == mnemonic).collect()
+    }
+
+    /// Returns the byte of this opcode.
+    pub fn to_byte(self) -> u8 {
+        use self::Opcode::*;
+
+        match self {
+            Nop => 0x00,
+            Ld => 0x10,
+            Ldi => 0x11,
+            St => 0x12,
+            Sti => 0x13,
+            Mov => 0x14,
+            LdPtr => 0x15,
+            StPtr => 0x16,
+            Jmp => 0x20,
+            Jz => 0x21,
+            Jc => 0x22,
+            Jnc => 0x23,
+            Jnz => 0x24,
+            JmpPtr => 0x25,
+            Add => 0x30,
+            Addi => 0x31,
+            Sub => 0x32,
+            Subi => 0x33,
+            Shr => 0x34,
+            Shl => 0x35,
+            And => 0x36,
+            Andi => 0x37,
+            Or => 0x38,
+            Ori => 0x39,
+            Xor => 0x3a,
+            Xori => 0x3b,
+            Not => 0x3c,
+            Print => 0x40,
+            In => 0x41,
+            Stop => 0x50,
+            Push => 0x60,
+            Pop => 0x61,
+            Call => 0x62,
+            Ret => 0x63,
+        }
+    }
+
+    /// Returns the name of this opcode as written in the source code.
+    pub fn mnemonic(self) -> &'static str {
+        use self::Opcode::*;
+
+        match self {
+            Nop => "nop",
+            Ld => "ld",
+            Ldi => "ldi",
+            St => "st",
+            Sti => "sti",
+            Mov => "mov",
+            LdPtr => "ld",
+            StPtr => "st",
+            Jmp => "jmp",
+            Jz => "jz",
+            Jc => "jc",
+            Jnc => "jnc",
+            Jnz => "jnz",
+            JmpPtr => "jmp",
+            Add => "add",
+            Addi => "addi",
+            Sub => "sub",
+            Subi => "subi",
+            Shr => "shr",
+            Shl => "shl",
+            And => "and",
+            Andi => "andi",
+            Or => "or",
+            Ori => "ori",
+            Xor => "xor",
+            Xori => "xori",
+            Not => "not",
+            Print => "print",
+            In => "in",
+            Stop => "stop",
+            Push => "push",
+            Pop => "pop",
+            Call => "call",
+            Ret => "ret",
+        }
+    }
+
+    /// Returns the kinds of the arguments of this opcode in the order they
+    /// are written and encoded.
+    pub fn operands(self) -> &'static [OperandKind] {
+        use self::Opcode::*;
+        use self::OperandKind::*;
+
+        match self {
+            Nop | Shr | Shl | Not | Stop | Push | Pop | Ret => &[],
+            Ld | St | Add | Sub | And | Or | Xor | Print | JmpPtr => &[Address],
+            Ldi | Addi | Subi | Andi | Ori | Xori => &[Immediate],
+            Sti => &[Immediate, Address],
+            Mov => &[Address, Address],
+            Jmp | Jz | Jc | Jnc | Jnz | In | Call => &[Target],
+            LdPtr | StPtr => &[Indirect],
+        }
+    }
+
+    /// Returns the extension this opcode belongs to, or `None` if it is part
+    /// of the base instruction set.
+    pub fn extension(self) -> Option<Extension> {
+        use self::Opcode::*;
+
+        match self {
+            Jc | Jnc | Jnz => Some(Extension::Carry),
+            LdPtr | StPtr | JmpPtr => Some(Extension::Indirect),
+            Push | Pop | Call | Ret => Some(Extension::Stack),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes this instruction (with its arguments)
+    /// occupies.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(self) -> u8 {
+        1 + self.operands().len() as u8
+    }
+}
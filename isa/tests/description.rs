@@ -0,0 +1,123 @@
+//! Checks parsing instruction set descriptions.
+
+use shit_isa::{Instruction, InstructionSet, MicroOp, Opcode, Register, Value};
+
+
+#[test]
+fn builtin_matches_opcode_table() {
+    let isa = InstructionSet::builtin();
+    assert_eq!(isa.instructions().count(), Opcode::ALL.len());
+    for opcode in Opcode::ALL.iter() {
+        let def = isa.get(opcode.to_byte()).expect("opcode is described");
+        assert_eq!(def.mnemonic(), opcode.mnemonic());
+        assert_eq!(def.operands(), opcode.operands());
+        assert_eq!(def.extension(), opcode.extension());
+        assert_eq!(def.len(), opcode.len());
+    }
+}
+
+#[test]
+fn decode_like_builtin() {
+    let isa = InstructionSet::builtin();
+    for bytes in [[0x13, 0x2a, 0x10], [0x62, 0x05, 0x00], [0x63, 0x00, 0x00], [0xff, 0, 0]] {
+        match (isa.decode(&bytes), Instruction::decode(&bytes)) {
+            (Ok((def, args)), Ok(instr)) => {
+                assert_eq!(def.opcode(), instr.opcode().to_byte());
+                assert_eq!(args.iter().collect::<Vec<_>>(), instr.args());
+            }
+            (Err(a), Err(b)) => assert_eq!(a, b),
+            (a, b) => panic!("{:?} != {:?}", a, b),
+        }
+    }
+}
+
+#[test]
+fn builtin_compiles_to_instructions() {
+    for def in InstructionSet::builtin().instructions() {
+        let bytes = [def.opcode(), 0x2a, 0x10];
+        let args = &bytes[1..def.len() as usize];
+        let instr = Instruction::decode(&bytes).ok();
+        assert_eq!(def.to_instruction(args), instr, "{}", def.mnemonic());
+    }
+
+    // Micro-operations without a matching instruction are interpreted
+    let isa = InstructionSet::parse(
+        "[[instruction]]\nopcode = 0x01\nmnemonic = \"x\"\nsemantics = [\"mov t, acc\"]\n",
+    )
+    .unwrap();
+    assert_eq!(isa.get(0x01).unwrap().to_instruction(&[]), None);
+}
+
+#[test]
+fn successors() {
+    let isa = InstructionSet::builtin();
+    let successors = |opcode: Opcode, args: &[u8]| {
+        let mut s = isa.get(opcode.to_byte()).unwrap().successors(args, 0x10);
+        s.sort_unstable();
+        s
+    };
+
+    assert_eq!(successors(Opcode::Ld, &[0x20]), [0x10]);
+    assert_eq!(successors(Opcode::Jmp, &[0x20]), [0x20]);
+    assert_eq!(successors(Opcode::Jz, &[0x20]), [0x10, 0x20]);
+    assert_eq!(successors(Opcode::In, &[0x20]), [0x10, 0x20]);
+    assert_eq!(successors(Opcode::Call, &[0x20]), [0x10, 0x20]);
+    assert_eq!(successors(Opcode::JmpPtr, &[0x20]), []);
+    assert_eq!(successors(Opcode::Ret, &[]), []);
+    assert_eq!(successors(Opcode::Stop, &[]), []);
+}
+
+#[test]
+fn custom_instruction() {
+    let isa = InstructionSet::parse(
+        r#"
+        [[instruction]]
+        opcode = 0x70
+        mnemonic = "swap"
+        operands = ["address"]
+        length = 2
+        semantics = ["mov t, [a0]", "mov [a0], acc", "mov acc, t"]
+        "#,
+    )
+    .unwrap();
+
+    let def = isa.get(0x70).unwrap();
+    assert_eq!(def.mnemonic(), "swap");
+    assert_eq!(def.semantics()[0], MicroOp::Mov {
+        dst: Value::Register(Register::Tmp),
+        src: Value::Memory(Box::new(Value::Arg(0))),
+    });
+    assert!(isa.get(0x10).is_none());
+}
+
+#[test]
+fn invalid_descriptions() {
+    let invalid = |instructions: &[&str]| {
+        let src = instructions
+            .iter()
+            .map(|i| format!("[[instruction]]\n{}\n", i))
+            .collect::<String>();
+        InstructionSet::parse(&src).unwrap_err()
+    };
+
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"", "opcode = 0x10\nmnemonic = \"b\""]);
+    assert!(err.contains("described twice"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"", "opcode = 0x11\nmnemonic = \"a\""]);
+    assert!(err.contains("more than once"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"1a\""]);
+    assert!(err.contains("invalid mnemonic"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"\noperands = [\"register\"]"]);
+    assert!(err.contains("invalid operand kind"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"\nlength = 2"]);
+    assert!(err.contains("length"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"\nextension = \"fpu\""]);
+    assert!(err.contains("unknown extension"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"\nsemantics = [\"mov acc, a0\"]"]);
+    assert!(err.contains("only 0 operand(s)"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"\nsemantics = [\"mov $01, acc\"]"]);
+    assert!(err.contains("can't be written"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"\nsemantics = [\"jmp\"]"]);
+    assert!(err.contains("invalid micro-operation"), "{}", err);
+    let err = invalid(&["opcode = 0x10\nmnemonic = \"a\"\ncolor = \"red\""]);
+    assert!(err.contains("unknown field"), "{}", err);
+}
@@ -0,0 +1,77 @@
+//! Property tests for encoding and decoding instructions.
+
+use proptest::prelude::*;
+use shit_isa::{DecodeError, Instruction, Opcode};
+
+
+/// Generates any valid instruction.
+fn instruction() -> impl Strategy<Value = Instruction> {
+    (0..Opcode::ALL.len(), any::<[u8; 2]>()).prop_map(|(idx, args)| {
+        let opcode = Opcode::ALL[idx];
+        let args = args.iter().cloned().take(opcode.operands().len());
+        Instruction::from_args(opcode, args).unwrap()
+    })
+}
+
+proptest! {
+    #[test]
+    fn decode_inverts_encode(instr in instruction(), trailing in any::<Vec<u8>>()) {
+        let mut bytes = vec![];
+        instr.encode(&mut bytes);
+        prop_assert_eq!(bytes.len(), instr.opcode().len() as usize);
+
+        // Bytes after the instruction don't matter
+        bytes.extend(trailing);
+        prop_assert_eq!(Instruction::decode(&bytes), Ok(instr));
+    }
+
+    #[test]
+    fn encode_inverts_decode(bytes in any::<[u8; 3]>()) {
+        match Instruction::decode(&bytes) {
+            Ok(instr) => {
+                let mut encoded = vec![];
+                instr.encode(&mut encoded);
+                prop_assert_eq!(&encoded[..], &bytes[..encoded.len()]);
+            }
+            Err(e) => {
+                prop_assert_eq!(e, DecodeError::IllegalOpcode(bytes[0]));
+                prop_assert!(Opcode::from_byte(bytes[0]).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_instructions(instr in instruction()) {
+        let mut bytes = vec![];
+        instr.encode(&mut bytes);
+        for len in 0..bytes.len() {
+            prop_assert_eq!(Instruction::decode(&bytes[..len]), Err(DecodeError::Truncated));
+        }
+    }
+
+    #[test]
+    fn map_keeps_instruction(instr in instruction()) {
+        let mapped = instr.map(|&arg| u16::from(arg) + 1000);
+        prop_assert_eq!(mapped.opcode(), instr.opcode());
+        prop_assert_eq!(mapped.map(|&arg| (arg - 1000) as u8), instr);
+    }
+}
+
+#[test]
+fn opcode_table_is_consistent() {
+    for (i, opcode) in Opcode::ALL.iter().enumerate() {
+        assert_eq!(Opcode::from_byte(opcode.to_byte()), Some(*opcode));
+
+        // Opcodes sharing a mnemonic are told apart by their operands
+        let same_mnemonic = Opcode::from_mnemonic(opcode.mnemonic());
+        assert!(same_mnemonic.contains(opcode));
+        for other in same_mnemonic.iter().filter(|&other| other != opcode) {
+            assert_ne!(other.operands(), opcode.operands());
+        }
+
+        // `ALL` is sorted by byte and every byte is unique
+        if i > 0 {
+            assert!(Opcode::ALL[i - 1].to_byte() < opcode.to_byte());
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::PathBuf,
+};
+
+use shit_cpu_emu::{Coverage, InstructionSet, SourceMap};
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // Get CLI arguments or print usage when they are invalid
+    let args = match Args::from_env() {
+        Some(args) => args,
+        None => {
+            println!("<program> or <coverage> argument missing!");
+            println!();
+            println!("Usage:");
+            println!("  lcov <program> <coverage>... [-o <output>] [--isa <file>]");
+            println!();
+            println!("Creates an LCOV report from the coverage data written by");
+            println!("`shit-cpu-emu --coverage`. The program has to be assembled with");
+            println!("`-g`, so that its source map can be found. If no output is");
+            println!("given, the report is printed. Programs for another instruction");
+            println!("set need its description given with `--isa`.");
+            std::process::exit(1);
+        }
+    };
+
+    let bin = fs::read(&args.program)?;
+    let map = SourceMap::load(args.program.with_extension("map"))?;
+
+    let mut coverage = Coverage::new();
+    for path in &args.coverage {
+        coverage.merge(&Coverage::load(path)?);
+    }
+
+    let isa = match &args.isa {
+        Some(path) => InstructionSet::load(path)?,
+        None => InstructionSet::builtin().clone(),
+    };
+
+    let report = coverage.lcov(&bin, &map, &isa);
+    match args.output {
+        Some(path) => fs::write(path, report)?,
+        None => print!("{}", report),
+    }
+
+    Ok(())
+}
+
+/// The command line arguments.
+struct Args {
+    program: PathBuf,
+    coverage: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    isa: Option<PathBuf>,
+}
+
+impl Args {
+    /// Parses the arguments passed to this program. Returns `None` if they are
+    /// invalid.
+    fn from_env() -> Option<Self> {
+        let mut program = None;
+        let mut coverage = vec![];
+        let mut output = None;
+        let mut isa = None;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => output = Some(PathBuf::from(args.next()?)),
+                "--isa" => isa = Some(PathBuf::from(args.next()?)),
+                _ if program.is_none() => program = Some(PathBuf::from(arg)),
+                _ => coverage.push(PathBuf::from(arg)),
+            }
+        }
+
+        if coverage.is_empty() {
+            return None;
+        }
+        Some(Self { program: program?, coverage, output, isa })
+    }
+}
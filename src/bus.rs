@@ -0,0 +1,310 @@
+//! Defines the `Bus` the machine accesses memory through, and a bus that maps
+//! address ranges to devices.
+
+use std::{fmt, ops::RangeInclusive};
+
+use crate::{
+    memory::Memory,
+    snapshot::SnapshotError,
+};
+
+
+/// Everything the machine reads from and writes to.
+///
+/// Reads and writes may have side effects, like consuming input. `peek` is
+/// used by the debugger, the disassembler and for fetching instructions and
+/// must not have side effects. The same goes for `poke`, which is used to
+/// change the memory from outside of the machine.
+pub trait Bus {
+    /// Reads the byte at `addr`.
+    fn read(&mut self, addr: u8) -> u8;
+
+    /// Writes `value` to `addr`.
+    fn write(&mut self, addr: u8, value: u8);
+
+    /// Returns the byte at `addr` without any side effects.
+    fn peek(&self, addr: u8) -> u8;
+
+    /// Writes `value` to `addr` without any side effects. Returns `false` and
+    /// leaves the bus unchanged if that isn't possible, because `addr` isn't
+    /// plain memory.
+    fn poke(&mut self, addr: u8, value: u8) -> bool;
+
+    /// Returns whether `poke` can write to `addr`, without writing anything.
+    fn can_poke(&self, addr: u8) -> bool;
+
+    /// Called once after every executed instruction.
+    fn tick(&mut self) {}
+
+    /// Returns whether the byte at `addr` only changes when it is written,
+    /// so that the machine may cache the instruction it belongs to.
+    fn is_cacheable(&self, _addr: u8) -> bool {
+        true
+    }
+
+    /// Returns the memory behind the bus with all of its banks, including the
+    /// bytes hidden by devices.
+    fn ram(&self) -> &Memory;
+
+    /// Appends the complete state of the bus, including the memory, to `out`.
+    fn save_state(&self, out: &mut Vec<u8>);
+
+    /// Restores a state written by `save_state`. On error, the bus is left
+    /// unchanged.
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SnapshotError>;
+}
+
+/// Plain RAM without any devices.
+impl Bus for Memory {
+    fn read(&mut self, addr: u8) -> u8 {
+        self[addr]
+    }
+
+    fn write(&mut self, addr: u8, value: u8) {
+        self[addr] = value;
+    }
+
+    fn peek(&self, addr: u8) -> u8 {
+        self[addr]
+    }
+
+    fn poke(&mut self, addr: u8, value: u8) -> bool {
+        self[addr] = value;
+        true
+    }
+
+    fn can_poke(&self, _addr: u8) -> bool {
+        true
+    }
+
+    /// The selected bank changes the bytes in the bank window without
+    /// writing them.
+    fn is_cacheable(&self, addr: u8) -> bool {
+        !self.is_banked(addr)
+    }
+
+    fn ram(&self) -> &Memory {
+        self
+    }
+
+    /// Writes all banks, followed by the bank select register if there is
+    /// more than one bank.
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+        if self.banks() > 1 {
+            out.push(self[self.bank_select()]);
+        }
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        if state.len() != memory_state_len(self) {
+            return Err(SnapshotError::BusMismatch);
+        }
+
+        let (bytes, select) = state.split_at(self.as_bytes().len());
+        let mut memory = Memory::from_image(bytes, self.banks()).expect("size checked above");
+        memory.set_bank_window(self.bank_window(), self.bank_select());
+        if let [bank] = *select {
+            memory[self.bank_select()] = bank;
+        }
+        *self = memory;
+        Ok(())
+    }
+}
+
+/// A peripheral that is mapped into the address space by `MappedBus`.
+///
+/// All methods get the offset of the accessed address from the start of the
+/// range the device is mapped to.
+pub trait Device {
+    /// Reads the register at `offset`.
+    fn read(&mut self, offset: u8) -> u8;
+
+    /// Writes `value` to the register at `offset`.
+    fn write(&mut self, offset: u8, value: u8);
+
+    /// Returns the register at `offset` without any side effects.
+    fn peek(&self, offset: u8) -> u8;
+
+    /// Called once after every executed instruction.
+    fn tick(&mut self) {}
+
+    /// Appends the state of the device to `out`. Devices without state don't
+    /// need to implement this.
+    fn save_state(&self, _out: &mut Vec<u8>) {}
+
+    /// Restores a state written by `save_state`.
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        match state {
+            [] => Ok(()),
+            _ => Err(SnapshotError::BusMismatch),
+        }
+    }
+}
+
+/// A device mapped to an address range.
+struct Mapping {
+    range: RangeInclusive<u8>,
+    device: Box<dyn Device>,
+}
+
+/// RAM with devices mapped over some address ranges. Accesses to a mapped
+/// address go to the device, all others to the RAM.
+pub struct MappedBus {
+    ram: Memory,
+    mappings: Vec<Mapping>,
+}
+
+impl MappedBus {
+    /// Creates a bus with the given RAM and no devices.
+    pub fn new(ram: Memory) -> Self {
+        Self { ram, mappings: vec![] }
+    }
+
+    /// Maps `device` to the addresses in `range`.
+    ///
+    /// Panics if the range overlaps the range of a device that was mapped
+    /// before.
+    pub fn map(&mut self, range: RangeInclusive<u8>, device: impl Device + 'static) {
+        let overlaps = self.mappings.iter().any(|m| {
+            m.range.start() <= range.end() && range.start() <= m.range.end()
+        });
+        assert!(!overlaps, "device mapped to {:02x?}, which overlaps another device", range);
+
+        self.mappings.push(Mapping { range, device: Box::new(device) });
+    }
+
+    /// Returns the RAM, including the bytes hidden by devices.
+    pub fn ram(&self) -> &Memory {
+        &self.ram
+    }
+
+    /// Returns the RAM mutably.
+    pub fn ram_mut(&mut self) -> &mut Memory {
+        &mut self.ram
+    }
+
+    /// Returns the mapping that contains `addr` and the offset into it.
+    fn find(&self, addr: u8) -> Option<(usize, u8)> {
+        self.mappings
+            .iter()
+            .position(|m| m.range.contains(&addr))
+            .map(|i| (i, addr - self.mappings[i].range.start()))
+    }
+}
+
+impl Bus for MappedBus {
+    fn read(&mut self, addr: u8) -> u8 {
+        match self.find(addr) {
+            Some((i, offset)) => self.mappings[i].device.read(offset),
+            None => self.ram[addr],
+        }
+    }
+
+    fn write(&mut self, addr: u8, value: u8) {
+        match self.find(addr) {
+            Some((i, offset)) => self.mappings[i].device.write(offset, value),
+            None => self.ram[addr] = value,
+        }
+    }
+
+    fn peek(&self, addr: u8) -> u8 {
+        match self.find(addr) {
+            Some((i, offset)) => self.mappings[i].device.peek(offset),
+            None => self.ram[addr],
+        }
+    }
+
+    /// Device registers can't be written without side effects.
+    fn poke(&mut self, addr: u8, value: u8) -> bool {
+        if !self.can_poke(addr) {
+            return false;
+        }
+        self.ram[addr] = value;
+        true
+    }
+
+    fn can_poke(&self, addr: u8) -> bool {
+        self.find(addr).is_none()
+    }
+
+    fn tick(&mut self) {
+        for mapping in &mut self.mappings {
+            mapping.device.tick();
+        }
+    }
+
+    /// Devices may change their registers at any time.
+    fn is_cacheable(&self, addr: u8) -> bool {
+        self.find(addr).is_none() && self.ram.is_cacheable(addr)
+    }
+
+    fn ram(&self) -> &Memory {
+        &self.ram
+    }
+
+    /// Writes the RAM followed by the state of each device, prefixed with its
+    /// length.
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.ram.save_state(out);
+        for mapping in &self.mappings {
+            let mut state = vec![];
+            mapping.device.save_state(&mut state);
+            assert!(state.len() <= u8::MAX as usize, "device state too large");
+
+            out.push(state.len() as u8);
+            out.extend_from_slice(&state);
+        }
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        let ram_len = memory_state_len(&self.ram);
+        if state.len() < ram_len {
+            return Err(SnapshotError::BusMismatch);
+        }
+        let (ram, mut rest) = state.split_at(ram_len);
+
+        // Split the state of all devices first, so that we don't change
+        // anything if the number of devices doesn't match.
+        let mut devices = vec![];
+        for _ in &self.mappings {
+            let (&len, tail) = rest.split_first().ok_or(SnapshotError::BusMismatch)?;
+            if tail.len() < len as usize {
+                return Err(SnapshotError::BusMismatch);
+            }
+            let (device, tail) = tail.split_at(len as usize);
+            devices.push(device);
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return Err(SnapshotError::BusMismatch);
+        }
+
+        // A device can still reject its state. Then we restore the state we
+        // had before, which the devices always accept.
+        let mut before = vec![];
+        self.save_state(&mut before);
+        for (mapping, device) in self.mappings.iter_mut().zip(devices) {
+            if let Err(e) = mapping.device.load_state(device) {
+                self.load_state(&before).expect("restoring own state failed");
+                return Err(e);
+            }
+        }
+        self.ram.load_state(ram)
+    }
+}
+
+/// Returns the length of the state `Memory::save_state` writes.
+fn memory_state_len(memory: &Memory) -> usize {
+    memory.as_bytes().len() + (memory.banks() > 1) as usize
+}
+
+impl fmt::Debug for MappedBus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ranges = self.mappings.iter().map(|m| &m.range).collect::<Vec<_>>();
+        f.debug_struct("MappedBus")
+            .field("ram", &self.ram)
+            .field("mappings", &ranges)
+            .finish()
+    }
+}
@@ -0,0 +1,195 @@
+//! Records which instructions and branch directions were executed, and turns
+//! that into LCOV reports.
+//!
+//! Coverage data is saved in a simple text format, so that the data of
+//! multiple runs can be merged. Every line is either `hit <addr> <count>` for
+//! an executed instruction or `branch <addr> <taken> <not taken>` for a
+//! conditional branch, with the address in hex.
+
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use shit_isa::InstructionSet;
+
+use crate::{
+    bus::Bus,
+    fault::Fault,
+    machine::{Machine, Status},
+    source_map::SourceMap,
+    trace::Tracer,
+};
+
+
+/// A tracer which records executed instructions and branch directions.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    hits: BTreeMap<u8, u64>,
+
+    /// How often each conditional branch was taken and not taken.
+    branches: BTreeMap<u8, (u64, u64)>,
+
+    // The instruction currently executed and the address following it, if
+    // it is a conditional branch
+    current: Option<(u8, Option<u8>)>,
+}
+
+impl Coverage {
+    /// Creates empty coverage data.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how often the instruction at `addr` was executed.
+    pub fn hits(&self, addr: u8) -> u64 {
+        self.hits.get(&addr).cloned().unwrap_or(0)
+    }
+
+    /// Returns how often the conditional branch at `addr` was taken and not
+    /// taken.
+    pub fn branch(&self, addr: u8) -> (u64, u64) {
+        self.branches.get(&addr).cloned().unwrap_or((0, 0))
+    }
+
+    /// Adds the counts of `other` to this coverage data.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&addr, &count) in &other.hits {
+            *self.hits.entry(addr).or_insert(0) += count;
+        }
+        for (&addr, &(taken, not_taken)) in &other.branches {
+            let entry = self.branches.entry(addr).or_insert((0, 0));
+            entry.0 += taken;
+            entry.1 += not_taken;
+        }
+    }
+
+    /// Parses coverage data in the format described in the module docs.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut coverage = Self::new();
+        for (line_number, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || format!("invalid coverage data in line {}: '{}'", line_number + 1, line);
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            let addr = parts.get(1).and_then(|s| u8::from_str_radix(s, 16).ok());
+            let counts = parts.iter().skip(2).map(|s| s.parse::<u64>().ok()).collect::<Vec<_>>();
+            match (parts[0], addr, &counts[..]) {
+                ("hit", Some(addr), &[Some(count)]) => {
+                    *coverage.hits.entry(addr).or_insert(0) += count;
+                }
+                ("branch", Some(addr), &[Some(taken), Some(not_taken)]) => {
+                    let entry = coverage.branches.entry(addr).or_insert((0, 0));
+                    entry.0 += taken;
+                    entry.1 += not_taken;
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(coverage)
+    }
+
+    /// Loads and parses the coverage data at the given path.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let src = fs::read_to_string(path)?;
+        Self::parse(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Formats the coverage data in the format described in the module docs.
+    pub fn to_data(&self) -> String {
+        let mut out = String::new();
+        for (addr, count) in &self.hits {
+            out.push_str(&format!("hit {:02x} {}\n", addr, count));
+        }
+        for (addr, (taken, not_taken)) in &self.branches {
+            out.push_str(&format!("branch {:02x} {} {}\n", addr, taken, not_taken));
+        }
+
+        out
+    }
+
+    /// Creates an LCOV report for the program `bin`, using its source map to
+    /// find the source lines and `isa` to find the conditional branches. Each
+    /// of them gets two LCOV branches: the first one for the jump being
+    /// taken, the second one for falling through.
+    pub fn lcov(&self, bin: &[u8], map: &SourceMap, isa: &InstructionSet) -> String {
+        let mut lines = BTreeMap::new();
+        let mut branches = vec![];
+        for (addr, line) in map.code() {
+            *lines.entry(line).or_insert(0) += self.hits(addr);
+
+            let start = addr as usize;
+            let bytes = (0..3).map(|i| bin.get(start + i).cloned().unwrap_or(0)).collect::<Vec<_>>();
+            if let Ok((def, _)) = isa.decode(&bytes) {
+                if def.is_conditional_branch() {
+                    branches.push((addr, line));
+                }
+            }
+        }
+
+        let mut out = format!("TN:\nSF:{}\n", map.source().display());
+        let mut branches_hit = 0;
+        for &(addr, line) in &branches {
+            let (taken, not_taken) = self.branch(addr);
+            for (i, &count) in [taken, not_taken].iter().enumerate() {
+                if self.hits(addr) == 0 {
+                    out.push_str(&format!("BRDA:{},{},{},-\n", line, addr, i));
+                } else {
+                    out.push_str(&format!("BRDA:{},{},{},{}\n", line, addr, i, count));
+                }
+                if count > 0 {
+                    branches_hit += 1;
+                }
+            }
+        }
+        out.push_str(&format!("BRF:{}\nBRH:{}\n", branches.len() * 2, branches_hit));
+
+        for (line, count) in &lines {
+            out.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        let lines_hit = lines.values().filter(|&&count| count > 0).count();
+        out.push_str(&format!("LF:{}\nLH:{}\n", lines.len(), lines_hit));
+        out.push_str("end_of_record\n");
+
+        out
+    }
+}
+
+impl Tracer for Coverage {
+    fn before<B: Bus>(&mut self, machine: &Machine<B>) {
+        let pc = machine.pc();
+        let bytes = [
+            machine.bus().peek(pc),
+            machine.bus().peek(pc.wrapping_add(1)),
+            machine.bus().peek(pc.wrapping_add(2)),
+        ];
+        // A branch to the following instruction always counts as not taken
+        let next = match machine.instruction_set().decode(&bytes) {
+            Ok((def, _)) if def.is_conditional_branch() => Some(pc.wrapping_add(def.len())),
+            _ => None,
+        };
+
+        self.current = Some((pc, next));
+    }
+
+    fn after<B: Bus>(&mut self, machine: &Machine<B>, result: &Result<Status, Fault>) {
+        let (pc, next) = match self.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        if let Ok(Status::Trapped(_)) | Err(_) = result {
+            return;
+        }
+
+        *self.hits.entry(pc).or_insert(0) += 1;
+        if let Some(next) = next {
+            let entry = self.branches.entry(pc).or_insert((0, 0));
+            if machine.pc() == next {
+                entry.1 += 1;
+            } else {
+                entry.0 += 1;
+            }
+        }
+    }
+}
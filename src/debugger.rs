@@ -0,0 +1,491 @@
+//! An interactive debugger which reads commands from stdin or any other
+//! reader.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    bus::Bus,
+    disasm::{self, Disassembled},
+    fault::Fault,
+    history::History,
+    machine::{Machine, Status},
+    memory::{Memory, MACHINE_MEMORY_SIZE},
+    snapshot::Snapshot,
+    symbols::Symbols,
+};
+
+
+const HELP: &str = "\
+Commands (short forms in parentheses):
+  step [n]          (s)  execute n instructions, 1 by default
+  continue          (c)  run until a breakpoint, watchpoint or halt
+  reverse-step [n]  (rs) undo the last n instructions, 1 by default
+  reverse-continue  (rc) run backwards until a breakpoint or watchpoint
+  last-write <addr> (lw) run backwards to the last write to <addr>
+  break <addr>      (b)  set a breakpoint
+  delete <addr>     (d)  remove a breakpoint
+  watch <addr>      (w)  stop when the byte at <addr> changes
+  unwatch <addr>         remove a watchpoint
+  info              (i)  show registers, breakpoints and watchpoints
+  mem <addr> [len]  (x)  show <len> bytes of memory, 16 by default
+  set pc <value>         set the program counter
+  set acc <value>        set the accumulator
+  set carry <0|1>        set the carry flag
+  set sp <value>         set the stack pointer
+  set <addr> <value>     set a byte in memory, but not a device register
+  disas [addr] [n]  (l)  disassemble n instructions at <addr> or around pc
+  snapshot <file>        save the state of the machine to <file>
+  restore <file>         load the state of the machine from <file>
+  help              (h)  show this help
+  quit              (q)  quit the debugger
+
+Addresses and values are hex numbers (`1f`, `$1f` or `0x1f`) or labels
+from the symbol file (`.start`). Counts are decimal numbers.
+
+Running backwards undoes registers and memory, but not input or output.
+Changing the machine with `set` or `restore` clears the history.";
+
+/// Why the execution was interrupted.
+#[derive(Debug, Clone, Copy)]
+enum Event {
+    /// The program counter reached a breakpoint.
+    Breakpoint,
+
+    /// A watched byte was changed.
+    Watchpoint { addr: u8, old: u8, new: u8 },
+
+    /// The machine executed `stop`.
+    Halted,
+
+    /// The machine trapped, see `FaultPolicy::Trap`.
+    Trapped(Fault),
+
+    /// The machine faulted, see `FaultPolicy::Halt`.
+    Fault(Fault),
+
+    /// There are no more recorded steps to undo.
+    StartOfHistory,
+}
+
+/// The debugger state: the machine that is debugged and everything the user
+/// configured.
+pub struct Debugger<B: Bus = Memory> {
+    machine: Machine<B>,
+    symbols: Symbols,
+    breakpoints: BTreeSet<u8>,
+    watchpoints: BTreeSet<u8>,
+    history: History,
+}
+
+impl<B: Bus> Debugger<B> {
+    /// Creates a debugger for the given machine. Labels from `symbols` are
+    /// accepted as addresses and shown in listings.
+    pub fn new(machine: Machine<B>, symbols: Symbols) -> Self {
+        Self {
+            machine,
+            symbols,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            history: History::default(),
+        }
+    }
+
+    /// Returns the debugged machine.
+    pub fn machine(&self) -> &Machine<B> {
+        &self.machine
+    }
+
+    /// Reads and executes commands from stdin until the user quits or stdin
+    /// is closed.
+    pub fn run(&mut self) -> io::Result<()> {
+        let stdin = io::stdin();
+        self.run_with(stdin.lock(), io::stdout())
+    }
+
+    /// Like `run`, but reads the commands from `input` and writes everything
+    /// the debugger prints to `out`.
+    pub fn run_with(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        let mut text = String::new();
+        self.show_location(&mut text);
+        out.write_all(text.as_bytes())?;
+
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(shit) ")?;
+            out.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+
+            let mut text = String::new();
+            let result = self.execute(&line, &mut text);
+            out.write_all(text.as_bytes())?;
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => writeln!(out, "error: {}", e)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Executes a single command and appends what it prints to `out`.
+    /// Returns `Ok(false)` if the debugger should quit.
+    fn execute(&mut self, line: &str, out: &mut String) -> Result<bool, String> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let (cmd, args) = match words.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => return Ok(true),
+        };
+
+        match (cmd, args) {
+            ("s", _) | ("step", _) => {
+                let count: u64 = match args {
+                    [] => 1,
+                    [n] => parse_count(n)?,
+                    _ => return Err("usage: step [n]".into()),
+                };
+
+                for _ in 0..count {
+                    if let Some(event) = self.single_step() {
+                        self.report(event, out);
+                        break;
+                    }
+                }
+                self.show_location(out);
+            }
+            ("c", []) | ("continue", []) => {
+                let event = self.cont();
+                self.report(event, out);
+                self.show_location(out);
+            }
+            ("rs", _) | ("reverse-step", _) => {
+                let count: u64 = match args {
+                    [] => 1,
+                    [n] => parse_count(n)?,
+                    _ => return Err("usage: reverse-step [n]".into()),
+                };
+
+                for _ in 0..count {
+                    if let Some(event) = self.single_step_back() {
+                        self.report(event, out);
+                        break;
+                    }
+                }
+                self.show_location(out);
+            }
+            ("rc", []) | ("reverse-continue", []) => {
+                let event = self.reverse_cont();
+                self.report(event, out);
+                self.show_location(out);
+            }
+            ("lw", [addr]) | ("last-write", [addr]) => {
+                let addr = self.parse_value(addr)?;
+                let steps = self.history
+                    .last_write(addr)
+                    .ok_or_else(|| format!("no recorded write to {}", self.fmt_addr(addr)))?;
+                for _ in 0..steps {
+                    self.history.undo(&mut self.machine);
+                }
+                out.push_str(&format!(
+                    "last write to {} {} steps ago:\n",
+                    self.fmt_addr(addr),
+                    steps,
+                ));
+                self.show_location(out);
+            }
+            ("b", [addr]) | ("break", [addr]) => {
+                let addr = self.parse_value(addr)?;
+                self.breakpoints.insert(addr);
+                out.push_str(&format!("breakpoint at {}\n", self.fmt_addr(addr)));
+            }
+            ("d", [addr]) | ("delete", [addr]) => {
+                let addr = self.parse_value(addr)?;
+                if !self.breakpoints.remove(&addr) {
+                    return Err(format!("no breakpoint at {}", self.fmt_addr(addr)));
+                }
+            }
+            ("w", [addr]) | ("watch", [addr]) => {
+                let addr = self.parse_value(addr)?;
+                self.watchpoints.insert(addr);
+                out.push_str(&format!("watchpoint at {}\n", self.fmt_addr(addr)));
+            }
+            ("unwatch", [addr]) => {
+                let addr = self.parse_value(addr)?;
+                if !self.watchpoints.remove(&addr) {
+                    return Err(format!("no watchpoint at {}", self.fmt_addr(addr)));
+                }
+            }
+            ("i", []) | ("info", []) => self.show_info(out),
+            ("x", _) | ("mem", _) => {
+                let (addr, len) = match args {
+                    [addr] => (self.parse_value(addr)?, 16),
+                    [addr, len] => (self.parse_value(addr)?, parse_count(len)?),
+                    _ => return Err("usage: mem <addr> [len]".into()),
+                };
+                self.show_memory(addr, len, out);
+            }
+            ("set", [target, value]) => {
+                let value = self.parse_value(value)?;
+                self.history.clear();
+                match *target {
+                    "pc" => self.machine.set_pc(value),
+                    "acc" => self.machine.set_acc(value),
+                    "carry" => self.machine.set_carry(value != 0),
+                    "sp" => self.machine.set_sp(value),
+                    addr => {
+                        let addr = self.parse_value(addr)?;
+                        if !self.machine.bus_mut().poke(addr, value) {
+                            let addr = self.fmt_addr(addr);
+                            return Err(format!("can't write to the device at {}", addr));
+                        }
+                    }
+                }
+            }
+            ("l", _) | ("disas", _) => match args {
+                [] => self.show_disassembly_around(self.machine.pc(), out),
+                [addr] => self.show_disassembly(self.parse_value(addr)?, 8, out),
+                [addr, n] => {
+                    let n = parse_count(n)?;
+                    self.show_disassembly(self.parse_value(addr)?, n, out);
+                }
+                _ => return Err("usage: disas [addr] [n]".into()),
+            },
+            ("snapshot", [path]) => {
+                Snapshot::capture(&self.machine).save(path).map_err(|e| e.to_string())?;
+                out.push_str(&format!("saved state to {}\n", path));
+            }
+            ("restore", [path]) => {
+                Snapshot::load(path)
+                    .map_err(|e| e.to_string())?
+                    .restore(&mut self.machine)
+                    .map_err(|e| e.to_string())?;
+                self.history.clear();
+                self.show_location(out);
+            }
+            ("h", _) | ("help", _) => out.push_str(&format!("{}\n", HELP)),
+            ("q", _) | ("quit", _) => return Ok(false),
+            _ => return Err(format!("unknown command '{}', try 'help'", line.trim())),
+        }
+
+        Ok(true)
+    }
+
+    /// Executes a single instruction. Returns an event if the user should be
+    /// notified and execution should not continue.
+    fn single_step(&mut self) -> Option<Event> {
+        let watched = self.watchpoints
+            .iter()
+            .map(|&addr| (addr, self.machine.bus().peek(addr)))
+            .collect::<Vec<_>>();
+
+        match self.machine.step_traced(&mut self.history) {
+            Ok(Status::Running) => {}
+            Ok(Status::Halted) => return Some(Event::Halted),
+            Ok(Status::Trapped(fault)) => return Some(Event::Trapped(fault)),
+            Err(fault) => return Some(Event::Fault(fault)),
+        }
+
+        watched.into_iter()
+            .map(|(addr, old)| (addr, old, self.machine.bus().peek(addr)))
+            .find(|(_, old, new)| old != new)
+            .map(|(addr, old, new)| Event::Watchpoint { addr, old, new })
+    }
+
+    /// Runs until an event occurs or a breakpoint is reached. A breakpoint at
+    /// the current program counter is ignored, so that we don't get stuck.
+    fn cont(&mut self) -> Event {
+        loop {
+            if let Some(event) = self.single_step() {
+                return event;
+            }
+
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return Event::Breakpoint;
+            }
+        }
+    }
+
+    /// Undoes a single instruction. Returns an event if the user should be
+    /// notified and execution should not continue backwards.
+    fn single_step_back(&mut self) -> Option<Event> {
+        let watched = self.watchpoints
+            .iter()
+            .map(|&addr| (addr, self.machine.bus().peek(addr)))
+            .collect::<Vec<_>>();
+
+        if !self.history.undo(&mut self.machine) {
+            return Some(Event::StartOfHistory);
+        }
+
+        watched.into_iter()
+            .map(|(addr, old)| (addr, old, self.machine.bus().peek(addr)))
+            .find(|(_, old, new)| old != new)
+            .map(|(addr, old, new)| Event::Watchpoint { addr, old, new })
+    }
+
+    /// Like `cont`, but runs backwards.
+    fn reverse_cont(&mut self) -> Event {
+        loop {
+            if let Some(event) = self.single_step_back() {
+                return event;
+            }
+
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return Event::Breakpoint;
+            }
+        }
+    }
+
+    /// Prints a message about the given event.
+    fn report(&self, event: Event, out: &mut String) {
+        let msg = match event {
+            Event::Breakpoint => format!("breakpoint at {}", self.fmt_addr(self.machine.pc())),
+            Event::Watchpoint { addr, old, new } => {
+                format!("watchpoint at {}: {:02x} -> {:02x}", self.fmt_addr(addr), old, new)
+            }
+            Event::Halted => "machine halted".into(),
+            Event::Trapped(fault) => format!("trapped: {}", fault),
+            Event::Fault(fault) => format!("fault: {}", fault),
+            Event::StartOfHistory => "reached the start of the recorded history".into(),
+        };
+        out.push_str(&msg);
+        out.push('\n');
+    }
+
+    /// Prints the registers and the instruction at the program counter.
+    fn show_location(&self, out: &mut String) {
+        out.push_str(&format!(
+            "pc: {:02x}  acc: {:02x}  carry: {}  sp: {:02x}\n",
+            self.machine.pc(),
+            self.machine.acc(),
+            self.machine.carry() as u8,
+            self.machine.sp(),
+        ));
+        self.show_instruction(&self.disassemble(self.machine.pc()), out);
+    }
+
+    /// Prints registers, breakpoints and watchpoints.
+    fn show_info(&self, out: &mut String) {
+        out.push_str(&format!("pc: {}\n", self.fmt_addr(self.machine.pc())));
+        out.push_str(&format!("acc: {:02x}\n", self.machine.acc()));
+        out.push_str(&format!("carry: {}\n", self.machine.carry() as u8));
+        out.push_str(&format!("sp: {}\n", self.fmt_addr(self.machine.sp())));
+
+        let breakpoints = self.breakpoints.iter().map(|&a| self.fmt_addr(a)).collect::<Vec<_>>();
+        out.push_str(&format!("breakpoints: {}\n", breakpoints.join(", ")));
+
+        let watchpoints = self.watchpoints
+            .iter()
+            .map(|&a| format!("{} = {:02x}", self.fmt_addr(a), self.machine.bus().peek(a)))
+            .collect::<Vec<_>>();
+        out.push_str(&format!("watchpoints: {}\n", watchpoints.join(", ")));
+    }
+
+    /// Prints `len` bytes of memory starting at `addr`, but not past the end
+    /// of memory.
+    fn show_memory(&self, addr: u8, len: usize, out: &mut String) {
+        let end = (addr as usize + len).min(MACHINE_MEMORY_SIZE);
+        let bytes = (addr as usize..end).map(|a| a as u8).collect::<Vec<_>>();
+        for row in bytes.chunks(16) {
+            let hex = row.iter()
+                .map(|&a| format!("{:02x}", self.machine.bus().peek(a)))
+                .collect::<Vec<_>>();
+            out.push_str(&format!("{:02x}: {}\n", row[0], hex.join(" ")));
+        }
+    }
+
+    /// Prints `count` instructions starting at `addr`.
+    fn show_disassembly(&self, addr: u8, count: usize, out: &mut String) {
+        let mut addr = addr;
+        for _ in 0..count {
+            let instr = self.disassemble(addr);
+            self.show_instruction(&instr, out);
+            addr = addr.wrapping_add(instr.bytes.len() as u8);
+        }
+    }
+
+    /// Prints a few instructions before and after `addr`.
+    ///
+    /// Since instructions have different lengths, we can't just decode
+    /// backwards. Instead we decode from the start of the memory and use the
+    /// last few instructions before `addr`, if `addr` is reached that way.
+    fn show_disassembly_around(&self, addr: u8, out: &mut String) {
+        const BEFORE: usize = 3;
+        const AFTER: usize = 5;
+
+        let mut starts = vec![];
+        let mut pos = 0usize;
+        while pos < addr as usize {
+            starts.push(pos as u8);
+            pos += self.disassemble(pos as u8).bytes.len();
+        }
+
+        let first = if pos == addr as usize {
+            starts.len().saturating_sub(BEFORE)
+        } else {
+            starts.len()
+        };
+        let start = starts.get(first).cloned().unwrap_or(addr);
+        self.show_disassembly(start, starts.len() - first + AFTER, out);
+    }
+
+    /// Prints a single disassembled instruction, marking the program counter
+    /// and breakpoints.
+    fn show_instruction(&self, instr: &Disassembled, out: &mut String) {
+        if let Some(name) = self.symbols.name_at(self.machine.bus(), instr.addr) {
+            out.push_str(&format!("      .{}:\n", name));
+        }
+
+        let marker = match (instr.addr == self.machine.pc(), self.breakpoints.contains(&instr.addr)) {
+            (true, _) => "=>",
+            (false, true) => " *",
+            (false, false) => "  ",
+        };
+        let bytes = instr.bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>();
+        out.push_str(&format!(
+            "{} {:02x}:  {:<9}  {}\n",
+            marker,
+            instr.addr,
+            bytes.join(" "),
+            instr.text,
+        ));
+    }
+
+    /// Disassembles the instruction at `addr`.
+    fn disassemble(&self, addr: u8) -> Disassembled {
+        disasm::disassemble(self.machine.bus(), addr, &self.symbols, self.machine.instruction_set())
+    }
+
+    /// Formats an address together with its label, if there is one.
+    fn fmt_addr(&self, addr: u8) -> String {
+        match self.symbols.name_at(self.machine.bus(), addr) {
+            Some(name) => format!("{:02x} (.{})", addr, name),
+            None => format!("{:02x}", addr),
+        }
+    }
+
+    /// Parses a hex number (`1f`, `$1f` or `0x1f`) or a label (`.start`).
+    fn parse_value(&self, s: &str) -> Result<u8, String> {
+        if let Some(name) = s.strip_prefix('.') {
+            return self.symbols.addr(name).ok_or_else(|| format!("unknown label '{}'", s));
+        }
+
+        let digits = s.trim_start_matches('$').trim_start_matches("0x");
+        u8::from_str_radix(digits, 16).map_err(|_| format!("invalid value '{}'", s))
+    }
+}
+
+/// Parses a decimal count, which has to be positive.
+fn parse_count<T: std::str::FromStr + Default + PartialEq>(s: &str) -> Result<T, String> {
+    match s.parse() {
+        Ok(n) if n != T::default() => Ok(n),
+        _ => Err(format!("invalid count '{}'", s)),
+    }
+}
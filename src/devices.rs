@@ -0,0 +1,181 @@
+//! Peripherals that can be mapped into the address space with `MappedBus`.
+//! All of them occupy a single address.
+
+use std::{cell::Cell, rc::Rc};
+
+use crate::{bus::Device, input::Input, output::Output, snapshot::SnapshotError};
+
+
+/// A serial console. Reading returns the next input byte, or 0 if the input
+/// is used up. Writing prints the byte. Since input can't be looked at
+/// without consuming it, peeking always returns 0.
+pub struct Console {
+    input: Box<dyn Input>,
+    output: Box<dyn Output>,
+}
+
+impl Console {
+    /// Creates a console reading from `input` and printing to `output`.
+    pub fn new(input: impl Input + 'static, output: impl Output + 'static) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+        }
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, _offset: u8) -> u8 {
+        self.input.read().unwrap_or(0)
+    }
+
+    fn write(&mut self, _offset: u8, value: u8) {
+        self.output.print(&[value]);
+    }
+
+    fn peek(&self, _offset: u8) -> u8 {
+        0
+    }
+}
+
+/// Counts executed instructions. Reading returns the count modulo 256,
+/// writing sets it.
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    count: u8,
+}
+
+impl Timer {
+    /// Creates a timer starting at 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, _offset: u8) -> u8 {
+        self.count
+    }
+
+    fn write(&mut self, _offset: u8, value: u8) {
+        self.count = value;
+    }
+
+    fn peek(&self, _offset: u8) -> u8 {
+        self.count
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.push(self.count);
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        match *state {
+            [count] => self.count = count,
+            _ => return Err(SnapshotError::BusMismatch),
+        }
+        Ok(())
+    }
+}
+
+/// A pseudo random number generator (xorshift). Every read returns a new
+/// random byte, writing reseeds the generator.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    /// Creates a generator with the given seed. Generators with the same seed
+    /// return the same bytes.
+    pub fn new(seed: u32) -> Self {
+        // xorshift gets stuck at 0
+        Self { state: if seed == 0 { 0x2545_f491 } else { seed } }
+    }
+
+    /// Returns the state after `state`.
+    fn next(state: u32) -> u32 {
+        let mut x = state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        x
+    }
+}
+
+impl Device for Rng {
+    fn read(&mut self, _offset: u8) -> u8 {
+        self.state = Self::next(self.state);
+        (self.state >> 24) as u8
+    }
+
+    fn write(&mut self, _offset: u8, value: u8) {
+        *self = Self::new(value.into());
+    }
+
+    /// Returns the byte the next read would return.
+    fn peek(&self, _offset: u8) -> u8 {
+        (Self::next(self.state) >> 24) as u8
+    }
+
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.state.to_le_bytes());
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        match *state {
+            [a, b, c, d] => self.state = u32::from_le_bytes([a, b, c, d]),
+            _ => return Err(SnapshotError::BusMismatch),
+        }
+        Ok(())
+    }
+}
+
+/// A port of 8 LEDs, one per bit. Clones share the state, so one clone can be
+/// mapped while another is used to look at the LEDs.
+#[derive(Debug, Clone, Default)]
+pub struct Led {
+    value: Rc<Cell<u8>>,
+}
+
+impl Led {
+    /// Creates a port with all LEDs off.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the last byte written to the port.
+    pub fn value(&self) -> u8 {
+        self.value.get()
+    }
+}
+
+impl Device for Led {
+    fn read(&mut self, _offset: u8) -> u8 {
+        self.value.get()
+    }
+
+    fn write(&mut self, _offset: u8, value: u8) {
+        self.value.set(value);
+    }
+
+    fn peek(&self, _offset: u8) -> u8 {
+        self.value.get()
+    }
+
+    fn save_state(&self, out: &mut Vec<u8>) {
+        out.push(self.value.get());
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), SnapshotError> {
+        match *state {
+            [value] => self.value.set(value),
+            _ => return Err(SnapshotError::BusMismatch),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+//! Turns machine code back into human readable instructions.
+
+use shit_isa::{InstructionSet, OperandKind};
+
+use crate::{bus::Bus, symbols::Symbols};
+
+
+/// A single disassembled instruction.
+#[derive(Debug, Clone)]
+pub struct Disassembled {
+    /// The address of the instruction.
+    pub addr: u8,
+
+    /// The raw bytes of the instruction, including the opcode.
+    pub bytes: Vec<u8>,
+
+    /// The instruction in assembler syntax, like `st [CHAR]`. Illegal opcodes
+    /// are shown as `.byte` directive.
+    pub text: String,
+}
+
+/// Disassembles the instruction at `addr` with the instructions of `isa`.
+/// Labels from `symbols` are used for addresses and jump targets, in the bank
+/// that is currently selected. Memory is only peeked, so this has no side
+/// effects on devices.
+pub fn disassemble<B: Bus + ?Sized>(
+    bus: &B,
+    addr: u8,
+    symbols: &Symbols,
+    isa: &InstructionSet,
+) -> Disassembled {
+    let bytes = [
+        bus.peek(addr),
+        bus.peek(addr.wrapping_add(1)),
+        bus.peek(addr.wrapping_add(2)),
+    ];
+    let (def, args) = match isa.decode(&bytes) {
+        Ok(decoded) => decoded,
+        Err(_) => {
+            return Disassembled {
+                addr,
+                bytes: vec![bytes[0]],
+                text: format!(".byte ${:02x}", bytes[0]),
+            };
+        }
+    };
+
+    let mut text = def.mnemonic().to_owned();
+    for (kind, &v) in def.operands().iter().zip(args) {
+        text.push(' ');
+        text.push_str(&match (kind, symbols.name_at(bus, v)) {
+            (OperandKind::Immediate, _) => format!("${:02x}", v),
+            (OperandKind::Address, Some(name)) => format!("[{}]", name),
+            (OperandKind::Address, None) => format!("[${:02x}]", v),
+            (OperandKind::Target, Some(name)) => format!(".{}", name),
+            (OperandKind::Target, None) => format!("${:02x}", v),
+            (OperandKind::Indirect, Some(name)) => format!("[[{}]]", name),
+            (OperandKind::Indirect, None) => format!("[[${:02x}]]", v),
+        });
+    }
+
+    Disassembled {
+        addr,
+        bytes: bytes[..def.len() as usize].to_vec(),
+        text,
+    }
+}
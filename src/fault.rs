@@ -0,0 +1,69 @@
+//! Defines the faults a program can cause and how the machine reacts to them.
+
+use std::{error::Error, fmt};
+
+
+/// An error caused by the executed program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The byte at `pc` is not a valid opcode.
+    IllegalOpcode { pc: u8, opcode: u8 },
+
+    /// The `print` instruction at `pc` would read past the end of the memory.
+    /// `src` is the address of the string's length byte `len`.
+    PrintOutOfRange { pc: u8, src: u8, len: u8 },
+
+    /// The `push` or `call` at `pc` found the stack full.
+    StackOverflow { pc: u8 },
+
+    /// The `pop` or `ret` at `pc` found the stack empty.
+    StackUnderflow { pc: u8 },
+}
+
+impl Fault {
+    /// Returns the address of the instruction that caused this fault.
+    pub fn pc(&self) -> u8 {
+        match *self {
+            Fault::IllegalOpcode { pc, .. } => pc,
+            Fault::PrintOutOfRange { pc, .. } => pc,
+            Fault::StackOverflow { pc } => pc,
+            Fault::StackUnderflow { pc } => pc,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::IllegalOpcode { pc, opcode } => {
+                write!(f, "illegal opcode {:02x} at {:02x}", opcode, pc)
+            }
+            Fault::PrintOutOfRange { pc, src, len } => write!(
+                f,
+                "print at {:02x} reads {} bytes after {:02x}, past the end of memory",
+                pc,
+                len,
+                src,
+            ),
+            Fault::StackOverflow { pc } => write!(f, "stack overflow at {:02x}", pc),
+            Fault::StackUnderflow { pc } => write!(f, "stack underflow at {:02x}", pc),
+        }
+    }
+}
+
+impl Error for Fault {}
+
+/// Decides what the machine does when a fault occurs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FaultPolicy {
+    /// Stop executing and return the fault as error. This is the default.
+    #[default]
+    Halt,
+
+    /// Return `Status::Trapped` without changing the state of the machine, so
+    /// that a debugger can inspect and fix the state before continuing.
+    Trap,
+
+    /// Skip the faulting instruction as if it was a `nop`.
+    Nop,
+}
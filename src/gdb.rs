@@ -0,0 +1,399 @@
+//! A stub for the GDB remote serial protocol, so that programs can be debugged
+//! with gdb or other frontends speaking the protocol.
+//!
+//! The machine has four 8 bit registers, `pc` (number 0), `acc` (number 1),
+//! `flags` (number 2, bit 0 is the carry flag) and `sp` (number 3), which are
+//! described in the target description `target.xml`. Supported
+//! packets are register and memory reads and writes, single step, continue and
+//! software breakpoints (`Z0`/`z0`). Continuing can be interrupted with
+//! Ctrl-C. When the program executes `stop`, the stub reports that the process
+//! exited.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+};
+
+use crate::{
+    bus::Bus,
+    fault::Fault,
+    machine::{Machine, Status},
+    memory::Memory,
+};
+
+
+/// The target description sent to the client.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.shit.cpu">
+    <reg name="pc" bitsize="8" type="code_ptr" regnum="0"/>
+    <reg name="acc" bitsize="8" type="uint8" regnum="1"/>
+    <reg name="flags" bitsize="8" type="uint8" regnum="2"/>
+    <reg name="sp" bitsize="8" type="data_ptr" regnum="3"/>
+  </feature>
+</target>
+"#;
+
+/// How many instructions are executed between checks for an interrupt while
+/// continuing.
+const INTERRUPT_CHECK_INTERVAL: usize = 1024;
+
+/// What to do after handling a packet.
+enum Action {
+    /// Send the reply and wait for the next packet.
+    Reply(String),
+
+    /// Send the reply and end the session.
+    ReplyAndClose(String),
+
+    /// End the session without a reply.
+    Close,
+}
+
+/// Why execution stopped after `s` or `c`.
+enum Stop {
+    /// A single step finished or the user interrupted.
+    Trap,
+
+    /// A breakpoint was reached.
+    Breakpoint,
+
+    /// The machine executed `stop`.
+    Halted,
+
+    /// The machine faulted or trapped.
+    Fault(Fault),
+}
+
+/// A GDB stub serving a single machine.
+pub struct GdbStub<B: Bus = Memory> {
+    machine: Machine<B>,
+    breakpoints: BTreeSet<u8>,
+}
+
+impl<B: Bus> GdbStub<B> {
+    /// Creates a stub for the given machine.
+    pub fn new(machine: Machine<B>) -> Self {
+        Self {
+            machine,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the debugged machine.
+    pub fn machine(&self) -> &Machine<B> {
+        &self.machine
+    }
+
+    /// Serves the client connected with `stream` until it detaches or
+    /// disconnects.
+    pub fn session(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut conn = Connection::new(stream)?;
+        while let Some(packet) = conn.read_packet()? {
+            match self.handle(&packet, &mut conn)? {
+                Action::Reply(reply) => conn.send(&reply)?,
+                Action::ReplyAndClose(reply) => {
+                    conn.send(&reply)?;
+                    break;
+                }
+                Action::Close => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a single packet.
+    fn handle(&mut self, packet: &str, conn: &mut Connection) -> io::Result<Action> {
+        let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match cmd {
+            "?" => "S05".to_owned(),
+            "g" => format!(
+                "{:02x}{:02x}{:02x}{:02x}",
+                self.machine.pc(),
+                self.machine.acc(),
+                self.flags(),
+                self.machine.sp(),
+            ),
+            "G" => match parse_hex_bytes(args).as_deref() {
+                Some(&[pc, acc, flags, sp]) => {
+                    self.machine.set_pc(pc);
+                    self.machine.set_acc(acc);
+                    self.machine.set_carry(flags & 0x01 != 0);
+                    self.machine.set_sp(sp);
+                    "OK".to_owned()
+                }
+                _ => "E01".to_owned(),
+            },
+            "p" => match parse_hex(args) {
+                Some(0) => format!("{:02x}", self.machine.pc()),
+                Some(1) => format!("{:02x}", self.machine.acc()),
+                Some(2) => format!("{:02x}", self.flags()),
+                Some(3) => format!("{:02x}", self.machine.sp()),
+                _ => "E01".to_owned(),
+            },
+            "P" => match args.split_once('=').map(|(r, v)| (parse_hex(r), parse_hex_bytes(v))) {
+                Some((Some(0), Some(v))) if v.len() == 1 => {
+                    self.machine.set_pc(v[0]);
+                    "OK".to_owned()
+                }
+                Some((Some(1), Some(v))) if v.len() == 1 => {
+                    self.machine.set_acc(v[0]);
+                    "OK".to_owned()
+                }
+                Some((Some(2), Some(v))) if v.len() == 1 => {
+                    self.machine.set_carry(v[0] & 0x01 != 0);
+                    "OK".to_owned()
+                }
+                Some((Some(3), Some(v))) if v.len() == 1 => {
+                    self.machine.set_sp(v[0]);
+                    "OK".to_owned()
+                }
+                _ => "E01".to_owned(),
+            },
+            "m" => match parse_range(args) {
+                Some((addr, len)) => (addr..addr + len)
+                    .map(|a| format!("{:02x}", self.machine.bus().peek(a as u8)))
+                    .collect(),
+                None => "E01".to_owned(),
+            },
+            "M" => {
+                let parsed = args.split_once(':')
+                    .and_then(|(range, data)| Some((parse_range(range)?, parse_hex_bytes(data)?)));
+                match parsed {
+                    Some(((addr, len), data)) if data.len() == len => {
+                        // Device registers can't be written without side effects.
+                        // Nothing is written if any of the bytes is one.
+                        let bus = self.machine.bus();
+                        if (addr..addr + len).all(|a| bus.can_poke(a as u8)) {
+                            for (i, v) in data.into_iter().enumerate() {
+                                self.machine.bus_mut().poke((addr + i) as u8, v);
+                            }
+                            "OK".to_owned()
+                        } else {
+                            "E0e".to_owned()
+                        }
+                    }
+                    _ => "E01".to_owned(),
+                }
+            }
+            "s" | "c" => {
+                if !args.is_empty() {
+                    match parse_hex(args) {
+                        Some(pc) if pc <= u8::MAX as usize => self.machine.set_pc(pc as u8),
+                        _ => return Ok(Action::Reply("E01".to_owned())),
+                    }
+                }
+
+                let stop = if cmd == "s" { self.step() } else { self.cont(conn)? };
+                match stop {
+                    Stop::Trap => "S05".to_owned(),
+                    Stop::Breakpoint => "T05swbreak:;".to_owned(),
+                    Stop::Halted => "W00".to_owned(),
+                    Stop::Fault(Fault::IllegalOpcode { .. }) => "S04".to_owned(),
+                    Stop::Fault(Fault::PrintOutOfRange { .. })
+                    | Stop::Fault(Fault::StackOverflow { .. })
+                    | Stop::Fault(Fault::StackUnderflow { .. }) => "S0b".to_owned(),
+                }
+            }
+            "Z" | "z" => match args.split(',').collect::<Vec<_>>()[..] {
+                ["0", addr, _kind] => match parse_hex(addr) {
+                    Some(addr) if addr <= u8::MAX as usize => {
+                        if cmd == "Z" {
+                            self.breakpoints.insert(addr as u8);
+                        } else {
+                            self.breakpoints.remove(&(addr as u8));
+                        }
+                        "OK".to_owned()
+                    }
+                    _ => "E01".to_owned(),
+                },
+                // Other breakpoint and watchpoint types are not supported
+                _ => String::new(),
+            },
+            "H" => "OK".to_owned(),
+            "D" => return Ok(Action::ReplyAndClose("OK".to_owned())),
+            "k" => return Ok(Action::Close),
+            _ => self.query(packet),
+        };
+
+        Ok(Action::Reply(reply))
+    }
+
+    /// Answers general queries. Unknown packets get an empty reply, which
+    /// tells the client that they are not supported.
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;swbreak+".to_owned();
+        }
+        if packet == "qAttached" {
+            return "1".to_owned();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_pair(range) {
+                Some((offset, len)) if offset <= TARGET_XML.len() => {
+                    let rest = &TARGET_XML[offset..];
+                    if rest.len() > len {
+                        format!("m{}", &rest[..len])
+                    } else {
+                        format!("l{}", rest)
+                    }
+                }
+                _ => "E01".to_owned(),
+            };
+        }
+
+        String::new()
+    }
+
+    /// Returns the value of the `flags` register.
+    fn flags(&self) -> u8 {
+        self.machine.carry() as u8
+    }
+
+    /// Executes a single instruction.
+    fn step(&mut self) -> Stop {
+        match self.machine.step() {
+            Ok(Status::Running) => Stop::Trap,
+            Ok(Status::Halted) => Stop::Halted,
+            Ok(Status::Trapped(fault)) | Err(fault) => Stop::Fault(fault),
+        }
+    }
+
+    /// Runs until a breakpoint is reached, the machine stops or the client
+    /// interrupts. A breakpoint at the current program counter is ignored,
+    /// so that we don't get stuck.
+    fn cont(&mut self, conn: &mut Connection) -> io::Result<Stop> {
+        let mut steps = 0usize;
+        loop {
+            steps = steps.wrapping_add(1);
+            match self.step() {
+                Stop::Trap => {}
+                stop => return Ok(stop),
+            }
+
+            if self.breakpoints.contains(&self.machine.pc()) {
+                return Ok(Stop::Breakpoint);
+            }
+            if steps.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && conn.interrupted()? {
+                return Ok(Stop::Trap);
+            }
+        }
+    }
+}
+
+
+/// The connection to a client, which handles framing of packets.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        // Packets are tiny and every one is answered, so don't let them wait
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    /// Reads the next packet, skipping acknowledgements and interrupts
+    /// outside of packets. Returns `None` if the client disconnected.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] != b'$' {
+                continue;
+            }
+
+            let mut data = vec![];
+            if self.reader.read_until(b'#', &mut data)? == 0 || data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+            if expected != Some(checksum_of(&data)) {
+                self.writer.write_all(b"-")?;
+                continue;
+            }
+
+            self.writer.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    /// Sends a packet with the given data.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.writer.write_all(packet.as_bytes())?;
+        self.writer.flush()
+    }
+
+    /// Checks without blocking whether the client sent an interrupt (0x03).
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.writer.set_nonblocking(true)?;
+        let result = match self.reader.fill_buf() {
+            Ok(buf) => Ok(buf.first() == Some(&0x03)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.writer.set_nonblocking(false)?;
+
+        let interrupted = result?;
+        if interrupted {
+            self.reader.consume(1);
+        }
+        Ok(interrupted)
+    }
+}
+
+/// The checksum of a packet: the sum of all bytes modulo 256.
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Parses a hex number.
+fn parse_hex(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+/// Parses pairs of hex digits into bytes.
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parses two hex numbers separated by a comma.
+fn parse_pair(s: &str) -> Option<(usize, usize)> {
+    let (a, b) = s.split_once(',')?;
+    Some((parse_hex(a)?, parse_hex(b)?))
+}
+
+/// Parses `addr,len` as used by memory packets. The range has to be inside
+/// of the memory.
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = parse_pair(s)?;
+    if addr.checked_add(len)? > 256 {
+        return None;
+    }
+
+    Some((addr, len))
+}
@@ -0,0 +1,127 @@
+//! Records executed instructions so that they can be undone.
+
+use std::collections::VecDeque;
+
+use crate::{
+    bus::Bus,
+    fault::Fault,
+    machine::{Machine, Status},
+    trace::Tracer,
+};
+
+
+/// Everything needed to undo a single step.
+#[derive(Debug, Clone)]
+struct Step {
+    pc: u8,
+    acc: u8,
+    carry: bool,
+    sp: u8,
+
+    /// The addresses written by the instruction and the bytes they contained
+    /// before, in the order of the writes.
+    writes: Vec<(u8, u8)>,
+}
+
+/// An undo log of executed instructions. Pass it as tracer to
+/// `Machine::step_traced` or `Machine::run_traced` to record steps.
+///
+/// Only the registers and memory writes are undone. Side effects of devices,
+/// like consumed input or printed strings, are not. Old values are restored
+/// with `Bus::poke`, so writes to devices aren't undone either.
+#[derive(Debug, Clone)]
+pub struct History {
+    steps: VecDeque<Step>,
+    limit: usize,
+}
+
+impl History {
+    /// Creates an empty history that remembers at most `limit` steps. Older
+    /// steps are forgotten.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            steps: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Returns the number of steps that can be undone.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Returns `true` if there is nothing to undo.
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Forgets all recorded steps, for example because the machine was
+    /// changed from outside.
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    /// Undoes the last recorded step. Returns `false` if there is nothing to
+    /// undo.
+    pub fn undo<B: Bus>(&mut self, machine: &mut Machine<B>) -> bool {
+        let step = match self.steps.pop_back() {
+            Some(step) => step,
+            None => return false,
+        };
+
+        for &(addr, old) in step.writes.iter().rev() {
+            machine.bus_mut().poke(addr, old);
+        }
+        machine.set_pc(step.pc);
+        machine.set_acc(step.acc);
+        machine.set_carry(step.carry);
+        machine.set_sp(step.sp);
+        true
+    }
+
+    /// Returns how many steps have to be undone to get back to right before
+    /// the last write to `addr`, or `None` if no recorded step wrote it.
+    pub fn last_write(&self, addr: u8) -> Option<usize> {
+        self.steps
+            .iter()
+            .rev()
+            .position(|step| step.writes.iter().any(|&(a, _)| a == addr))
+            .map(|i| i + 1)
+    }
+}
+
+impl Default for History {
+    /// Remembers up to 100 000 steps.
+    fn default() -> Self {
+        Self::new(100_000)
+    }
+}
+
+impl Tracer for History {
+    fn before<B: Bus>(&mut self, machine: &Machine<B>) {
+        self.steps.push_back(Step {
+            pc: machine.pc(),
+            acc: machine.acc(),
+            carry: machine.carry(),
+            sp: machine.sp(),
+            writes: vec![],
+        });
+    }
+
+    fn write(&mut self, addr: u8, old: u8, _value: u8) {
+        if let Some(step) = self.steps.back_mut() {
+            step.writes.push((addr, old));
+        }
+    }
+
+    fn after<B: Bus>(&mut self, _machine: &Machine<B>, result: &Result<Status, Fault>) {
+        // Only running steps change the machine, there is nothing to undo for
+        // `stop`, traps and faults.
+        if *result != Ok(Status::Running) {
+            self.steps.pop_back();
+        }
+        if self.steps.len() > self.limit {
+            self.steps.pop_front();
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Input devices which provide the bytes read by the `in` instruction.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, Read},
+};
+
+
+/// Provides the bytes read by the `in` instruction.
+pub trait Input {
+    /// Returns the next byte or `None` if the end of the input was reached.
+    fn read(&mut self) -> Option<u8>;
+}
+
+impl<I: Input + ?Sized> Input for Box<I> {
+    fn read(&mut self) -> Option<u8> {
+        (**self).read()
+    }
+}
+
+/// Reads bytes from anything that implements `io::Read`, like stdin or a
+/// file.
+///
+/// I/O errors are treated as the end of the input.
+pub struct Reader<R: Read> {
+    inner: io::Bytes<BufReader<R>>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Creates an input device reading from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self { inner: BufReader::new(inner).bytes() }
+    }
+}
+
+impl Reader<io::Stdin> {
+    /// Creates an input device reading from stdin.
+    pub fn stdin() -> Self {
+        Self::new(io::stdin())
+    }
+}
+
+impl<R: Read> Input for Reader<R> {
+    fn read(&mut self) -> Option<u8> {
+        self.inner.next().and_then(|b| b.ok())
+    }
+}
+
+/// Provides a fixed list of bytes, for example in tests. This is the default
+/// input of a machine, without any bytes.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    bytes: VecDeque<u8>,
+}
+
+impl Script {
+    /// Creates an input device which provides the given bytes and nothing
+    /// more.
+    pub fn new(bytes: impl Into<Vec<u8>>) -> Self {
+        Self { bytes: bytes.into().into() }
+    }
+
+    /// Returns the bytes that were not read yet.
+    pub fn remaining(&self) -> &VecDeque<u8> {
+        &self.bytes
+    }
+}
+
+impl Input for Script {
+    fn read(&mut self) -> Option<u8> {
+        self.bytes.pop_front()
+    }
+}
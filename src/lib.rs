@@ -0,0 +1,49 @@
+//! Emulator for the SHiT CPU.
+//!
+//! The main type is `Machine`, which holds the whole state of the CPU and can
+//! execute a program step by step. `Debugger` wraps a machine to inspect it
+//! interactively.
+
+mod bus;
+mod coverage;
+mod debugger;
+mod devices;
+mod fault;
+mod gdb;
+mod history;
+mod input;
+mod loops;
+mod machine;
+mod memory;
+mod output;
+mod profile;
+mod snapshot;
+mod source_map;
+mod symbols;
+mod trace;
+
+pub mod disasm;
+
+pub use crate::{
+    bus::{Bus, Device, MappedBus},
+    coverage::Coverage,
+    debugger::Debugger,
+    devices::{Console, Led, Rng, Timer},
+    fault::{Fault, FaultPolicy},
+    gdb::GdbStub,
+    history::History,
+    input::{Input, Reader, Script},
+    loops::{Loop, LoopDetector},
+    machine::{Machine, Status, DEFAULT_STACK_REGION},
+    memory::{
+        ImageTooLarge, Memory, DEFAULT_BANK_SELECT, DEFAULT_BANK_WINDOW, MACHINE_MEMORY_SIZE,
+    },
+    output::{Buffer, Output, Stdout},
+    profile::Profile,
+    snapshot::{Snapshot, SnapshotError},
+    source_map::SourceMap,
+    symbols::Symbols,
+    trace::{TraceFormat, TraceWriter, Tracer},
+};
+
+pub use shit_isa::{Extension, InstructionSet};
@@ -0,0 +1,238 @@
+//! Detects programs that are stuck in an infinite loop.
+//!
+//! The whole state of a machine is finite, so a program that doesn't halt
+//! has to reach a state it was in before at some point. Since the machine is
+//! deterministic, it then repeats the same steps forever. `LoopDetector` finds
+//! such a repeated state with Brent's cycle detection algorithm, which only
+//! needs to remember a single state. States are compared by their hash, so
+//! that no state has to be copied after every step.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use shit_isa::{Instruction, MicroOp};
+
+use crate::{
+    bus::Bus,
+    fault::Fault,
+    machine::{Machine, Status},
+    output::Buffer,
+    snapshot::Snapshot,
+    trace::Tracer,
+};
+
+
+/// An infinite loop found by `LoopDetector`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loop {
+    /// The program counter of the first state that is part of the loop.
+    pub entry: u8,
+
+    /// The number of steps after which the state repeats.
+    pub length: usize,
+}
+
+/// A tracer which detects when the machine reaches a state it was in before.
+///
+/// The state consists of the registers and the memory with all of its banks.
+/// Input and devices are not part of it, so every instruction which executes
+/// `in` or accesses an address the bus reports as not cacheable starts the
+/// detection over. Loops
+/// containing such instructions are never detected. For described
+/// instructions which don't match an instruction of the SHiT CPU, this is the
+/// case as soon as any address is not cacheable.
+#[derive(Debug, Clone, Default)]
+pub struct LoopDetector {
+    /// The state before the first instruction after the last one which
+    /// depended on the outside world. All states since then are determined
+    /// by it. `None` while such an instruction is executed.
+    origin: Option<Snapshot>,
+
+    /// The number of steps executed since `origin`.
+    steps: usize,
+
+    /// The hash of the state Brent's algorithm compares against, and the
+    /// step it was seen at.
+    saved: (u64, usize),
+
+    /// How many steps `saved` is kept before it is replaced.
+    power: usize,
+
+    /// The length of the loop, once it was found.
+    found: Option<usize>,
+}
+
+impl LoopDetector {
+    /// Creates a detector which hasn't seen any state yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the length of the detected loop, if the machine is in one.
+    pub fn found(&self) -> Option<usize> {
+        self.found
+    }
+
+    /// Finds the first state of the detected loop by executing the steps
+    /// since the detection started again. Afterwards, the machine is put back
+    /// into its current state. Nothing the machine prints while doing that is
+    /// output.
+    ///
+    /// Returns `None` if no loop was detected yet, or if executing the steps
+    /// again doesn't lead into the loop. That happens if one of them faults
+    /// or halts, or if two different states had the same hash.
+    pub fn locate<B: Bus>(&self, machine: &mut Machine<B>) -> Option<Loop> {
+        let length = self.found?;
+        let origin = self.origin.as_ref()?;
+        let current = Snapshot::capture(machine);
+        let output = machine.replace_output(Box::new(Buffer::new()));
+
+        let entry = self.replay(machine, origin, length);
+
+        current.restore(machine).expect("restoring own snapshot failed");
+        machine.replace_output(output);
+        entry.map(|entry| Loop { entry, length })
+    }
+
+    /// Returns the program counter of the first state that is the same as the
+    /// state `length` steps later, starting at `origin`.
+    fn replay<B: Bus>(
+        &self,
+        machine: &mut Machine<B>,
+        origin: &Snapshot,
+        length: usize,
+    ) -> Option<u8> {
+        // Advances the machine from `state` by `steps` steps, returning the
+        // new state and its hash.
+        let advance = |machine: &mut Machine<B>, state: &Snapshot, steps| {
+            state.restore(machine).expect("restoring own snapshot failed");
+            for _ in 0..steps {
+                if machine.step() != Ok(Status::Running) {
+                    return None;
+                }
+            }
+            Some((Snapshot::capture(machine), state_hash(machine)))
+        };
+
+        // The state `length` steps ahead of `slow` is the same as `slow` for
+        // the first time at the entry of the loop, which is at most as far
+        // from `origin` as the step the loop was detected at.
+        origin.restore(machine).expect("restoring own snapshot failed");
+        let mut slow = (origin.clone(), state_hash(machine));
+        let mut fast = advance(machine, origin, length)?;
+        for _ in 0..=self.steps {
+            if slow.1 == fast.1 {
+                slow.0.restore(machine).expect("restoring own snapshot failed");
+                return Some(machine.pc());
+            }
+            slow = advance(machine, &slow.0, 1)?;
+            fast = advance(machine, &fast.0, 1)?;
+        }
+
+        None
+    }
+}
+
+impl Tracer for LoopDetector {
+    fn before<B: Bus>(&mut self, machine: &Machine<B>) {
+        // The detection starts over after every instruction which depends on
+        // the outside world, from the state right before the next one.
+        if depends_on_outside(machine) {
+            self.origin = None;
+            self.found = None;
+        } else if self.origin.is_none() {
+            self.origin = Some(Snapshot::capture(machine));
+            self.saved = (state_hash(machine), 0);
+            self.steps = 0;
+            self.power = 1;
+        }
+    }
+
+    fn after<B: Bus>(&mut self, machine: &Machine<B>, result: &Result<Status, Fault>) {
+        // A halted or trapped machine doesn't change, but it isn't looping
+        // either. Replaying the steps since `origin` must not stop early, so
+        // we start over.
+        if result != &Ok(Status::Running) {
+            self.origin = None;
+            self.found = None;
+            return;
+        }
+        if self.origin.is_none() {
+            return;
+        }
+
+        // Once found, the loop doesn't change anymore
+        self.steps += 1;
+        if self.found.is_some() {
+            return;
+        }
+
+        let hash = state_hash(machine);
+        let (saved, saved_at) = &mut self.saved;
+        if *saved == hash {
+            self.found = Some(self.steps - *saved_at);
+        } else if self.steps - *saved_at == self.power {
+            *saved = hash;
+            *saved_at = self.steps;
+            self.power *= 2;
+        }
+    }
+}
+
+/// Returns the hash of the registers and the memory of `machine`.
+fn state_hash<B: Bus>(machine: &Machine<B>) -> u64 {
+    let ram = machine.bus().ram();
+    let mut hasher = DefaultHasher::new();
+    (machine.pc(), machine.acc(), machine.carry(), machine.sp()).hash(&mut hasher);
+    (ram.as_bytes(), ram[ram.bank_select()]).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Returns whether the instruction at the program counter reads input or
+/// accesses addresses that may change without being written.
+fn depends_on_outside<B: Bus>(machine: &Machine<B>) -> bool {
+    let bus = machine.bus();
+    let pc = machine.pc();
+    let bytes = [bus.peek(pc), bus.peek(pc.wrapping_add(1)), bus.peek(pc.wrapping_add(2))];
+
+    let (def, args) = match machine.instruction_set().decode(&bytes) {
+        Ok(decoded) => decoded,
+        Err(_) => return !bus.is_cacheable(pc),
+    };
+
+    // Which addresses other micro-operations access is only known while
+    // executing them, so any device could be involved.
+    let instr = match def.to_instruction(args) {
+        Some(instr) => instr,
+        None => {
+            let reads_input = def.semantics().iter().any(|op| matches!(op, MicroOp::In { .. }));
+            return reads_input || !(0..=255).all(|addr| bus.is_cacheable(addr));
+        }
+    };
+
+    let addresses = match instr {
+        Instruction::In { .. } => return true,
+        Instruction::Ld { src }
+        | Instruction::Add { src }
+        | Instruction::Sub { src }
+        | Instruction::And { src }
+        | Instruction::Or { src }
+        | Instruction::Xor { src } => vec![src],
+        Instruction::St { dst } | Instruction::Sti { dst, .. } => vec![dst],
+        Instruction::Mov { src, dst } => vec![src, dst],
+        Instruction::JmpPtr { ptr } => vec![ptr],
+        Instruction::LdPtr { ptr } | Instruction::StPtr { ptr } => vec![ptr, bus.peek(ptr)],
+        Instruction::Push | Instruction::Call { .. } => vec![machine.sp()],
+        Instruction::Pop | Instruction::Ret => vec![machine.sp().wrapping_add(1)],
+        Instruction::Print { src } => {
+            let end = src.saturating_add(bus.peek(src));
+            (src..=end).collect()
+        }
+        _ => vec![],
+    };
+
+    let fetched = (0..def.len()).map(|i| pc.wrapping_add(i));
+    !fetched.chain(addresses).all(|addr| bus.is_cacheable(addr))
+}
@@ -0,0 +1,824 @@
+//! Defines the `Machine`, the state of the CPU and how instructions are
+//! executed.
+
+use std::{collections::VecDeque, fmt, mem, ops::RangeInclusive};
+
+use shit_isa::{
+    BinaryOp, Condition, Extension, Instruction, InstructionDef, InstructionSet, MicroOp, Register,
+    UnaryOp, Value,
+};
+
+use crate::{
+    bus::Bus,
+    fault::{Fault, FaultPolicy},
+    input::{Input, Script},
+    memory::{Memory, MACHINE_MEMORY_SIZE},
+    output::{Output, Stdout},
+    trace::Tracer,
+};
+
+
+/// The length of the longest instruction.
+const MAX_INSTRUCTION_LEN: u8 = 3;
+
+/// The addresses reserved for the stack, unless configured otherwise with
+/// `Machine::set_stack_region`.
+pub const DEFAULT_STACK_REGION: RangeInclusive<u8> = 0xe0..=0xef;
+
+/// Instructions compiled from their description, indexed by address.
+type DecodeCache = [Option<Compiled>; MACHINE_MEMORY_SIZE];
+
+/// An instruction of the SHiT CPU that does the same as the micro-operations
+/// of a described instruction, see `InstructionDef::to_instruction`.
+#[derive(Debug, Clone, Copy)]
+struct Compiled {
+    instr: Instruction,
+    len: u8,
+    extension: Option<Extension>,
+}
+
+/// The state of the machine after executing a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The machine can execute more instructions.
+    Running,
+
+    /// The machine executed a `stop` instruction. Executing more steps will
+    /// execute the `stop` instruction again.
+    Halted,
+
+    /// The instruction at the program counter caused a fault and the fault
+    /// policy is `FaultPolicy::Trap`. The instruction was not executed.
+    Trapped(Fault),
+}
+
+/// The CPU with its registers and the bus it accesses memory through. By
+/// default, the bus is plain `Memory`.
+pub struct Machine<B: Bus = Memory> {
+    pc: u8,
+    bus: B,
+    acc: u8,
+    carry: bool,
+    sp: u8,
+    stack: RangeInclusive<u8>,
+    extensions: Vec<Extension>,
+    fault_policy: FaultPolicy,
+    output: Box<dyn Output>,
+    input: Box<dyn Input>,
+
+    /// Input read by an instruction which faulted afterwards. It is read
+    /// again before any new input.
+    unread: VecDeque<u8>,
+
+    /// See `set_decode_cache`. `None` if the cache is disabled.
+    cache: Option<Box<DecodeCache>>,
+
+    /// See `set_instruction_set`. `None` for the built-in instruction set.
+    isa: Option<Box<InstructionSet>>,
+}
+
+impl Machine {
+    /// Creates a machine with the given program loaded at address 0. All
+    /// registers start as 0.
+    ///
+    /// Panics if the program is larger than 256 bytes, like
+    /// `Memory::from_program`.
+    pub fn from_program(program: &[u8]) -> Self {
+        Machine::with_bus(Memory::from_program(program))
+    }
+}
+
+impl<B: Bus> Machine<B> {
+    /// Creates a machine which accesses memory through `bus`. All registers
+    /// start as 0.
+    pub fn with_bus(bus: B) -> Self {
+        Machine {
+            pc: 0,
+            acc: 0,
+            carry: false,
+            sp: *DEFAULT_STACK_REGION.end(),
+            stack: DEFAULT_STACK_REGION,
+            extensions: vec![],
+            bus,
+            fault_policy: FaultPolicy::default(),
+            output: Box::new(Stdout::lines()),
+            input: Box::new(Script::default()),
+            unread: VecDeque::new(),
+            cache: Some(Box::new([None; MACHINE_MEMORY_SIZE])),
+            isa: None,
+        }
+    }
+
+    /// Sets the device that receives everything the program prints. By
+    /// default, this is `Stdout::lines()`.
+    pub fn set_output(&mut self, output: impl Output + 'static) {
+        self.output = Box::new(output);
+    }
+
+    /// Sets the device that receives everything the program prints and
+    /// returns the one used before.
+    pub fn replace_output(&mut self, output: Box<dyn Output>) -> Box<dyn Output> {
+        mem::replace(&mut self.output, output)
+    }
+
+    /// Sets the device that provides the bytes read by `in`. By default, the
+    /// machine has no input at all.
+    pub fn set_input(&mut self, input: impl Input + 'static) {
+        self.input = Box::new(input);
+        self.unread.clear();
+    }
+
+    /// Enables or disables the decode cache, which is enabled by default.
+    ///
+    /// Without the cache, every instruction is executed by interpreting the
+    /// micro-operations of its description. With the cache, instructions that
+    /// match an instruction of the SHiT CPU are compiled to it the first time
+    /// they are executed, and executed natively afterwards. Writes by the
+    /// program invalidate the instructions they overlap, so self-modifying
+    /// code works exactly like without the cache. Instructions on addresses
+    /// the bus reports as not cacheable, like devices, are interpreted every
+    /// time.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cache = if enabled {
+            Some(Box::new([None; MACHINE_MEMORY_SIZE]))
+        } else {
+            None
+        };
+    }
+
+    /// Executes the instructions described by `isa` instead of the ones of
+    /// the SHiT CPU.
+    pub fn set_instruction_set(&mut self, isa: InstructionSet) {
+        self.isa = Some(Box::new(isa));
+        if let Some(cache) = &mut self.cache {
+            **cache = [None; MACHINE_MEMORY_SIZE];
+        }
+    }
+
+    /// Returns the instruction set the machine executes.
+    pub fn instruction_set(&self) -> &InstructionSet {
+        self.isa.as_deref().unwrap_or_else(|| InstructionSet::builtin())
+    }
+
+    /// Enables an extension of the instruction set. Without it, the opcodes
+    /// of the extension are illegal.
+    pub fn enable_extension(&mut self, extension: Extension) {
+        if !self.extensions.contains(&extension) {
+            self.extensions.push(extension);
+        }
+    }
+
+    /// Returns whether the given extension is enabled.
+    pub fn has_extension(&self, extension: Extension) -> bool {
+        self.extensions.contains(&extension)
+    }
+
+    /// Reserves `region` for the stack of the stack extension and empties
+    /// the stack. The stack grows downwards from the end of the region.
+    ///
+    /// Panics if the region is empty or contains address 0, where programs
+    /// start.
+    pub fn set_stack_region(&mut self, region: RangeInclusive<u8>) {
+        assert!(
+            !region.is_empty() && *region.start() > 0,
+            "invalid stack region {:02x?}",
+            region,
+        );
+        self.sp = *region.end();
+        self.stack = region;
+    }
+
+    /// Returns the addresses reserved for the stack.
+    pub fn stack_region(&self) -> RangeInclusive<u8> {
+        self.stack.clone()
+    }
+
+    /// Sets what happens when the program causes a fault.
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    /// Returns the program counter.
+    pub fn pc(&self) -> u8 {
+        self.pc
+    }
+
+    /// Sets the program counter.
+    pub fn set_pc(&mut self, pc: u8) {
+        self.pc = pc;
+    }
+
+    /// Returns the accumulator.
+    pub fn acc(&self) -> u8 {
+        self.acc
+    }
+
+    /// Sets the accumulator.
+    pub fn set_acc(&mut self, acc: u8) {
+        self.acc = acc;
+    }
+
+    /// Returns the carry flag. It is set by `add`, `sub`, `shl` and `shr`,
+    /// but only if the carry extension is enabled.
+    pub fn carry(&self) -> bool {
+        self.carry
+    }
+
+    /// Sets the carry flag.
+    pub fn set_carry(&mut self, carry: bool) {
+        self.carry = carry;
+    }
+
+    /// Returns the stack pointer, which is the address the next `push`
+    /// writes to. If the stack is empty, it is the end of the stack region.
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    /// Sets the stack pointer.
+    pub fn set_sp(&mut self, sp: u8) {
+        self.sp = sp;
+    }
+
+    /// Returns the bus of the machine.
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    /// Returns the bus of the machine mutably. Since the memory may be
+    /// changed through it, this clears the decode cache.
+    pub fn bus_mut(&mut self) -> &mut B {
+        if let Some(cache) = &mut self.cache {
+            **cache = [None; MACHINE_MEMORY_SIZE];
+        }
+        &mut self.bus
+    }
+
+    /// Runs the machine until it halts or traps.
+    pub fn run(&mut self) -> Result<Status, Fault> {
+        self.run_traced(&mut ())
+    }
+
+    /// Like `run`, but reports every executed instruction to `tracer`.
+    pub fn run_traced<T: Tracer>(&mut self, tracer: &mut T) -> Result<Status, Fault> {
+        loop {
+            let status = self.step_traced(tracer)?;
+            if status != Status::Running {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Executes at most `steps` instructions. Returns early if the machine
+    /// halts or traps.
+    pub fn run_for(&mut self, steps: usize) -> Result<Status, Fault> {
+        for _ in 0..steps {
+            let status = self.step()?;
+            if status != Status::Running {
+                return Ok(status);
+            }
+        }
+
+        Ok(Status::Running)
+    }
+
+    /// Executes the instruction at the program counter.
+    ///
+    /// If the instruction causes a fault, the fault policy decides whether an
+    /// error is returned, the machine traps or the instruction is skipped.
+    pub fn step(&mut self) -> Result<Status, Fault> {
+        self.step_traced(&mut ())
+    }
+
+    /// Like `step`, but reports the executed instruction to `tracer`.
+    pub fn step_traced<T: Tracer>(&mut self, tracer: &mut T) -> Result<Status, Fault> {
+        tracer.before(self);
+        let result = self.execute(tracer);
+        self.bus.tick();
+        tracer.after(self, &result);
+        result
+    }
+
+    /// Executes the instruction at the program counter and reports all writes
+    /// to memory to `tracer`.
+    fn execute<T: Tracer>(&mut self, tracer: &mut T) -> Result<Status, Fault> {
+        if let Some(compiled) = self.cache.as_ref().and_then(|cache| cache[self.pc as usize]) {
+            if let Some(extension) = compiled.extension {
+                if !self.extensions.contains(&extension) {
+                    let opcode = self.bus.peek(self.pc);
+                    return self.fault(Fault::IllegalOpcode { pc: self.pc, opcode }, 1);
+                }
+            }
+            return self.execute_native(compiled.instr, compiled.len, tracer);
+        }
+
+        let isa = self.isa.take();
+        let result = self.execute_described(
+            isa.as_deref().unwrap_or_else(|| InstructionSet::builtin()),
+            tracer,
+        );
+        self.isa = isa;
+        result
+    }
+
+    /// Executes `instr`, which is `instruction_len` bytes long, natively.
+    fn execute_native<T: Tracer>(
+        &mut self,
+        instr: Instruction,
+        instruction_len: u8,
+        tracer: &mut T,
+    ) -> Result<Status, Fault> {
+        let mut next_pc = self.pc.wrapping_add(instruction_len);
+        match instr {
+
+            // ==========================
+            // ========== 0x0_ ==========
+            // ==========================
+
+            Instruction::Nop => {}
+
+            // ==========================
+            // ========== 0x1_ ==========
+            // ==========================
+
+            Instruction::Ld { src } => self.acc = self.bus.read(src),
+            Instruction::Ldi { v } => self.acc = v,
+            Instruction::St { dst } => self.write(dst, self.acc, tracer),
+            Instruction::Sti { v, dst } => self.write(dst, v, tracer),
+            Instruction::Mov { src, dst } => {
+                let value = self.bus.read(src);
+                self.write(dst, value, tracer);
+            }
+            Instruction::LdPtr { ptr } => {
+                let src = self.bus.read(ptr);
+                self.acc = self.bus.read(src);
+            }
+            Instruction::StPtr { ptr } => {
+                let dst = self.bus.read(ptr);
+                self.write(dst, self.acc, tracer);
+            }
+
+            // ==========================
+            // ========== 0x2_ ==========
+            // ==========================
+
+            Instruction::Jmp { target } => next_pc = target,
+            Instruction::Jz { target } => {
+                if self.acc == 0 {
+                    next_pc = target;
+                }
+            }
+            Instruction::Jc { target } => {
+                if self.carry {
+                    next_pc = target;
+                }
+            }
+            Instruction::Jnc { target } => {
+                if !self.carry {
+                    next_pc = target;
+                }
+            }
+            Instruction::Jnz { target } => {
+                if self.acc != 0 {
+                    next_pc = target;
+                }
+            }
+            Instruction::JmpPtr { ptr } => next_pc = self.bus.read(ptr),
+
+            // ==========================
+            // ========== 0x3_ ==========
+            // ==========================
+
+            Instruction::Add { src } => {
+                let v = self.bus.read(src);
+                self.add(v);
+            }
+            Instruction::Addi { v } => self.add(v),
+            Instruction::Sub { src } => {
+                let v = self.bus.read(src);
+                self.sub(v);
+            }
+            Instruction::Subi { v } => self.sub(v),
+            Instruction::Shr => {
+                self.update_carry(self.acc & 0x01 != 0);
+                self.acc >>= 1;
+            }
+            Instruction::Shl => {
+                self.update_carry(self.acc & 0x80 != 0);
+                self.acc <<= 1;
+            }
+            Instruction::And { src } => self.acc &= self.bus.read(src),
+            Instruction::Andi { v } => self.acc &= v,
+            Instruction::Or { src } => self.acc |= self.bus.read(src),
+            Instruction::Ori { v } => self.acc |= v,
+            Instruction::Xor { src } => self.acc ^= self.bus.read(src),
+            Instruction::Xori { v } => self.acc ^= v,
+            Instruction::Not => self.acc = !self.acc,
+
+            // ==========================
+            // ========== 0x4_ ==========
+            // ==========================
+
+            Instruction::Print { src } => {
+                let len = self.bus.read(src);
+                let start = src as usize + 1;
+                let end = src as usize + len as usize;
+                if end >= MACHINE_MEMORY_SIZE {
+                    let fault = Fault::PrintOutOfRange { pc: self.pc, src, len };
+                    return self.fault(fault, instruction_len);
+                }
+
+                let bytes = (start..=end).map(|addr| self.bus.read(addr as u8)).collect::<Vec<_>>();
+                self.output.print(&bytes);
+            }
+            Instruction::In { eof } => match self.unread.pop_front().or_else(|| self.input.read()) {
+                Some(byte) => self.acc = byte,
+                None => next_pc = eof,
+            },
+
+            // ==========================
+            // ========== 0x5_ ==========
+            // ==========================
+
+            Instruction::Stop => return Ok(Status::Halted),
+
+            // ==========================
+            // ========== 0x6_ ==========
+            // ==========================
+
+            Instruction::Push => {
+                if !self.push(self.acc, tracer) {
+                    return self.fault(Fault::StackOverflow { pc: self.pc }, instruction_len);
+                }
+            }
+            Instruction::Pop => match self.pop() {
+                Some(v) => self.acc = v,
+                None => return self.fault(Fault::StackUnderflow { pc: self.pc }, instruction_len),
+            },
+            Instruction::Call { target } => {
+                if !self.push(next_pc, tracer) {
+                    return self.fault(Fault::StackOverflow { pc: self.pc }, instruction_len);
+                }
+                next_pc = target;
+            }
+            Instruction::Ret => match self.pop() {
+                Some(addr) => next_pc = addr,
+                None => return self.fault(Fault::StackUnderflow { pc: self.pc }, instruction_len),
+            },
+        }
+
+        self.pc = next_pc;
+        Ok(Status::Running)
+    }
+
+    /// Executes the instruction at the program counter by interpreting the
+    /// micro-operations of its description in `isa`. If possible, the
+    /// instruction is compiled into the decode cache.
+    fn execute_described<T: Tracer>(
+        &mut self,
+        isa: &InstructionSet,
+        tracer: &mut T,
+    ) -> Result<Status, Fault> {
+        // Fetching wraps around at the end of the memory, just like the
+        // program counter. We peek, so that the bytes after a short
+        // instruction don't trigger device reads.
+        let pc = self.pc;
+        let mut bytes = [0; MAX_INSTRUCTION_LEN as usize];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.bus.peek(pc.wrapping_add(i as u8));
+        }
+        let (def, args) = match isa.decode(&bytes) {
+            Ok(decoded) => decoded,
+            Err(_) => return self.fault(Fault::IllegalOpcode { pc, opcode: bytes[0] }, 1),
+        };
+        if let Some(extension) = def.extension() {
+            if !self.extensions.contains(&extension) {
+                return self.fault(Fault::IllegalOpcode { pc, opcode: bytes[0] }, 1);
+            }
+        }
+
+        if let Some(cache) = &mut self.cache {
+            let bus = &self.bus;
+            if (0..def.len()).all(|i| bus.is_cacheable(pc.wrapping_add(i))) {
+                cache[pc as usize] = def.to_instruction(args).map(|instr| Compiled {
+                    instr,
+                    len: def.len(),
+                    extension: def.extension(),
+                });
+            }
+        }
+
+        let mut state = MicroState {
+            args,
+            acc: self.acc,
+            carry: self.carry,
+            sp: self.sp,
+            pc: pc.wrapping_add(def.len()),
+            tmp: 0,
+            writes: vec![],
+            prints: vec![],
+            input: vec![],
+        };
+        let halted = match self.run_micro_ops(def, &mut state) {
+            Ok(halted) => halted,
+            Err(fault) => {
+                for byte in state.input.into_iter().rev() {
+                    self.unread.push_front(byte);
+                }
+                return self.fault(fault, def.len());
+            }
+        };
+
+        self.acc = state.acc;
+        self.carry = state.carry;
+        self.sp = state.sp;
+        for (addr, value) in state.writes {
+            self.write(addr, value, tracer);
+        }
+        for bytes in state.prints {
+            self.output.print(&bytes);
+        }
+
+        if halted {
+            return Ok(Status::Halted);
+        }
+        self.pc = state.pc;
+        Ok(Status::Running)
+    }
+
+    /// Executes the micro-operations of `def` on `state`. Returns whether
+    /// the machine halted.
+    fn run_micro_ops(
+        &mut self,
+        def: &InstructionDef,
+        state: &mut MicroState,
+    ) -> Result<bool, Fault> {
+        let pc = self.pc;
+        let has_carry = self.extensions.contains(&Extension::Carry);
+        let bus = &mut self.bus;
+        let (unread, input) = (&mut self.unread, &mut self.input);
+        for op in def.semantics() {
+            match op {
+                MicroOp::Mov { dst, src } => {
+                    let value = state.get(bus, src);
+                    let dst = state.place(bus, dst);
+                    state.store(dst, value);
+                }
+                MicroOp::Binary { op, dst, src } => {
+                    let value = state.get(bus, src);
+                    let dst = state.place(bus, dst);
+                    let old = state.load(bus, dst);
+                    let new = match op {
+                        BinaryOp::Add => {
+                            let (sum, carry) = old.overflowing_add(value);
+                            state.update_carry(carry, has_carry);
+                            sum
+                        }
+                        BinaryOp::Sub => {
+                            let (difference, carry) = old.overflowing_sub(value);
+                            state.update_carry(carry, has_carry);
+                            difference
+                        }
+                        BinaryOp::And => old & value,
+                        BinaryOp::Or => old | value,
+                        BinaryOp::Xor => old ^ value,
+                    };
+                    state.store(dst, new);
+                }
+                MicroOp::Unary { op, dst } => {
+                    let dst = state.place(bus, dst);
+                    let old = state.load(bus, dst);
+                    let new = match op {
+                        UnaryOp::Not => !old,
+                        UnaryOp::Shl => {
+                            state.update_carry(old & 0x80 != 0, has_carry);
+                            old << 1
+                        }
+                        UnaryOp::Shr => {
+                            state.update_carry(old & 0x01 != 0, has_carry);
+                            old >> 1
+                        }
+                    };
+                    state.store(dst, new);
+                }
+                MicroOp::Jump { cond, target } => {
+                    let taken = match cond {
+                        Condition::Always => true,
+                        Condition::Zero => state.acc == 0,
+                        Condition::NotZero => state.acc != 0,
+                        Condition::Carry => state.carry,
+                        Condition::NoCarry => !state.carry,
+                    };
+                    if taken {
+                        state.pc = state.get(bus, target);
+                    }
+                }
+                MicroOp::Push { src } => {
+                    let value = state.get(bus, src);
+                    if !self.stack.contains(&state.sp) {
+                        return Err(Fault::StackOverflow { pc });
+                    }
+                    state.writes.push((state.sp, value));
+                    state.sp -= 1;
+                }
+                MicroOp::Pop { dst } => {
+                    if state.sp >= *self.stack.end() || state.sp < self.stack.start() - 1 {
+                        return Err(Fault::StackUnderflow { pc });
+                    }
+                    state.sp += 1;
+                    let value = state.read(bus, state.sp);
+                    let dst = state.place(bus, dst);
+                    state.store(dst, value);
+                }
+                MicroOp::Print { src } => {
+                    let src = state.get(bus, src);
+                    let len = state.read(bus, src);
+                    let end = src as usize + len as usize;
+                    if end >= MACHINE_MEMORY_SIZE {
+                        return Err(Fault::PrintOutOfRange { pc, src, len });
+                    }
+                    let bytes = (src as usize + 1..=end).map(|addr| state.read(bus, addr as u8));
+                    state.prints.push(bytes.collect());
+                }
+                MicroOp::In { dst, eof } => match unread.pop_front().or_else(|| input.read()) {
+                    Some(byte) => {
+                        state.input.push(byte);
+                        let dst = state.place(bus, dst);
+                        state.store(dst, byte);
+                    }
+                    None => state.pc = state.get(bus, eof),
+                },
+                MicroOp::Halt => return Ok(true),
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Writes `value` to memory at `addr`.
+    fn write<T: Tracer>(&mut self, addr: u8, value: u8, tracer: &mut T) {
+        tracer.write(addr, self.bus.peek(addr), value);
+        self.bus.write(addr, value);
+
+        // Invalidate all instructions that contain `addr`
+        if let Some(cache) = &mut self.cache {
+            for i in 0..MAX_INSTRUCTION_LEN {
+                cache[addr.wrapping_sub(i) as usize] = None;
+            }
+        }
+    }
+
+    /// Adds `v` to the accumulator.
+    fn add(&mut self, v: u8) {
+        let (sum, carry) = self.acc.overflowing_add(v);
+        self.acc = sum;
+        self.update_carry(carry);
+    }
+
+    /// Subtracts `v` from the accumulator.
+    fn sub(&mut self, v: u8) {
+        let (difference, carry) = self.acc.overflowing_sub(v);
+        self.acc = difference;
+        self.update_carry(carry);
+    }
+
+    /// Sets the carry flag, unless the carry extension is disabled. Without
+    /// the extension, the flag never changes while executing instructions.
+    fn update_carry(&mut self, carry: bool) {
+        if self.has_extension(Extension::Carry) {
+            self.carry = carry;
+        }
+    }
+
+    /// Pushes `value` onto the stack. Returns `false` if the stack is full
+    /// or the stack pointer is outside of the stack region.
+    fn push<T: Tracer>(&mut self, value: u8, tracer: &mut T) -> bool {
+        if !self.stack.contains(&self.sp) {
+            return false;
+        }
+
+        self.write(self.sp, value, tracer);
+        self.sp -= 1;
+        true
+    }
+
+    /// Pops the top of the stack. Returns `None` if the stack is empty or the
+    /// stack pointer is outside of the stack region.
+    fn pop(&mut self) -> Option<u8> {
+        if self.sp >= *self.stack.end() || self.sp < self.stack.start() - 1 {
+            return None;
+        }
+
+        self.sp += 1;
+        Some(self.bus.read(self.sp))
+    }
+
+    /// Handles the fault according to the fault policy. `instruction_len` is
+    /// the number of bytes to skip if the faulting instruction is treated as
+    /// `nop`.
+    fn fault(&mut self, fault: Fault, instruction_len: u8) -> Result<Status, Fault> {
+        match self.fault_policy {
+            FaultPolicy::Halt => Err(fault),
+            FaultPolicy::Trap => Ok(Status::Trapped(fault)),
+            FaultPolicy::Nop => {
+                self.pc = self.pc.wrapping_add(instruction_len);
+                Ok(Status::Running)
+            }
+        }
+    }
+}
+
+/// The state of an instruction executed by micro-operations. Writes and
+/// prints are only collected, so that the machine is unchanged if the
+/// instruction faults. Input can't be collected like that, so the bytes read
+/// are remembered to be read again after a fault.
+struct MicroState<'a> {
+    args: &'a [u8],
+    acc: u8,
+    carry: bool,
+    sp: u8,
+    pc: u8,
+    tmp: u8,
+    writes: Vec<(u8, u8)>,
+    prints: Vec<Vec<u8>>,
+    input: Vec<u8>,
+}
+
+/// A value a micro-operation writes to, with the address already resolved.
+#[derive(Clone, Copy)]
+enum Place {
+    Register(Register),
+    Memory(u8),
+}
+
+impl MicroState<'_> {
+    /// Reads the byte at `addr`, including the writes of this instruction.
+    fn read<B: Bus>(&self, bus: &mut B, addr: u8) -> u8 {
+        match self.writes.iter().rev().find(|&&(a, _)| a == addr) {
+            Some(&(_, value)) => value,
+            None => bus.read(addr),
+        }
+    }
+
+    fn get<B: Bus>(&self, bus: &mut B, value: &Value) -> u8 {
+        match value {
+            Value::Register(reg) => self.load(bus, Place::Register(*reg)),
+            Value::Arg(idx) => self.args[*idx],
+            Value::Const(v) => *v,
+            Value::Memory(addr) => {
+                let addr = self.get(bus, addr);
+                self.read(bus, addr)
+            }
+        }
+    }
+
+    fn place<B: Bus>(&self, bus: &mut B, value: &Value) -> Place {
+        match value {
+            Value::Register(reg) => Place::Register(*reg),
+            Value::Memory(addr) => Place::Memory(self.get(bus, addr)),
+            Value::Arg(_) | Value::Const(_) => unreachable!("checked when parsing"),
+        }
+    }
+
+    fn load<B: Bus>(&self, bus: &mut B, place: Place) -> u8 {
+        match place {
+            Place::Register(Register::Acc) => self.acc,
+            Place::Register(Register::Sp) => self.sp,
+            Place::Register(Register::Pc) => self.pc,
+            Place::Register(Register::Tmp) => self.tmp,
+            Place::Memory(addr) => self.read(bus, addr),
+        }
+    }
+
+    /// Like `Machine::update_carry`, `enabled` tells whether the carry
+    /// extension is enabled.
+    fn update_carry(&mut self, carry: bool, enabled: bool) {
+        if enabled {
+            self.carry = carry;
+        }
+    }
+
+    fn store(&mut self, place: Place, value: u8) {
+        match place {
+            Place::Register(Register::Acc) => self.acc = value,
+            Place::Register(Register::Sp) => self.sp = value,
+            Place::Register(Register::Pc) => self.pc = value,
+            Place::Register(Register::Tmp) => self.tmp = value,
+            Place::Memory(addr) => self.writes.push((addr, value)),
+        }
+    }
+}
+
+impl<B: Bus + fmt::Debug> fmt::Debug for Machine<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Machine")
+            .field("pc", &self.pc)
+            .field("bus", &self.bus)
+            .field("acc", &self.acc)
+            .field("carry", &self.carry)
+            .field("sp", &self.sp)
+            .field("extensions", &self.extensions)
+            .field("fault_policy", &self.fault_policy)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,254 +1,454 @@
 use std::env;
-use std::fs;
 use std::fmt;
-use std::io;
-use std::ops;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::SystemTime;
 
-const MACHINE_MEMORY_SIZE: usize = 256;
+use shit_cpu_emu::{
+    Bus, Console, Coverage, Debugger, Extension, Fault, FaultPolicy, GdbStub, Input, InstructionSet,
+    Led, LoopDetector, Machine, MappedBus, Memory, Output, Profile, Reader, Rng, Script, Snapshot,
+    SourceMap, Status, Stdout, Symbols, Timer, TraceFormat, TraceWriter, DEFAULT_BANK_SELECT,
+    DEFAULT_BANK_WINDOW,
+};
 
-struct Memory ([u8; MACHINE_MEMORY_SIZE]);
 
-#[derive(Debug)]
-struct Machine {
-    pc: u8,
-    memory: Memory,
-    acc: u8,
-}
-
-impl Memory {
-    fn from_program(program: &Vec<u8>) -> Self {
-        assert!(program.len() <= MACHINE_MEMORY_SIZE);
+fn main() -> Result<(), io::Error> {
 
-        let mut out = [0; MACHINE_MEMORY_SIZE];
-        out[..program.len()].copy_from_slice(program);
-        Memory(out)
-    }
-}
+    // get program file name and options from command line args
+    let args = if let Some(args) = Args::from_env() {
+        args
+    } else {
+        println!("No program found to emulte!");
+        println!();
+        println!("Usage:");
+        println!("  shit-cpu-emu <program> [--on-fault halt|trap|nop] [--debug]");
+        println!("               [--trace] [--trace-format text|json] [--raw-output]");
+        println!("               [--input <file>] [--mmio]");
+        println!("               [--load-state <file>] [--save-state <file>]");
+        println!("               [--gdb <host:port>] [--profile] [--coverage <file>]");
+        println!("               [--max-steps <n>] [--detect-loops]");
+        println!("               [--extension carry|indirect|stack]...");
+        println!("               [--stack-region <start>-<end>] [--isa <file>]");
+        println!("               [--banks <n>] [--bank-window <start>-<end>]");
+        println!("               [--bank-select <addr>]");
+        println!();
+        println!("The program reads its input from stdin, unless `--input` is given.");
+        println!("With `--mmio`, a console is mapped to $fc, a timer to $fd, a random");
+        println!("number generator to $fe and an LED port to $ff. The console gets the");
+        println!("input instead of the `in` instruction.");
+        println!("`--load-state` resumes a snapshot of a machine running <program>.");
+        println!("`--save-state` writes a snapshot when the program stops.");
+        println!("`--gdb` waits for a GDB remote protocol client on <host:port>.");
+        println!("`--profile` prints how often each instruction was executed.");
+        println!("`--coverage` adds the coverage of this run to <file>.");
+        println!("`--max-steps` stops the program after <n> instructions. With");
+        println!("`--detect-loops`, programs stuck in an infinite loop are stopped as soon");
+        println!("as the loop is detected.");
+        println!("`--extension carry` enables the carry flag jumps `jc`, `jnc` and `jnz`.");
+        println!("`--extension indirect` enables `ld`, `st` and `jmp` through pointers.");
+        println!("`--extension stack` enables `push`, `pop`, `call` and `ret`. The stack");
+        println!("uses the addresses $e0 to $ef, unless `--stack-region` gives other ones,");
+        println!("like `--stack-region c0-ff`.");
+        println!("`--isa` executes the instructions described in <file> instead of the");
+        println!("ones of the SHiT CPU.");
+        println!("`--banks` gives the machine <n> banks of 256 bytes for programs larger");
+        println!("than 256 bytes. Writing a number to the bank select register at $fb");
+        println!("makes that bank visible at the addresses $80 to $df. `--bank-window`");
+        println!("and `--bank-select` move them, like `--bank-window 40-7f`. With `--mmio`,");
+        println!("the select register can't be at the devices from $fc to $ff.");
+        println!("The trace is written to stderr. `--trace`, `--max-steps`,");
+        println!("`--detect-loops`, `--profile` and `--coverage` can't be combined with");
+        println!("`--debug` or `--gdb`.");
+        println!("With `--raw-output`, printed strings are written to stdout unchanged,");
+        println!("without a newline after each one.");
+        std::process::exit(1);
+    };
+    println!("Program name: {}", args.program);
 
-impl ops::Index<u8> for Memory {
-    type Output = u8;
+    let program = fs::read(&args.program)?;
+    println!("Raw program: {:02x?}", program);
 
-    fn index(&self, index: u8) -> &Self::Output {
-        &self.0[index as usize]
-    }
-}
+    let mut memory = Memory::from_image(&program, args.banks).map_err(|e| {
+        let msg = format!("{} (use `--banks` to add banks of 256 bytes)", e);
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    })?;
+    memory.set_bank_window(args.bank_window.clone(), args.bank_select);
 
-impl ops::IndexMut<u8> for Memory {
-    fn index_mut(&mut self, index: u8) -> &mut Self::Output {
-        &mut self.0[index as usize]
-    }
-}
+    // Use the symbol file written by the assembler, if there is one
+    let symbols_path = Path::new(&args.program).with_extension("sym");
+    let symbols = if symbols_path.exists() {
+        Symbols::load(symbols_path)?
+    } else {
+        Symbols::new()
+    };
 
-impl ops::Index<ops::RangeInclusive<u8>> for Memory {
-    type Output = [u8];
+    let input: Box<dyn Input> = match &args.input {
+        Some(path) => Box::new(Reader::new(File::open(path)?)),
+        None => Box::new(Reader::stdin()),
+    };
+    let output: Box<dyn Output> = if args.raw_output {
+        Box::new(Stdout::raw())
+    } else {
+        Box::new(Stdout::lines())
+    };
 
-    fn index(&self, index: ops::RangeInclusive<u8>) -> &Self::Output {
-        let (start, end) = index.into_inner();
-        &self.0[start as usize..=end as usize]
+    if !args.mmio {
+        let mut machine = Machine::with_bus(memory);
+        machine.set_input(input);
+        machine.set_output(output);
+        return run(machine, &args, symbols);
     }
-}
-
-impl fmt::Debug for Memory {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
 
-        // represent memory as hex block
-        let mut out = String::new();
-        for byte in self.0.iter() {
-            out.push_str(&format!("{:02x} ", byte));
-        }
+    let led = Led::new();
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+
+    let mut bus = MappedBus::new(memory);
+    bus.map(0xfc..=0xfc, Console::new(input, Stdout::raw()));
+    bus.map(0xfd..=0xfd, Timer::new());
+    bus.map(0xfe..=0xfe, Rng::new(seed));
+    bus.map(0xff..=0xff, led.clone());
+
+    let mut machine = Machine::with_bus(bus);
+    machine.set_input(Script::default());
+    machine.set_output(output);
+    run(machine, &args, symbols)?;
+    println!();
+    println!("LEDs: {:08b}", led.value());
 
-        out.fmt(f)
-    }
+    Ok(())
 }
 
-impl Machine {
-    fn from_program(program: &Vec<u8>) -> Self {
-        Machine {
-            pc: 0,
-            acc: 0,
-            memory: Memory::from_program(program)
-        }
+/// Runs the machine as configured by the command line arguments.
+fn run<B: Bus + fmt::Debug>(
+    mut machine: Machine<B>,
+    args: &Args,
+    symbols: Symbols,
+) -> Result<(), io::Error> {
+    machine.set_fault_policy(args.fault_policy);
+    if let Some(path) = &args.isa {
+        machine.set_instruction_set(InstructionSet::load(path)?);
     }
+    for &extension in &args.extensions {
+        machine.enable_extension(extension);
+    }
+    if let Some(region) = &args.stack_region {
+        machine.set_stack_region(region.clone());
+    }
+    if let Some(path) = &args.load_state {
+        Snapshot::load(path)?
+            .restore(&mut machine)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        println!("Loaded state from {}", path);
+    }
+    println!("{:#?}", machine);
 
-    fn run(&mut self) {
-
-        loop {
-            let current_op_code = self.memory[self.pc];
-            let instruction_len = match current_op_code {
-
-                // ==========================
-                // ========== 0x0_ ==========
-                // ==========================
-
-                // nop
-                0x00 => 1,
+    if let Some(addr) = &args.gdb {
+        let listener = TcpListener::bind(addr)?;
+        println!("Waiting for gdb on {}", listener.local_addr()?);
+        let (stream, peer) = listener.accept()?;
+        println!("gdb connected from {}", peer);
 
-                // ==========================
-                // ========== 0x1_ ==========
-                // ==========================
+        let mut stub = GdbStub::new(machine);
+        stub.session(stream)?;
+        return save_state(stub.machine(), args);
+    }
 
-                // load
-                0x10 => {
-                    let src = self.memory[self.pc.wrapping_add(1)];
-                    self.acc = self.memory[src];
-                    2
-                }
+    if args.debug {
+        let mut debugger = Debugger::new(machine, symbols);
+        debugger.run()?;
+        return save_state(debugger.machine(), args);
+    }
 
-                // load immediate
-                0x11 => {
-                    self.acc = self.memory[self.pc.wrapping_add(1)];
-                    2
-                }
+    println!("Running program:");
+    let trace = args.trace.map(|format| {
+        TraceWriter::new(BufWriter::new(io::stderr()), format, symbols.clone())
+    });
+    let profile = if args.profile { Some(Profile::new()) } else { None };
+    let coverage = args.coverage.as_ref().map(|_| Coverage::new());
+    let loops = if args.detect_loops { Some(LoopDetector::new()) } else { None };
+    let mut tracers = (loops, (trace, (profile, coverage)));
+    let mut steps = 0;
+    let result = loop {
+        if args.max_steps == Some(steps) {
+            break None;
+        }
 
-                // store
-                0x12 => {
-                    let dst = self.memory[self.pc.wrapping_add(1)];
-                    self.memory[dst] = self.acc;
-                    2
-                }
+        let result = machine.step_traced(&mut tracers);
+        steps += 1;
+        if result != Ok(Status::Running) || tracers.0.as_ref().and_then(|l| l.found()).is_some() {
+            break Some(result);
+        }
+    };
 
-                // store immediate
-                0x13 => {
-                    let val = self.memory[self.pc.wrapping_add(1)];
-                    let dst = self.memory[self.pc.wrapping_add(2)];
-                    self.memory[dst] = val;
-                    3
-                }
+    let (loops, (trace, (profile, coverage))) = tracers;
+    if let Some(trace) = trace {
+        trace.finish()?;
+    }
+    if let Some(profile) = profile {
+        report_profile(&profile, &symbols, args)?;
+    }
+    if let (Some(coverage), Some(path)) = (coverage, &args.coverage) {
+        save_coverage(coverage, path)?;
+    }
+    save_state(&machine, args)?;
 
-                // move
-                0x14 => {
-                    let src = self.memory[self.pc.wrapping_add(1)];
-                    let dst = self.memory[self.pc.wrapping_add(2)];
-                    self.memory[dst] = self.memory[src];
-                    3
+    match result {
+        None => {
+            println!();
+            println!("Stopped after {} steps without reaching `stop`", steps);
+            std::process::exit(3);
+        }
+        Some(Ok(Status::Running)) => {
+            let loops = loops.expect("loop was detected");
+            let length = loops.found().expect("loop was detected");
+
+            // Replaying the loop to find its start may fail, for example if
+            // it reads from a device after all
+            let entry = match loops.locate(&mut machine) {
+                Some(found) => {
+                    let label = symbols.name_at(machine.bus(), found.entry);
+                    let label = label.map(|n| format!(" (.{})", n)).unwrap_or_default();
+                    format!(" at {:02x}{}", found.entry, label)
                 }
+                None => String::new(),
+            };
+            println!();
+            println!(
+                "Infinite loop{}: the program repeats the same {} step(s) forever",
+                entry,
+                length,
+            );
+            std::process::exit(3);
+        }
+        Some(Ok(Status::Trapped(fault))) => {
+            // Let the user inspect the machine in the debugger
+            println!("Trapped: {}", fault);
+            Debugger::new(machine, symbols).run()?;
+        }
+        Some(Err(fault)) => {
+            report_fault(&machine, fault);
+            std::process::exit(2);
+        }
+        Some(Ok(Status::Halted)) => {}
+    }
 
-                // ==========================
-                // ========== 0x2_ ==========
-                // ==========================
-
-                // jump
-                0x20 => {
-                    self.pc = self.memory[self.pc.wrapping_add(1)];
-                    0
-                }
+    Ok(())
+}
 
-                // jump zero
-                0x21 => {
-                    if self.acc == 0 {
-                        self.pc = self.memory[self.pc.wrapping_add(1)];
-                        0
-                    } else {
-                        2
-                    }
-                }
+/// Prints the profile and writes the annotated source, if there is a source
+/// map for the program.
+fn report_profile(profile: &Profile, symbols: &Symbols, args: &Args) -> Result<(), io::Error> {
+    println!();
+    print!("{}", profile.report(symbols));
+
+    let map_path = Path::new(&args.program).with_extension("map");
+    if map_path.exists() {
+        let map = SourceMap::load(map_path)?;
+        let source = fs::read_to_string(map.source())?;
+        let annotated_path = Path::new(&args.program).with_extension("prof.s");
+        fs::write(&annotated_path, profile.annotate(&source, &map))?;
+        println!();
+        println!("Annotated source written to {}", annotated_path.display());
+    }
 
-                // ==========================
-                // ========== 0x3_ ==========
-                // ==========================
+    Ok(())
+}
 
-                // add
-                0x30 => {
-                    let src = self.memory[self.pc.wrapping_add(1)];
-                    self.acc = self.acc.wrapping_add(self.memory[src]);
-                    2
-                }
+/// Adds the coverage data to the file at `path`, so that it contains the
+/// coverage of all runs.
+fn save_coverage(mut coverage: Coverage, path: &str) -> Result<(), io::Error> {
+    if Path::new(path).exists() {
+        coverage.merge(&Coverage::load(path)?);
+    }
 
-                // add immediate
-                0x31 => {
-                    let add = self.memory[self.pc.wrapping_add(1)];
-                    self.acc = self.acc.wrapping_add(add);
-                    2
-                }
+    fs::write(path, coverage.to_data())?;
+    println!("Coverage data written to {}", path);
+    Ok(())
+}
 
-                // subtract
-                0x32 => {
-                    let src = self.memory[self.pc.wrapping_add(1)];
-                    self.acc = self.acc.wrapping_sub(self.memory[src]);
-                    2
-                }
+/// Saves a snapshot of the machine if `--save-state` was given.
+fn save_state<B: Bus>(machine: &Machine<B>, args: &Args) -> Result<(), io::Error> {
+    if let Some(path) = &args.save_state {
+        Snapshot::capture(machine).save(path)?;
+        println!("Saved state to {}", path);
+    }
 
-                // substract immediate
-                0x33 => {
-                    let sub = self.memory[self.pc.wrapping_add(1)];
-                    self.acc = self.acc.wrapping_sub(sub);
-                    2
-                }
+    Ok(())
+}
 
-                // shift right
-                0x34 => {
-                    self.acc = self.acc >> 1;
-                    1
-                }
+/// Prints the fault together with the state of the machine.
+fn report_fault<B: Bus>(machine: &Machine<B>, fault: Fault) {
+    println!();
+    println!("Fault: {}", fault);
+    println!(
+        "pc: {:02x}  acc: {:02x}  carry: {}  sp: {:02x}",
+        fault.pc(),
+        machine.acc(),
+        machine.carry() as u8,
+        machine.sp(),
+    );
+    println!();
+    print!("{}", machine.bus().ram().hex_dump());
+}
 
-                // shift left
-                0x35 => {
-                    self.acc = self.acc << 1;
-                    1
-                }
+/// The command line arguments.
+struct Args {
+    program: String,
+    fault_policy: FaultPolicy,
+    debug: bool,
+    trace: Option<TraceFormat>,
+    raw_output: bool,
+    input: Option<String>,
+    mmio: bool,
+    load_state: Option<String>,
+    save_state: Option<String>,
+    gdb: Option<String>,
+    profile: bool,
+    coverage: Option<String>,
+    max_steps: Option<u64>,
+    detect_loops: bool,
+    extensions: Vec<Extension>,
+    stack_region: Option<RangeInclusive<u8>>,
+    isa: Option<String>,
+    banks: usize,
+    bank_window: RangeInclusive<u8>,
+    bank_select: u8,
+}
 
-                // and
-                0x36 => {
-                    let src = self.memory[self.pc.wrapping_add(1)];
-                    self.acc = self.acc & self.memory[src];
-                    2
+impl Args {
+    /// Parses the arguments passed to this program. Returns `None` if they are
+    /// invalid.
+    fn from_env() -> Option<Self> {
+        let mut program = None;
+        let mut fault_policy = FaultPolicy::default();
+        let mut debug = false;
+        let mut trace = false;
+        let mut trace_format = TraceFormat::Text;
+        let mut raw_output = false;
+        let mut input = None;
+        let mut mmio = false;
+        let mut load_state = None;
+        let mut save_state = None;
+        let mut gdb = None;
+        let mut profile = false;
+        let mut coverage = None;
+        let mut max_steps = None;
+        let mut detect_loops = false;
+        let mut extensions = vec![];
+        let mut stack_region = None;
+        let mut isa = None;
+        let mut banks = 1;
+        let mut bank_window = DEFAULT_BANK_WINDOW;
+        let mut bank_select = DEFAULT_BANK_SELECT;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--on-fault" => {
+                    fault_policy = match args.next()?.as_str() {
+                        "halt" => FaultPolicy::Halt,
+                        "trap" => FaultPolicy::Trap,
+                        "nop" => FaultPolicy::Nop,
+                        _ => return None,
+                    };
                 }
-
-                // and immediate
-                0x37 => {
-                    self.acc = self.acc & self.memory[self.pc.wrapping_add(1)];
-                    2
+                "--debug" => debug = true,
+                "--trace" => trace = true,
+                "--raw-output" => raw_output = true,
+                "--input" => input = Some(args.next()?),
+                "--mmio" => mmio = true,
+                "--load-state" => load_state = Some(args.next()?),
+                "--save-state" => save_state = Some(args.next()?),
+                "--gdb" => gdb = Some(args.next()?),
+                "--profile" => profile = true,
+                "--coverage" => coverage = Some(args.next()?),
+                "--max-steps" => max_steps = Some(args.next()?.parse().ok()?),
+                "--detect-loops" => detect_loops = true,
+                "--extension" => extensions.push(match args.next()?.as_str() {
+                    "carry" => Extension::Carry,
+                    "indirect" => Extension::Indirect,
+                    "stack" => Extension::Stack,
+                    _ => return None,
+                }),
+                "--stack-region" => stack_region = Some(parse_region(&args.next()?)?),
+                "--isa" => isa = Some(args.next()?),
+                "--banks" => {
+                    banks = args.next()?.parse().ok()?;
+                    if !(1..=256).contains(&banks) {
+                        return None;
+                    }
                 }
-
-                // ==========================
-                // ========== 0x4_ ==========
-                // ==========================
-
-                // print
-                0x40 => {
-                    let src = self.memory[self.pc.wrapping_add(1)];
-                    let len = self.memory[src];
-                    let start = src.wrapping_add(1);
-                    let end = src.wrapping_add(len);
-                    let chars = &self.memory[start..=end];
-                    println!("{}", String::from_utf8_lossy(chars));
-                    2
+                "--bank-window" => bank_window = parse_region(&args.next()?)?,
+                "--bank-select" => bank_select = u8::from_str_radix(&args.next()?, 16).ok()?,
+                "--trace-format" => {
+                    trace = true;
+                    trace_format = match args.next()?.as_str() {
+                        "text" => TraceFormat::Text,
+                        "json" => TraceFormat::Json,
+                        _ => return None,
+                    };
                 }
+                _ if program.is_none() => program = Some(arg),
+                _ => return None,
+            }
+        }
 
-                // ==========================
-                // ========== 0x5_ ==========
-                // ==========================
-
-                // stop
-                0x50 => return,
+        if bank_window.contains(&bank_select) {
+            return None;
+        }
 
-                opcode => panic!("Unknown instruction {:02x} in position: {:02x}", opcode, self.pc),
-            };
+        // The devices of `--mmio` would hide the select register
+        if mmio && (0xfc..=0xff).contains(&bank_select) {
+            return None;
+        }
 
-            self.pc = self.pc.wrapping_add(instruction_len);
+        // The debugger and the gdb stub step the machine on their own, without
+        // any tracers or limits
+        let tracers =
+            trace || max_steps.is_some() || detect_loops || profile || coverage.is_some();
+        if (debug || gdb.is_some()) && tracers {
+            return None;
         }
+
+        Some(Self {
+            program: program?,
+            fault_policy,
+            debug,
+            trace: if trace { Some(trace_format) } else { None },
+            raw_output,
+            input,
+            mmio,
+            load_state,
+            save_state,
+            gdb,
+            profile,
+            coverage,
+            max_steps,
+            detect_loops,
+            extensions,
+            stack_region,
+            isa,
+            banks,
+            bank_window,
+            bank_select,
+        })
     }
 }
 
-fn main() -> Result<(), io::Error> {
-
-    // get program file name from command line args
-    let prog_name = if let Some(name) = env::args().nth(1) {
-        name
-    } else {
-        println!("No program found to emulte!");
-        std::process::exit(1);
-    };
-    println!("Program name: {}", prog_name);
-
-    let program = fs::read(prog_name)?;
-    println!("Raw program: {:02x?}", program);
-
-    let mut machine = Machine::from_program(&program);
-    println!("{:#?}", machine);
-
-    println!("Running program:");
-    machine.run();
-
-    Ok(())
+/// Parses an address range like `e0-ef`. Neither the stack region nor the
+/// bank window may be empty or contain address 0, where programs start.
+fn parse_region(s: &str) -> Option<RangeInclusive<u8>> {
+    let (start, end) = s.split_once('-')?;
+    let start = u8::from_str_radix(start, 16).ok()?;
+    let end = u8::from_str_radix(end, 16).ok()?;
+    if start == 0 || start > end {
+        return None;
+    }
+    Some(start..=end)
 }
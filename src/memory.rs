@@ -0,0 +1,214 @@
+//! Defines the `Memory` of the machine.
+
+use std::{error::Error, fmt, ops, ops::RangeInclusive};
+
+
+/// The number of addresses the machine can access, which is also the size of
+/// every memory bank.
+pub const MACHINE_MEMORY_SIZE: usize = 256;
+
+/// The addresses the selected bank is visible at, unless configured otherwise
+/// with `Memory::set_bank_window`.
+pub const DEFAULT_BANK_WINDOW: RangeInclusive<u8> = 0x80..=0xdf;
+
+/// The address of the bank select register, unless configured otherwise with
+/// `Memory::set_bank_window`.
+pub const DEFAULT_BANK_SELECT: u8 = 0xfb;
+
+/// The whole memory of the machine. It is indexed by `u8` addresses, so every
+/// address is valid.
+///
+/// The memory consists of one or more banks of 256 bytes. Bank 0 is always
+/// visible, except for the bank window. At the addresses of the window, the
+/// bank chosen by the bank select register is visible instead. Writing a
+/// number to the select register selects that bank, modulo the number of
+/// banks. With a single bank, there is neither a window nor a select
+/// register.
+pub struct Memory {
+    /// All banks after each other.
+    bytes: Vec<u8>,
+    window: RangeInclusive<u8>,
+    select: u8,
+
+    /// The value of the bank select register.
+    bank: u8,
+}
+
+impl Memory {
+    /// Creates a memory with a single bank and the given program at its
+    /// start. All other bytes are zero.
+    ///
+    /// Panics if the program is larger than 256 bytes. Programs read from
+    /// files or other unchecked sources should be loaded with `from_image`
+    /// instead, which returns an error.
+    pub fn from_program(program: &[u8]) -> Self {
+        Self::from_image(program, 1).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Creates a memory with `banks` banks and the given image at its start.
+    /// The image continues with bank 1 after the first 256 bytes, and so on.
+    /// All other bytes are zero. The bank window is `DEFAULT_BANK_WINDOW`
+    /// and the select register is at `DEFAULT_BANK_SELECT`.
+    ///
+    /// Panics if `banks` is 0 or larger than 256, the number of banks the
+    /// select register can choose from.
+    pub fn from_image(image: &[u8], banks: usize) -> Result<Self, ImageTooLarge> {
+        assert!((1..=256).contains(&banks), "invalid number of banks {}", banks);
+
+        let capacity = banks * MACHINE_MEMORY_SIZE;
+        if image.len() > capacity {
+            return Err(ImageTooLarge { len: image.len(), capacity });
+        }
+
+        let mut bytes = vec![0; capacity];
+        bytes[..image.len()].copy_from_slice(image);
+        Ok(Memory {
+            bytes,
+            window: DEFAULT_BANK_WINDOW,
+            select: DEFAULT_BANK_SELECT,
+            bank: 0,
+        })
+    }
+
+    /// Makes the selected bank visible at the addresses in `window` and moves
+    /// the bank select register to `select`.
+    ///
+    /// Panics if the window is empty or contains the select register.
+    pub fn set_bank_window(&mut self, window: RangeInclusive<u8>, select: u8) {
+        assert!(
+            !window.is_empty() && !window.contains(&select),
+            "invalid bank window {:02x?} with select register {:02x}",
+            window,
+            select,
+        );
+        self.window = window;
+        self.select = select;
+    }
+
+    /// Returns the addresses the selected bank is visible at.
+    pub fn bank_window(&self) -> RangeInclusive<u8> {
+        self.window.clone()
+    }
+
+    /// Returns the address of the bank select register.
+    pub fn bank_select(&self) -> u8 {
+        self.select
+    }
+
+    /// Returns the number of banks.
+    pub fn banks(&self) -> usize {
+        self.bytes.len() / MACHINE_MEMORY_SIZE
+    }
+
+    /// Returns the bank that is visible in the bank window.
+    pub fn bank(&self) -> usize {
+        self.bank as usize % self.banks()
+    }
+
+    /// Returns whether `addr` shows a different byte depending on the
+    /// selected bank.
+    pub fn is_banked(&self, addr: u8) -> bool {
+        self.banks() > 1 && self.window.contains(&addr)
+    }
+
+    /// Returns all bytes of the memory, bank after bank.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the bytes visible at the addresses in `range`. Unlike the
+    /// bytes returned by `as_bytes`, they may come from different banks when
+    /// the range crosses the edge of the bank window.
+    pub fn read_range(&self, range: RangeInclusive<u8>) -> Vec<u8> {
+        range.map(|addr| self[addr]).collect()
+    }
+
+    /// Formats the memory as hex dump with 16 bytes per line, each line
+    /// prefixed with the address of its first byte. With more than one bank,
+    /// every bank gets a header.
+    pub fn hex_dump(&self) -> String {
+        let mut out = String::new();
+        for (bank, bytes) in self.bytes.chunks(MACHINE_MEMORY_SIZE).enumerate() {
+            if self.banks() > 1 {
+                out.push_str(&format!("bank {}:\n", bank));
+            }
+            for (i, row) in bytes.chunks(16).enumerate() {
+                out.push_str(&format!("{:02x}:", i * 16));
+                for byte in row {
+                    out.push_str(&format!(" {:02x}", byte));
+                }
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+
+    /// Returns the index into `bytes` of the byte at `addr`, or `None` for
+    /// the bank select register.
+    fn locate(&self, addr: u8) -> Option<usize> {
+        if self.banks() > 1 && addr == self.select {
+            return None;
+        }
+
+        let bank = if self.is_banked(addr) { self.bank() } else { 0 };
+        Some(bank * MACHINE_MEMORY_SIZE + addr as usize)
+    }
+}
+
+impl ops::Index<u8> for Memory {
+    type Output = u8;
+
+    fn index(&self, index: u8) -> &Self::Output {
+        match self.locate(index) {
+            Some(i) => &self.bytes[i],
+            None => &self.bank,
+        }
+    }
+}
+
+impl ops::IndexMut<u8> for Memory {
+    fn index_mut(&mut self, index: u8) -> &mut Self::Output {
+        match self.locate(index) {
+            Some(i) => &mut self.bytes[i],
+            None => &mut self.bank,
+        }
+    }
+}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+
+        // represent memory as hex block
+        let mut out = String::new();
+        for byte in self.bytes.iter() {
+            out.push_str(&format!("{:02x} ", byte));
+        }
+
+        out.fmt(f)
+    }
+}
+
+
+/// An error when an image is larger than the memory it is loaded into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageTooLarge {
+    /// The size of the image in bytes.
+    pub len: usize,
+
+    /// The size of all banks of the memory in bytes.
+    pub capacity: usize,
+}
+
+impl fmt::Display for ImageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "image of {} bytes doesn't fit into {} bytes of memory",
+            self.len,
+            self.capacity,
+        )
+    }
+}
+
+impl Error for ImageTooLarge {}
@@ -0,0 +1,103 @@
+//! Output devices which receive what the program prints.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+};
+
+
+/// Receives the strings printed by the `print` instruction.
+pub trait Output {
+    /// Called for every executed `print` with the bytes of the string.
+    fn print(&mut self, bytes: &[u8]);
+}
+
+impl<O: Output + ?Sized> Output for Box<O> {
+    fn print(&mut self, bytes: &[u8]) {
+        (**self).print(bytes)
+    }
+}
+
+/// Writes printed strings to stdout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stdout {
+    raw: bool,
+}
+
+impl Stdout {
+    /// Prints every string as (lossy) UTF-8 followed by a newline. This is the
+    /// default.
+    pub fn lines() -> Self {
+        Self { raw: false }
+    }
+
+    /// Writes the bytes of every string unchanged, without adding a newline.
+    pub fn raw() -> Self {
+        Self { raw: true }
+    }
+}
+
+impl Output for Stdout {
+    fn print(&mut self, bytes: &[u8]) {
+        if self.raw {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            stdout.write_all(bytes)
+                .and_then(|_| stdout.flush())
+                .expect("failed printing to stdout");
+        } else {
+            println!("{}", String::from_utf8_lossy(bytes));
+        }
+    }
+}
+
+/// Collects all printed strings in memory, for example to check them in
+/// tests.
+///
+/// Clones of a buffer share the same contents, so one clone can be passed to
+/// the machine while another one is used to read the output.
+#[derive(Clone, Default)]
+pub struct Buffer {
+    prints: Rc<RefCell<Vec<Vec<u8>>>>,
+}
+
+impl Buffer {
+    /// Creates an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the bytes of every `print` in the order they were executed.
+    pub fn prints(&self) -> Vec<Vec<u8>> {
+        self.prints.borrow().clone()
+    }
+
+    /// Returns all printed bytes concatenated, without any separators.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.prints.borrow().concat()
+    }
+
+    /// Returns all printed strings as (lossy) UTF-8, each followed by a
+    /// newline. This is what `Stdout::lines` would print.
+    pub fn text(&self) -> String {
+        self.prints
+            .borrow()
+            .iter()
+            .map(|p| format!("{}\n", String::from_utf8_lossy(p)))
+            .collect()
+    }
+}
+
+impl Output for Buffer {
+    fn print(&mut self, bytes: &[u8]) {
+        self.prints.borrow_mut().push(bytes.to_vec());
+    }
+}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Buffer").field("prints", &self.prints.borrow().len()).finish()
+    }
+}
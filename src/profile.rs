@@ -0,0 +1,148 @@
+//! Counts how often every address and opcode is executed.
+
+use std::collections::HashMap;
+
+use shit_isa::Opcode;
+
+use crate::{
+    bus::Bus,
+    fault::Fault,
+    machine::{Machine, Status},
+    source_map::SourceMap,
+    symbols::Symbols,
+    trace::Tracer,
+};
+
+
+/// A tracer which counts executed instructions per address and per opcode.
+///
+/// Instructions that fault are not counted, unless they are skipped with
+/// `FaultPolicy::Nop`.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    hits: [u64; 256],
+
+    /// The count and mnemonic of every executed opcode byte.
+    opcodes: HashMap<u8, (u64, String)>,
+    total: u64,
+
+    // The address and opcode of the instruction currently executed
+    current: Option<(u8, u8)>,
+}
+
+impl Profile {
+    /// Creates a profile without any executed instructions.
+    pub fn new() -> Self {
+        Self {
+            hits: [0; 256],
+            opcodes: HashMap::new(),
+            total: 0,
+            current: None,
+        }
+    }
+
+    /// Returns how often the instruction at `addr` was executed.
+    pub fn hits(&self, addr: u8) -> u64 {
+        self.hits[addr as usize]
+    }
+
+    /// Returns how often instructions with the given opcode were executed.
+    pub fn opcode_hits(&self, opcode: Opcode) -> u64 {
+        self.opcodes.get(&opcode.to_byte()).map_or(0, |&(count, _)| count)
+    }
+
+    /// Returns the number of executed instructions.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Formats the total instruction count, a table of all executed
+    /// addresses sorted by hits and a histogram of opcodes. Labels from
+    /// `symbols` are shown next to the addresses.
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let percent = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+
+        let mut out = format!("Executed {} instructions\n\n", self.total);
+        out.push_str("Hot spots:\n");
+        out.push_str("  addr  label            count       %\n");
+        let mut addrs = (0..=255u8).filter(|&a| self.hits(a) > 0).collect::<Vec<_>>();
+        addrs.sort_by_key(|&a| (std::cmp::Reverse(self.hits(a)), a));
+        for addr in addrs {
+            let count = self.hits(addr);
+            let label = symbols.name(addr).map(|n| format!(".{}", n)).unwrap_or_default();
+            out.push_str(&format!(
+                "  {:02x}    {:<12} {:>9}  {:>5.1}%\n",
+                addr,
+                label,
+                count,
+                percent(count),
+            ));
+        }
+
+        out.push_str("\nOpcodes:\n");
+        let mut opcodes = self.opcodes.iter().collect::<Vec<_>>();
+        opcodes.sort_by_key(|&(&byte, &(count, _))| (std::cmp::Reverse(count), byte));
+        for (_, (count, mnemonic)) in opcodes {
+            out.push_str(&format!(
+                "  {:<8} {:>9}  {:>5.1}%\n",
+                mnemonic,
+                count,
+                percent(*count),
+            ));
+        }
+
+        out
+    }
+
+    /// Returns a copy of `source` with the execution count of every line in
+    /// the left margin. `map` has to be the source map of the program.
+    pub fn annotate(&self, source: &str, map: &SourceMap) -> String {
+        let mut counts = HashMap::new();
+        for addr in 0..=255u8 {
+            if let Some(line) = map.line(addr) {
+                *counts.entry(line).or_insert(0) += self.hits(addr);
+            }
+        }
+
+        let mut out = String::new();
+        for (i, line) in source.lines().enumerate() {
+            let count = counts.get(&(i + 1)).map(|c| c.to_string()).unwrap_or_default();
+            let annotated = format!("{:>9} | {}", count, line);
+            out.push_str(annotated.trim_end());
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tracer for Profile {
+    fn before<B: Bus>(&mut self, machine: &Machine<B>) {
+        let pc = machine.pc();
+        self.current = Some((pc, machine.bus().peek(pc)));
+    }
+
+    fn after<B: Bus>(&mut self, machine: &Machine<B>, result: &Result<Status, Fault>) {
+        let (pc, opcode) = match self.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        if let Ok(Status::Trapped(_)) | Err(_) = result {
+            return;
+        }
+
+        self.hits[pc as usize] += 1;
+        self.total += 1;
+        // Illegal opcodes skipped with `FaultPolicy::Nop` aren't counted
+        if let Some(def) = machine.instruction_set().get(opcode) {
+            let entry = self.opcodes.entry(opcode).or_insert_with(|| (0, def.mnemonic().to_owned()));
+            entry.0 += 1;
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Snapshots of the whole machine state, which can be saved to a file and
+//! restored later.
+//!
+//! The file format is binary:
+//!
+//! ```text
+//! "SHITSNAP"   8 bytes magic
+//! version      1 byte, currently 3
+//! pc           1 byte
+//! acc          1 byte
+//! flags        1 byte, bit 0 is the carry flag
+//! sp           1 byte
+//! bus state    the rest of the file, written by `Bus::save_state`
+//! ```
+//!
+//! Version 1 didn't have the flags and version 2 didn't have the stack
+//! pointer. Such snapshots can still be loaded. The carry flag is cleared and
+//! the stack is emptied then.
+//!
+//! For plain `Memory`, the bus state is just the 256 bytes of memory. Memory
+//! with more than one bank writes all banks, followed by the bank select
+//! register. A `MappedBus` appends the state of every device, in the order
+//! they were mapped, each prefixed with its length.
+
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use crate::{bus::Bus, machine::Machine};
+
+
+/// The first bytes of every snapshot file.
+const MAGIC: &[u8; 8] = b"SHITSNAP";
+
+/// The version of the file format written by `Snapshot::to_bytes`.
+const VERSION: u8 = 3;
+
+/// The flag bit of the carry flag.
+const CARRY: u8 = 0x01;
+
+/// The state of a machine at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pc: u8,
+    acc: u8,
+    carry: bool,
+
+    /// `None` for snapshots of a version without stack pointer.
+    sp: Option<u8>,
+    bus: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Captures the registers and the state of the bus, including all
+    /// devices.
+    pub fn capture<B: Bus>(machine: &Machine<B>) -> Self {
+        let mut bus = vec![];
+        machine.bus().save_state(&mut bus);
+        Self {
+            pc: machine.pc(),
+            acc: machine.acc(),
+            carry: machine.carry(),
+            sp: Some(machine.sp()),
+            bus,
+        }
+    }
+
+    /// Puts `machine` into the captured state. Fails if the snapshot was
+    /// taken of a machine with a different bus, for example with other
+    /// devices. In that case, the machine is left unchanged.
+    pub fn restore<B: Bus>(&self, machine: &mut Machine<B>) -> Result<(), SnapshotError> {
+        machine.bus_mut().load_state(&self.bus)?;
+        machine.set_pc(self.pc);
+        machine.set_acc(self.acc);
+        machine.set_carry(self.carry);
+        machine.set_sp(self.sp.unwrap_or(*machine.stack_region().end()));
+        Ok(())
+    }
+
+    /// Encodes the snapshot in the file format described in the module docs.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        let flags = if self.carry { CARRY } else { 0 };
+        let sp = self.sp.unwrap_or(0);
+        out.extend_from_slice(&[VERSION, self.pc, self.acc, flags, sp]);
+        out.extend_from_slice(&self.bus);
+        out
+    }
+
+    /// Decodes a snapshot written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(SnapshotError::NotASnapshot);
+        }
+
+        match bytes[MAGIC.len()..] {
+            [VERSION, pc, acc, flags, sp, ref bus @ ..] => Ok(Self {
+                pc,
+                acc,
+                carry: flags & CARRY != 0,
+                sp: Some(sp),
+                bus: bus.to_vec(),
+            }),
+            [2, pc, acc, flags, ref bus @ ..] => Ok(Self {
+                pc,
+                acc,
+                carry: flags & CARRY != 0,
+                sp: None,
+                bus: bus.to_vec(),
+            }),
+            [1, pc, acc, ref bus @ ..] => {
+                Ok(Self { pc, acc, carry: false, sp: None, bus: bus.to_vec() })
+            }
+            [version, ..] if !(1..=VERSION).contains(&version) => {
+                Err(SnapshotError::UnsupportedVersion(version))
+            }
+            _ => Err(SnapshotError::Truncated),
+        }
+    }
+
+    /// Writes the snapshot to the file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    /// Reads a snapshot from the file at `path`. Invalid snapshots result in
+    /// an error of kind `InvalidData`.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        Self::from_bytes(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+
+/// An error when decoding or restoring a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The data doesn't start with the magic bytes.
+    NotASnapshot,
+
+    /// The snapshot was written in a format version we don't understand.
+    UnsupportedVersion(u8),
+
+    /// The data ends too early.
+    Truncated,
+
+    /// The bus state doesn't fit the bus of the machine, for example because
+    /// different devices are mapped.
+    BusMismatch,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::NotASnapshot => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {} (expected {})", v, VERSION)
+            }
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::BusMismatch => write!(f, "snapshot doesn't match the devices of the machine"),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
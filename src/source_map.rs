@@ -0,0 +1,81 @@
+//! Source maps written by the assembler, which map addresses to lines of the
+//! assembly source.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+
+/// The source file of a program and the line every address was assembled
+/// from.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    source: PathBuf,
+
+    /// The line of every address and whether it is data instead of code.
+    lines: BTreeMap<u8, (usize, bool)>,
+}
+
+impl SourceMap {
+    /// Parses a source map. The first line has to name the source file, like
+    /// `file asm/magic-1.s`. Every other non-empty line has to contain the hex
+    /// address and the 1-based line number, like `02 4`, optionally followed
+    /// by `data` for bytes that are not instructions.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut lines = src.lines().enumerate();
+        let source = match lines.next().and_then(|(_, l)| l.strip_prefix("file ")) {
+            Some(path) => PathBuf::from(path.trim()),
+            None => return Err("source map doesn't start with the source file".into()),
+        };
+
+        let mut map = Self { source, lines: BTreeMap::new() };
+        for (line_number, line) in lines {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || format!("invalid source map entry in line {}: '{}'", line_number + 1, line);
+            let mut parts = line.split_whitespace();
+            let addr = parts.next().and_then(|s| u8::from_str_radix(s, 16).ok());
+            let source_line = parts.next().and_then(|s| s.parse().ok());
+            let data = match parts.next() {
+                None => false,
+                Some("data") => true,
+                Some(_) => return Err(invalid()),
+            };
+            match (addr, source_line, parts.next()) {
+                (Some(addr), Some(source_line), None) => {
+                    map.lines.insert(addr, (source_line, data));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(map)
+    }
+
+    /// Loads and parses the source map at the given path.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let src = fs::read_to_string(path)?;
+        Self::parse(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns the path of the source file.
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// Returns the 1-based number of the line the given address was assembled
+    /// from.
+    pub fn line(&self, addr: u8) -> Option<usize> {
+        self.lines.get(&addr).map(|&(line, _)| line)
+    }
+
+    /// Returns the address and line of every instruction, sorted by address.
+    pub fn code(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
+        self.lines.iter().filter(|(_, &(_, data))| !data).map(|(&addr, &(line, _))| (addr, line))
+    }
+}
@@ -0,0 +1,94 @@
+//! Symbol files written by the assembler, which map label names to addresses.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::Path,
+};
+
+use crate::bus::Bus;
+
+
+/// Label names and their addresses.
+///
+/// Labels of memory banks other than bank 0 are only visible in the bank
+/// window while their bank is selected. Different banks can have labels at
+/// the same address, so they are looked up with `name_at`.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    /// The names by bank and address.
+    names: BTreeMap<(u8, u8), String>,
+    addrs: HashMap<String, u8>,
+}
+
+impl Symbols {
+    /// Creates an empty symbol table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a symbol file. Every non-empty line has to contain the hex
+    /// address and the name of a label, like `02 start`. Labels in banks
+    /// other than bank 0 are prefixed with the hex number of their bank, like
+    /// `01:80 draw`.
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut symbols = Self::new();
+        for (line_number, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || format!("invalid symbol in line {}: '{}'", line_number + 1, line);
+            let mut parts = line.split_whitespace();
+            let location = parts.next().and_then(|s| {
+                let (bank, addr) = s.split_once(':').unwrap_or(("0", s));
+                Some((u8::from_str_radix(bank, 16).ok()?, u8::from_str_radix(addr, 16).ok()?))
+            });
+            let name = parts.next();
+            match (location, name, parts.next()) {
+                (Some((bank, addr)), Some(name), None) => symbols.insert_banked(name, bank, addr),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// Loads and parses the symbol file at the given path.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let src = fs::read_to_string(path)?;
+        Self::parse(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Adds a label in bank 0. If there are multiple labels for one address,
+    /// the first one is used as the name of the address.
+    pub fn insert(&mut self, name: &str, addr: u8) {
+        self.insert_banked(name, 0, addr);
+    }
+
+    /// Like `insert`, but for a label in the given memory bank.
+    pub fn insert_banked(&mut self, name: &str, bank: u8, addr: u8) {
+        self.names.entry((bank, addr)).or_insert_with(|| name.to_owned());
+        self.addrs.insert(name.to_owned(), addr);
+    }
+
+    /// Returns the name of the label at the given address in bank 0.
+    pub fn name(&self, addr: u8) -> Option<&str> {
+        self.names.get(&(0, addr)).map(|s| s.as_str())
+    }
+
+    /// Returns the name of the label that is visible at the given address of
+    /// `bus`. Inside the bank window, that's the label of the selected bank.
+    pub fn name_at<B: Bus + ?Sized>(&self, bus: &B, addr: u8) -> Option<&str> {
+        let ram = bus.ram();
+        let bank = if ram.is_banked(addr) { ram.bank() as u8 } else { 0 };
+        self.names.get(&(bank, addr)).map(|s| s.as_str())
+    }
+
+    /// Returns the address of the label with the given name (without the
+    /// leading `.`).
+    pub fn addr(&self, name: &str) -> Option<u8> {
+        self.addrs.get(name).cloned()
+    }
+}
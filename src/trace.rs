@@ -0,0 +1,224 @@
+//! Tracing of executed instructions.
+//!
+//! A `Tracer` is passed to `Machine::run_traced` or `Machine::step_traced`
+//! and gets notified about every instruction. Since the machine is generic
+//! over the tracer, the no-op tracer `()` used by `Machine::run` is compiled
+//! away completely.
+
+use std::io::{self, Write};
+
+use crate::{
+    bus::Bus,
+    disasm::{self, Disassembled},
+    fault::Fault,
+    machine::{Machine, Status},
+    symbols::Symbols,
+};
+
+
+/// Receives notifications about executed instructions.
+pub trait Tracer {
+    /// Called before the instruction at the program counter is executed.
+    fn before<B: Bus>(&mut self, _machine: &Machine<B>) {}
+
+    /// Called when the instruction writes `value` to memory at `addr`, which
+    /// contained `old` before.
+    fn write(&mut self, _addr: u8, _old: u8, _value: u8) {}
+
+    /// Called after the instruction was executed.
+    fn after<B: Bus>(&mut self, _machine: &Machine<B>, _result: &Result<Status, Fault>) {}
+}
+
+/// The tracer which does nothing.
+impl Tracer for () {}
+
+/// An optional tracer, which does nothing if it is `None`.
+impl<T: Tracer> Tracer for Option<T> {
+    fn before<B: Bus>(&mut self, machine: &Machine<B>) {
+        if let Some(t) = self {
+            t.before(machine);
+        }
+    }
+
+    fn write(&mut self, addr: u8, old: u8, value: u8) {
+        if let Some(t) = self {
+            t.write(addr, old, value);
+        }
+    }
+
+    fn after<B: Bus>(&mut self, machine: &Machine<B>, result: &Result<Status, Fault>) {
+        if let Some(t) = self {
+            t.after(machine, result);
+        }
+    }
+}
+
+/// Two tracers which are notified one after the other.
+impl<T: Tracer, U: Tracer> Tracer for (T, U) {
+    fn before<B: Bus>(&mut self, machine: &Machine<B>) {
+        self.0.before(machine);
+        self.1.before(machine);
+    }
+
+    fn write(&mut self, addr: u8, old: u8, value: u8) {
+        self.0.write(addr, old, value);
+        self.1.write(addr, old, value);
+    }
+
+    fn after<B: Bus>(&mut self, machine: &Machine<B>, result: &Result<Status, Fault>) {
+        self.0.after(machine, result);
+        self.1.after(machine, result);
+    }
+}
+
+/// The output format of a `TraceWriter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One human readable line per instruction.
+    Text,
+
+    /// One JSON object per line.
+    Json,
+}
+
+/// A tracer that writes one line per executed instruction.
+///
+/// Each line contains the program counter, the raw bytes and the disassembly
+/// of the instruction, the accumulator before and after and every memory byte
+/// written.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: TraceFormat,
+    symbols: Symbols,
+
+    // State of the instruction currently executed
+    instr: Option<Disassembled>,
+    acc_before: u8,
+    written: Vec<(u8, u8)>,
+
+    /// The first error that occured while writing. Since the tracer can't
+    /// return errors, we stop writing after that.
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    /// Creates a trace writer. Labels from `symbols` are used in the
+    /// disassembly.
+    pub fn new(out: W, format: TraceFormat, symbols: Symbols) -> Self {
+        Self {
+            out,
+            format,
+            symbols,
+            instr: None,
+            acc_before: 0,
+            written: vec![],
+            error: None,
+        }
+    }
+
+    /// Flushes the output. Returns the first error that occured while writing
+    /// the trace.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+
+    /// Writes the line for the instruction which was just executed.
+    fn write_line(
+        &mut self,
+        instr: &Disassembled,
+        acc_after: u8,
+        fault: Option<Fault>,
+    ) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => {
+                let bytes = instr.bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>();
+                write!(
+                    self.out,
+                    "{:02x}:  {:<9}  {:<16}  acc {:02x} -> {:02x}",
+                    instr.addr,
+                    bytes.join(" "),
+                    instr.text,
+                    self.acc_before,
+                    acc_after,
+                )?;
+                for (addr, value) in &self.written {
+                    write!(self.out, "  [{:02x}] <- {:02x}", addr, value)?;
+                }
+                if let Some(fault) = fault {
+                    write!(self.out, "  fault: {}", fault)?;
+                }
+                writeln!(self.out)
+            }
+            TraceFormat::Json => {
+                let bytes = instr.bytes.iter().map(|b| b.to_string()).collect::<Vec<_>>();
+                write!(
+                    self.out,
+                    r#"{{"pc":{},"bytes":[{}],"instr":"{}","acc_before":{},"acc_after":{}"#,
+                    instr.addr,
+                    bytes.join(","),
+                    json_escape(&instr.text),
+                    self.acc_before,
+                    acc_after,
+                )?;
+                let writes = self
+                    .written
+                    .iter()
+                    .map(|(addr, value)| format!(r#"{{"addr":{},"value":{}}}"#, addr, value))
+                    .collect::<Vec<_>>();
+                write!(self.out, r#","writes":[{}]"#, writes.join(","))?;
+                if let Some(fault) = fault {
+                    write!(self.out, r#","fault":"{}""#, json_escape(&fault.to_string()))?;
+                }
+                writeln!(self.out, "}}")
+            }
+        }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn before<B: Bus>(&mut self, machine: &Machine<B>) {
+        let isa = machine.instruction_set();
+        self.instr = Some(disasm::disassemble(machine.bus(), machine.pc(), &self.symbols, isa));
+        self.acc_before = machine.acc();
+        self.written.clear();
+    }
+
+    fn write(&mut self, addr: u8, _old: u8, value: u8) {
+        self.written.push((addr, value));
+    }
+
+    fn after<B: Bus>(&mut self, machine: &Machine<B>, result: &Result<Status, Fault>) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let fault = match *result {
+            Ok(Status::Trapped(fault)) | Err(fault) => Some(fault),
+            Ok(_) => None,
+        };
+
+        if let Some(instr) = self.instr.take() {
+            if let Err(e) = self.write_line(&instr, machine.acc(), fault) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+/// Escapes a string to be used inside quotes in JSON.
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
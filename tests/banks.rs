@@ -0,0 +1,143 @@
+//! Checks memory with more than one bank.
+
+use shit_cpu_emu::{
+    ImageTooLarge, Led, Machine, MappedBus, Memory, Snapshot, SnapshotError, Status, Symbols,
+    DEFAULT_BANK_SELECT,
+};
+
+
+/// Creates an image with each of `banks` placed at the start of its bank.
+fn image(banks: &[&[u8]]) -> Vec<u8> {
+    let mut image = vec![];
+    for bank in banks {
+        image.resize(image.len().next_multiple_of(256), 0);
+        image.extend_from_slice(bank);
+    }
+    image
+}
+
+/// Like `image`, but the banks after bank 0 start at $80, where the bank
+/// window starts by default.
+fn windowed(banks: &[&[u8]]) -> Vec<u8> {
+    let mut image = image(&banks[..1]);
+    for (i, bank) in banks.iter().enumerate().skip(1) {
+        image.resize(i * 256 + 0x80, 0);
+        image.extend_from_slice(bank);
+    }
+    image
+}
+
+#[test]
+fn image_too_large() {
+    let err = Memory::from_image(&[0; 257], 1).unwrap_err();
+    assert_eq!(err, ImageTooLarge { len: 257, capacity: 256 });
+    assert_eq!(err.to_string(), "image of 257 bytes doesn't fit into 256 bytes of memory");
+
+    let memory = Memory::from_image(&[0; 257], 2).unwrap();
+    assert_eq!(memory.banks(), 2);
+    assert_eq!(memory.as_bytes().len(), 512);
+}
+
+#[test]
+fn switch_banks() {
+    let program = windowed(&[
+        // sti $01 [$fb]; jmp $80; sti $02 [$fb]; jmp $80; stop
+        &[0x13, 0x01, 0xfb, 0x20, 0x80, 0x13, 0x02, 0xfb, 0x20, 0x80, 0x50],
+        // ldi $11; st [$20]; jmp $05
+        &[0x11, 0x11, 0x12, 0x20, 0x20, 0x05],
+        // ldi $22; st [$21]; st [$90]; jmp $0a
+        &[0x11, 0x22, 0x12, 0x21, 0x12, 0x90, 0x20, 0x0a],
+    ]);
+
+    // Bank 2 replaces the code of bank 1 at $80, also in the decode cache
+    let mut machine = Machine::with_bus(Memory::from_image(&program, 3).unwrap());
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.bus().read_range(0x20..=0x21), [0x11, 0x22]);
+    assert_eq!(machine.bus().bank(), 2);
+    assert_eq!(machine.bus()[DEFAULT_BANK_SELECT], 0x02);
+    assert_eq!(machine.bus()[0x90], 0x22);
+    assert_eq!(machine.bus().as_bytes()[0x290], 0x22);
+    assert_eq!(machine.bus().as_bytes()[0x90], 0x00);
+
+    // Ranges across the edge of the window mix the banks
+    assert_eq!(machine.bus().read_range(0x7f..=0x81), [0x00, 0x11, 0x22]);
+    assert_eq!(machine.bus().read_range(0xdf..=0xe0), [0x00, 0x00]);
+    assert_eq!(machine.bus().read_range(0xfa..=0xfc), [0x00, 0x02, 0x00]);
+}
+
+#[test]
+fn select_wraps_around() {
+    // sti $03 [$fb]; stop
+    let program = image(&[&[0x13, 0x03, 0xfb, 0x50], &[]]);
+    let mut machine = Machine::with_bus(Memory::from_image(&program, 2).unwrap());
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.bus().bank(), 1);
+    assert_eq!(machine.bus()[DEFAULT_BANK_SELECT], 0x03);
+}
+
+#[test]
+fn single_bank_has_no_select_register() {
+    // sti $03 [$fb]; sti $04 [$80]; stop
+    let mut machine = Machine::from_program(&[0x13, 0x03, 0xfb, 0x13, 0x04, 0x80, 0x50]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.bus().bank(), 0);
+    assert_eq!(machine.bus()[0xfb], 0x03);
+    assert_eq!(machine.bus().as_bytes()[0x80], 0x04);
+}
+
+#[test]
+fn custom_window() {
+    // sti $01 [$3f]; ld [$40]; stop
+    let mut program = image(&[&[0x13, 0x01, 0x3f, 0x10, 0x40, 0x50]]);
+    program.resize(0x140, 0);
+    program.push(0x2a);
+
+    let mut memory = Memory::from_image(&program, 2).unwrap();
+    memory.set_bank_window(0x40..=0x7f, 0x3f);
+    let mut bus = MappedBus::new(memory);
+    bus.map(0xff..=0xff, Led::new());
+    let mut machine = Machine::with_bus(bus);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.acc(), 0x2a);
+
+    // Without the default window, $80 and $fb are plain RAM of bank 0
+    assert_eq!(machine.bus().ram().bank_select(), 0x3f);
+    assert!(!machine.bus().ram().is_banked(0x80));
+}
+
+#[test]
+fn labels_of_the_selected_bank() {
+    let symbols = Symbols::parse("00 start\n01:80 f\n02:80 g\n").unwrap();
+    assert_eq!(symbols.addr("g"), Some(0x80));
+
+    // sti $01 [$fb]; stop
+    let program = image(&[&[0x13, 0x01, 0xfb, 0x50], &[]]);
+    let mut machine = Machine::with_bus(Memory::from_image(&program, 3).unwrap());
+    assert_eq!(symbols.name_at(machine.bus(), 0x80), None);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(symbols.name_at(machine.bus(), 0x80), Some("f"));
+    assert_eq!(symbols.name_at(machine.bus(), 0x00), Some("start"));
+    assert_eq!(symbols.name(0x80), None);
+
+    assert!(Symbols::parse("01:100 f\n").is_err());
+    assert!(Symbols::parse("100:80 f\n").is_err());
+}
+
+#[test]
+fn snapshot_includes_banks() {
+    // sti $01 [$fb]; sti $07 [$80]; stop
+    let program = image(&[&[0x13, 0x01, 0xfb, 0x13, 0x07, 0x80, 0x50], &[]]);
+    let mut machine = Machine::with_bus(Memory::from_image(&program, 2).unwrap());
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    let snapshot = Snapshot::from_bytes(&Snapshot::capture(&machine).to_bytes()).unwrap();
+
+    let mut resumed = Machine::with_bus(Memory::from_image(&program, 2).unwrap());
+    snapshot.restore(&mut resumed).unwrap();
+    assert_eq!(resumed.bus().bank(), 1);
+    assert_eq!(resumed.bus()[0x80], 0x07);
+    assert_eq!(resumed.bus().as_bytes(), machine.bus().as_bytes());
+
+    // The number of banks has to match
+    let mut other = Machine::from_program(&program[..256]);
+    assert_eq!(snapshot.restore(&mut other), Err(SnapshotError::BusMismatch));
+}
@@ -0,0 +1,74 @@
+//! Checks memory-mapped devices on a `MappedBus`.
+
+use shit_cpu_emu::{
+    Buffer, Bus, Console, Device, Led, Machine, MappedBus, Memory, Rng, Script, Status, Timer,
+};
+
+
+fn machine(program: &[u8]) -> Machine<MappedBus> {
+    Machine::with_bus(MappedBus::new(Memory::from_program(program)))
+}
+
+#[test]
+fn led_port() {
+    // ldi $05; st [$ff]; stop
+    let mut machine = machine(&[0x11, 0x05, 0x12, 0xff, 0x50]);
+    let led = Led::new();
+    machine.bus_mut().map(0xff..=0xff, led.clone());
+
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(led.value(), 0x05);
+    assert_eq!(machine.bus().ram()[0xff], 0x00);
+}
+
+#[test]
+fn timer_counts_instructions() {
+    // nop; nop; ld [$fd]; stop
+    let mut machine = machine(&[0x00, 0x00, 0x10, 0xfd, 0x50]);
+    machine.bus_mut().map(0xfd..=0xfd, Timer::new());
+
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.acc(), 2);
+}
+
+#[test]
+fn console_echo() {
+    // ld [$fc]; st [$fc]; ld [$fc]; stop
+    let mut machine = machine(&[0x10, 0xfc, 0x12, 0xfc, 0x10, 0xfc, 0x50]);
+    let out = Buffer::new();
+    machine.bus_mut().map(0xfc..=0xfc, Console::new(Script::new(&b"x"[..]), out.clone()));
+
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(out.bytes(), b"x");
+    assert_eq!(machine.acc(), 0, "reading past the end of input returns 0");
+}
+
+#[test]
+fn rng_is_deterministic() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+    for _ in 0..16 {
+        let next = a.peek(0);
+        assert_eq!(a.read(0), next);
+        assert_eq!(b.read(0), next);
+    }
+}
+
+#[test]
+fn peek_has_no_side_effects() {
+    let mut bus = MappedBus::new(Memory::from_program(&[]));
+    bus.map(0xfe..=0xfe, Rng::new(7));
+
+    assert_eq!(bus.peek(0xfe), bus.peek(0xfe));
+    let peeked = bus.peek(0xfe);
+    assert_eq!(bus.read(0xfe), peeked);
+    assert_ne!(bus.peek(0xfe), peeked);
+}
+
+#[test]
+#[should_panic]
+fn overlapping_devices() {
+    let mut bus = MappedBus::new(Memory::from_program(&[]));
+    bus.map(0xf0..=0xf3, Led::new());
+    bus.map(0xf3..=0xf4, Led::new());
+}
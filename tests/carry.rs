@@ -0,0 +1,108 @@
+//! Checks the carry extension.
+
+use shit_cpu_emu::{Extension, Fault, InstructionSet, Machine, Snapshot, Status};
+
+
+/// Runs `program` with the carry extension until it halts.
+fn run(program: &[u8]) -> Machine {
+    let mut machine = Machine::from_program(program);
+    machine.enable_extension(Extension::Carry);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    machine
+}
+
+#[test]
+fn disabled_by_default() {
+    // jc $00
+    let mut machine = Machine::from_program(&[0x22, 0x00]);
+    assert!(!machine.has_extension(Extension::Carry));
+    assert_eq!(machine.run(), Err(Fault::IllegalOpcode { pc: 0x00, opcode: 0x22 }));
+}
+
+#[test]
+fn arithmetic_sets_carry() {
+    // ldi $f0; addi $20; stop
+    let machine = run(&[0x11, 0xf0, 0x31, 0x20, 0x50]);
+    assert_eq!((machine.acc(), machine.carry()), (0x10, true));
+
+    // ldi $f0; addi $0f; stop
+    let machine = run(&[0x11, 0xf0, 0x31, 0x0f, 0x50]);
+    assert_eq!((machine.acc(), machine.carry()), (0xff, false));
+
+    // ldi $10; subi $20; stop
+    let machine = run(&[0x11, 0x10, 0x33, 0x20, 0x50]);
+    assert_eq!((machine.acc(), machine.carry()), (0xf0, true));
+
+    // ldi $10; sub [$06]; stop; .byte $10
+    let machine = run(&[0x11, 0x10, 0x32, 0x06, 0x50, 0x00, 0x10]);
+    assert_eq!((machine.acc(), machine.carry()), (0x00, false));
+
+    // ldi $81; shl; stop
+    let machine = run(&[0x11, 0x81, 0x35, 0x50]);
+    assert_eq!((machine.acc(), machine.carry()), (0x02, true));
+
+    // ldi $81; shr; shr; stop
+    let machine = run(&[0x11, 0x81, 0x34, 0x34, 0x50]);
+    assert_eq!((machine.acc(), machine.carry()), (0x20, false));
+}
+
+#[test]
+fn unchanged_without_extension() {
+    // ldi $ff; addi $01; shr; stop
+    let mut machine = Machine::from_program(&[0x11, 0xff, 0x31, 0x01, 0x34, 0x50]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!((machine.acc(), machine.carry()), (0x00, false));
+
+    // The described instruction set behaves the same
+    let mut machine = Machine::from_program(&[0x11, 0xff, 0x31, 0x01, 0x34, 0x50]);
+    machine.set_instruction_set(InstructionSet::builtin().clone());
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert!(!machine.carry());
+}
+
+#[test]
+fn jumps() {
+    //     ldi $ff; addi $01; jc .c; stop
+    //     .c: jnz .c; jnc .nc; stop
+    //     .nc: ldi $01; stop
+    let program = [
+        0x11, 0xff, 0x31, 0x01, 0x22, 0x07, 0x50,
+        0x24, 0x07, 0x23, 0x0c, 0x50,
+        0x11, 0x01, 0x50,
+    ];
+
+    // `jc` is taken, `jnz` and `jnc` are not, since the sum is 0 and the
+    // carry is still set.
+    let machine = run(&program);
+    assert_eq!((machine.pc(), machine.acc()), (0x0b, 0x00));
+}
+
+#[test]
+fn add_16_bit() {
+    // Adds $01ff and $0001, stored little endian at $20 and $22:
+    //
+    //     ld [$20]; add [$22]; st [$24]
+    //     ld [$21]; jnc .no_carry; addi $01
+    //     .no_carry: add [$23]; st [$25]; stop
+    let mut program = vec![
+        0x10, 0x20, 0x30, 0x22, 0x12, 0x24,
+        0x10, 0x21, 0x23, 0x0c, 0x31, 0x01,
+        0x30, 0x23, 0x12, 0x25, 0x50,
+    ];
+    program.resize(0x20, 0);
+    program.extend_from_slice(&[0xff, 0x01, 0x01, 0x00]);
+
+    let machine = run(&program);
+    assert_eq!((machine.bus()[0x24], machine.bus()[0x25]), (0x00, 0x02));
+}
+
+#[test]
+fn snapshot_keeps_carry() {
+    // ldi $ff; addi $01; stop
+    let machine = run(&[0x11, 0xff, 0x31, 0x01, 0x50]);
+    let bytes = Snapshot::capture(&machine).to_bytes();
+
+    let mut restored = Machine::from_program(&[]);
+    Snapshot::from_bytes(&bytes).unwrap().restore(&mut restored).unwrap();
+    assert!(restored.carry());
+}
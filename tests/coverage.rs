@@ -0,0 +1,63 @@
+//! Checks coverage data and LCOV reports.
+
+use shit_cpu_emu::{Buffer, Coverage, InstructionSet, Machine, SourceMap, Status};
+
+
+const MAGIC_1: &[u8] = include_bytes!("../programs/magic-1.bin");
+
+fn cover(program: &[u8]) -> Coverage {
+    let mut machine = Machine::from_program(program);
+    machine.set_output(Buffer::new());
+    let mut coverage = Coverage::new();
+    assert_eq!(machine.run_traced(&mut coverage), Ok(Status::Halted));
+    coverage
+}
+
+#[test]
+fn branch_directions() {
+    let coverage = cover(MAGIC_1);
+
+    // `jz .end` falls through for a..y and jumps after z
+    assert_eq!(coverage.branch(0x08), (1, 25));
+    assert_eq!(coverage.hits(0x08), 26);
+    assert_eq!(coverage.hits(0x11), 0);
+}
+
+#[test]
+fn data_roundtrip_and_merge() {
+    let coverage = cover(MAGIC_1);
+    let parsed = Coverage::parse(&coverage.to_data()).unwrap();
+    assert_eq!(parsed.to_data(), coverage.to_data());
+
+    let mut merged = parsed;
+    merged.merge(&coverage);
+    assert_eq!(merged.hits(0x02), 52);
+    assert_eq!(merged.branch(0x08), (2, 50));
+
+    assert!(Coverage::parse("hit 02").is_err());
+    assert!(Coverage::parse("miss 02 1").is_err());
+}
+
+#[test]
+fn lcov() {
+    // jmp .end; jz .end; .end: stop; .byte $00
+    let program = [0x20, 0x04, 0x21, 0x04, 0x50, 0x00];
+    let map = SourceMap::parse("file test.s\n00 1\n02 2\n04 4\n05 5 data\n").unwrap();
+    let report = cover(&program).lcov(&program, &map, InstructionSet::builtin());
+
+    let expected = [
+        "TN:",
+        "SF:test.s",
+        "BRDA:2,2,0,-",
+        "BRDA:2,2,1,-",
+        "BRF:2",
+        "BRH:0",
+        "DA:1,1",
+        "DA:2,0",
+        "DA:4,1",
+        "LF:3",
+        "LH:2",
+        "end_of_record",
+    ];
+    assert_eq!(report.lines().collect::<Vec<_>>(), expected);
+}
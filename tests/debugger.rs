@@ -0,0 +1,95 @@
+//! Checks the commands of the `Debugger`.
+
+use shit_cpu_emu::{Buffer, Console, Debugger, Machine, MappedBus, Memory, Script, Symbols};
+
+
+/// ldi $2a; st [$20]; ldi $07; st [$21]; stop
+const PROGRAM: &[u8] = &[0x11, 0x2a, 0x12, 0x20, 0x11, 0x07, 0x12, 0x21, 0x50];
+
+/// Runs the debugger on `PROGRAM` with the given commands. Returns the
+/// debugger and everything it printed.
+fn debug(commands: &str) -> (Debugger, String) {
+    let mut debugger = Debugger::new(Machine::from_program(PROGRAM), Symbols::new());
+    let mut out = vec![];
+    debugger.run_with(commands.as_bytes(), &mut out).unwrap();
+    (debugger, String::from_utf8(out).unwrap())
+}
+
+#[test]
+fn step() {
+    let (debugger, _) = debug("step\nstep 2\n");
+    assert_eq!(debugger.machine().pc(), 0x06);
+    assert_eq!(debugger.machine().bus()[0x20], 0x2a);
+
+    // Counts are positive decimal numbers
+    let (debugger, out) = debug("step 0\nstep -1\nstep 1f\nreverse-step 0\n");
+    assert_eq!(debugger.machine().pc(), 0x00);
+    assert_eq!(out.matches("error: invalid count").count(), 4, "{}", out);
+
+    let (debugger, _) = debug("step 3\nreverse-step 2\n");
+    assert_eq!(debugger.machine().pc(), 0x02);
+}
+
+#[test]
+fn breakpoints() {
+    let (debugger, out) = debug("break 06\ncontinue\n");
+    assert_eq!(debugger.machine().pc(), 0x06);
+    assert!(out.contains("breakpoint at 06"), "{}", out);
+
+    let (debugger, out) = debug("break 06\ndelete 06\ncontinue\n");
+    assert_eq!(debugger.machine().pc(), 0x08);
+    assert!(out.contains("machine halted"), "{}", out);
+}
+
+#[test]
+fn watchpoints() {
+    let (debugger, out) = debug("watch 21\ncontinue\n");
+    assert_eq!(debugger.machine().pc(), 0x08);
+    assert!(out.contains("watchpoint at 21: 00 -> 07"), "{}", out);
+
+    let (_, out) = debug("watch 21\nunwatch 21\ncontinue\n");
+    assert!(out.contains("machine halted"), "{}", out);
+}
+
+#[test]
+fn mem() {
+    let (_, out) = debug("mem 00 3\n");
+    assert!(out.contains("00: 11 2a 12\n"), "{}", out);
+
+    // The length is decimal and stops at the end of memory
+    let (_, out) = debug("mem 00 20\n");
+    assert!(out.contains("\n10: 00 00 00 00\n"), "{}", out);
+    let (_, out) = debug("mem fc 100\n");
+    assert!(out.contains("fc: 00 00 00 00\n(shit) "), "{}", out);
+
+    let (_, out) = debug("mem 00 0\n");
+    assert!(out.contains("error: invalid count '0'"), "{}", out);
+}
+
+#[test]
+fn set() {
+    let (debugger, _) = debug("set 30 5a\nset acc 11\nset pc 04\nset carry 1\n");
+    let machine = debugger.machine();
+    assert_eq!(machine.bus()[0x30], 0x5a);
+    assert_eq!(machine.acc(), 0x11);
+    assert_eq!(machine.pc(), 0x04);
+    assert!(machine.carry());
+
+    let (debugger, out) = debug("set carry zz\nset 30\n");
+    assert!(!debugger.machine().carry());
+    assert_eq!(out.matches("error:").count(), 2, "{}", out);
+}
+
+#[test]
+fn set_device() {
+    let mut bus = MappedBus::new(Memory::from_program(PROGRAM));
+    let console = Buffer::new();
+    bus.map(0xfc..=0xfc, Console::new(Script::default(), console.clone()));
+    let mut debugger = Debugger::new(Machine::with_bus(bus), Symbols::new());
+
+    let mut out = vec![];
+    debugger.run_with(&b"set fc 41\n"[..], &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("error: can't write to the device at fc"), "{}", out);
+    assert!(console.bytes().is_empty());
+}
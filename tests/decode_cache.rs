@@ -0,0 +1,57 @@
+//! Checks that the decode cache doesn't change the behavior of programs.
+
+use shit_cpu_emu::{Buffer, Machine, Snapshot, Status};
+
+
+/// Runs `program` with and without the decode cache and compares the state
+/// of both machines after every step.
+fn assert_same_as_interpreter(program: &[u8], steps: usize) {
+    let mut cached = Machine::from_program(program);
+    let mut plain = Machine::from_program(program);
+    plain.set_decode_cache(false);
+    cached.set_output(Buffer::new());
+    plain.set_output(Buffer::new());
+
+    for step in 0..steps {
+        let status = cached.step();
+        assert_eq!(status, plain.step(), "status after step {}", step);
+        assert_eq!(Snapshot::capture(&cached), Snapshot::capture(&plain), "state after step {}", step);
+        if status != Ok(Status::Running) {
+            return;
+        }
+    }
+}
+
+#[test]
+fn sample_program() {
+    assert_same_as_interpreter(include_bytes!("../programs/magic-1.bin"), 1000);
+}
+
+#[test]
+fn modified_operand() {
+    // addi $01; st [$01]; jmp $00
+    //
+    // Stores the accumulator into the operand of `addi`, so it doubles every
+    // iteration.
+    assert_same_as_interpreter(&[0x31, 0x01, 0x12, 0x01, 0x20, 0x00], 30);
+}
+
+#[test]
+fn modified_opcode() {
+    // nop; nop; sti $50 [$01]; jmp $00
+    //
+    // Turns the second `nop` into `stop` after the first iteration.
+    assert_same_as_interpreter(&[0x00, 0x00, 0x13, 0x50, 0x01, 0x20, 0x00], 30);
+}
+
+#[test]
+fn modified_through_bus() {
+    // ldi $01; stop
+    let mut machine = Machine::from_program(&[0x11, 0x01, 0x50]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+
+    machine.bus_mut()[0x01] = 0x02;
+    machine.set_pc(0);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.acc(), 0x02);
+}
@@ -0,0 +1,90 @@
+//! Checks the fault policies and how the emulator binary reports faults.
+
+use std::{env, fs, process::{Command, Stdio}};
+
+use shit_cpu_emu::{Buffer, Fault, FaultPolicy, Machine, Status};
+
+
+/// ldi $2a; .byte $ff; ldi $07; stop
+const ILLEGAL: &[u8] = &[0x11, 0x2a, 0xff, 0x11, 0x07, 0x50];
+
+/// print [$fe]; ldi $07; stop, with a string of 5 bytes at $fe, which ends
+/// past the end of memory.
+fn out_of_range() -> Vec<u8> {
+    let mut program = vec![0x40, 0xfe, 0x11, 0x07, 0x50];
+    program.resize(0xff, 0);
+    program[0xfe] = 0x05;
+    program
+}
+
+/// Runs `program` with the given fault policy. Returns the machine, the
+/// result of running it and everything it printed.
+fn run(program: &[u8], policy: FaultPolicy) -> (Machine, Result<Status, Fault>, Vec<u8>) {
+    let out = Buffer::new();
+    let mut machine = Machine::from_program(program);
+    machine.set_output(out.clone());
+    machine.set_fault_policy(policy);
+    let result = machine.run();
+    (machine, result, out.bytes())
+}
+
+#[test]
+fn illegal_opcode() {
+    let fault = Fault::IllegalOpcode { pc: 0x02, opcode: 0xff };
+
+    let (machine, result, _) = run(ILLEGAL, FaultPolicy::Halt);
+    assert_eq!(result, Err(fault));
+    assert_eq!((machine.pc(), machine.acc()), (0x02, 0x2a));
+
+    let (machine, result, _) = run(ILLEGAL, FaultPolicy::Trap);
+    assert_eq!(result, Ok(Status::Trapped(fault)));
+    assert_eq!((machine.pc(), machine.acc()), (0x02, 0x2a));
+
+    // The illegal opcode is skipped as a single byte
+    let (machine, result, _) = run(ILLEGAL, FaultPolicy::Nop);
+    assert_eq!(result, Ok(Status::Halted));
+    assert_eq!((machine.pc(), machine.acc()), (0x05, 0x07));
+}
+
+#[test]
+fn print_out_of_range() {
+    let program = out_of_range();
+    let fault = Fault::PrintOutOfRange { pc: 0x00, src: 0xfe, len: 0x05 };
+
+    let (machine, result, out) = run(&program, FaultPolicy::Halt);
+    assert_eq!(result, Err(fault));
+    assert_eq!(machine.pc(), 0x00);
+    assert!(out.is_empty());
+
+    let (machine, result, out) = run(&program, FaultPolicy::Trap);
+    assert_eq!(result, Ok(Status::Trapped(fault)));
+    assert_eq!(machine.pc(), 0x00);
+    assert!(out.is_empty());
+
+    // The whole `print` is skipped without printing anything
+    let (machine, result, out) = run(&program, FaultPolicy::Nop);
+    assert_eq!(result, Ok(Status::Halted));
+    assert_eq!((machine.pc(), machine.acc()), (0x04, 0x07));
+    assert!(out.is_empty());
+}
+
+#[test]
+fn cli_reports_faults() {
+    let dir = env::temp_dir().join(format!("emulator-test-fault-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("illegal.bin");
+    fs::write(&program, ILLEGAL).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_shit-cpu-emu"))
+        .arg(&program)
+        .stdin(Stdio::null())
+        .output()
+        .expect("failed to run the emulator");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(2), "{}", stdout);
+    assert!(stdout.contains("Fault: illegal opcode ff at 02\n"), "{}", stdout);
+    assert!(stdout.contains("pc: 02  acc: 2a  carry: 0"), "{}", stdout);
+    assert!(stdout.contains("\n00: 11 2a ff 11 07 50 00 00"), "{}", stdout);
+    assert!(stdout.contains("\nf0: 00 00"), "{}", stdout);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,277 @@
+//! Differential tests running random memory images with and without the
+//! decode cache and with a copy of the built-in instruction set, comparing
+//! every step against `Reference`, a deliberately simple implementation of
+//! the instruction set in this file.
+
+use std::collections::VecDeque;
+
+use proptest::prelude::*;
+use shit_cpu_emu::{
+    Buffer, Extension, Fault, FaultPolicy, InstructionSet, Machine, Script, Status,
+    MACHINE_MEMORY_SIZE,
+};
+use shit_isa::Opcode;
+
+/// How many steps each image is executed for.
+const STEPS: usize = 2000;
+
+/// Generates a full memory image. Half of the bytes are valid opcodes, so
+/// that the program gets further than the first illegal opcode.
+fn image() -> impl Strategy<Value = Vec<u8>> {
+    let opcode = (0..Opcode::ALL.len()).prop_map(|idx| Opcode::ALL[idx].to_byte());
+    prop::collection::vec(prop_oneof![any::<u8>(), opcode], MACHINE_MEMORY_SIZE)
+}
+
+fn fault_policy() -> impl Strategy<Value = FaultPolicy> {
+    prop_oneof![Just(FaultPolicy::Halt), Just(FaultPolicy::Trap), Just(FaultPolicy::Nop)]
+}
+
+/// The configuration of the machines compared with each other.
+#[derive(Debug, Clone)]
+struct Config {
+    policy: FaultPolicy,
+    carry: bool,
+    indirect: bool,
+    stack: bool,
+    input: Vec<u8>,
+}
+
+fn config() -> impl Strategy<Value = Config> {
+    (fault_policy(), any::<bool>(), any::<bool>(), any::<bool>(), any::<Vec<u8>>()).prop_map(
+        |(policy, carry, indirect, stack, input)| Config { policy, carry, indirect, stack, input },
+    )
+}
+
+/// How the machines compared with each other execute instructions.
+#[derive(Debug, Clone, Copy)]
+enum Engine {
+    /// Instructions compiled to native ones in the decode cache.
+    Cached,
+
+    /// Only the micro-operations of the description.
+    Uncached,
+
+    /// A copy of the built-in instruction set, like one loaded from a file.
+    Described,
+}
+
+/// Creates a machine for `image` which records its output in the returned
+/// buffer.
+fn machine(image: &[u8], config: &Config, engine: Engine) -> (Machine, Buffer) {
+    let output = Buffer::new();
+    let mut machine = Machine::from_program(image);
+    match engine {
+        Engine::Cached => {}
+        Engine::Uncached => machine.set_decode_cache(false),
+        Engine::Described => machine.set_instruction_set(InstructionSet::builtin().clone()),
+    }
+    if config.carry {
+        machine.enable_extension(Extension::Carry);
+    }
+    if config.indirect {
+        machine.enable_extension(Extension::Indirect);
+    }
+    if config.stack {
+        machine.enable_extension(Extension::Stack);
+    }
+    machine.set_fault_policy(config.policy);
+    machine.set_input(Script::new(config.input.clone()));
+    machine.set_output(output.clone());
+    (machine, output)
+}
+
+/// The instruction set written down as plainly as possible, without sharing
+/// any code with the emulator, as the expected behavior of the machine.
+struct Reference {
+    memory: [u8; MACHINE_MEMORY_SIZE],
+    pc: u8,
+    acc: u8,
+    carry: bool,
+    sp: u8,
+    input: VecDeque<u8>,
+    prints: Vec<Vec<u8>>,
+    config: Config,
+}
+
+impl Reference {
+    fn new(image: &[u8], config: &Config) -> Self {
+        let mut memory = [0; MACHINE_MEMORY_SIZE];
+        memory[..image.len()].copy_from_slice(image);
+        Self {
+            memory,
+            pc: 0,
+            acc: 0,
+            carry: false,
+            sp: 0xef,
+            input: config.input.iter().cloned().collect(),
+            prints: vec![],
+            config: config.clone(),
+        }
+    }
+
+    fn step(&mut self) -> Result<Status, Fault> {
+        let pc = self.pc;
+        let opcode = self.memory[pc as usize];
+        let a0 = self.memory[pc.wrapping_add(1) as usize];
+        let a1 = self.memory[pc.wrapping_add(2) as usize];
+
+        let (len, enabled) = match opcode {
+            0x00 | 0x34 | 0x35 | 0x3c | 0x50 => (1, true),
+            0x10..=0x12 | 0x20 | 0x21 | 0x30..=0x33 | 0x36..=0x3b | 0x40 | 0x41 => (2, true),
+            0x13 | 0x14 => (3, true),
+            0x15 | 0x16 | 0x25 => (2, self.config.indirect),
+            0x22..=0x24 => (2, self.config.carry),
+            0x60 | 0x61 | 0x63 => (1, self.config.stack),
+            0x62 => (2, self.config.stack),
+            _ => (1, false),
+        };
+        if !enabled {
+            return self.fault(Fault::IllegalOpcode { pc, opcode }, 1);
+        }
+
+        let mut next = pc.wrapping_add(len);
+        let m = &mut self.memory;
+        match opcode {
+            0x00 => {}
+            0x10 => self.acc = m[a0 as usize],
+            0x11 => self.acc = a0,
+            0x12 => m[a0 as usize] = self.acc,
+            0x13 => m[a1 as usize] = a0,
+            0x14 => m[a1 as usize] = m[a0 as usize],
+            0x15 => self.acc = m[m[a0 as usize] as usize],
+            0x16 => m[m[a0 as usize] as usize] = self.acc,
+            0x20 => next = a0,
+            0x21 if self.acc == 0 => next = a0,
+            0x22 if self.carry => next = a0,
+            0x23 if !self.carry => next = a0,
+            0x24 if self.acc != 0 => next = a0,
+            0x21..=0x24 => {}
+            0x25 => next = m[a0 as usize],
+            0x30..=0x33 => {
+                let v = if opcode & 1 == 0 { m[a0 as usize] } else { a0 };
+                let (sum, carry) = if opcode < 0x32 {
+                    self.acc.overflowing_add(v)
+                } else {
+                    self.acc.overflowing_sub(v)
+                };
+                self.acc = sum;
+                self.carry = carry && self.config.carry;
+            }
+            0x34 => {
+                self.carry = self.acc & 0x01 != 0 && self.config.carry;
+                self.acc >>= 1;
+            }
+            0x35 => {
+                self.carry = self.acc & 0x80 != 0 && self.config.carry;
+                self.acc <<= 1;
+            }
+            0x36 => self.acc &= m[a0 as usize],
+            0x37 => self.acc &= a0,
+            0x38 => self.acc |= m[a0 as usize],
+            0x39 => self.acc |= a0,
+            0x3a => self.acc ^= m[a0 as usize],
+            0x3b => self.acc ^= a0,
+            0x3c => self.acc = !self.acc,
+            0x40 => {
+                let len = m[a0 as usize];
+                let end = a0 as usize + len as usize;
+                if end >= MACHINE_MEMORY_SIZE {
+                    return self.fault(Fault::PrintOutOfRange { pc, src: a0, len }, 2);
+                }
+                self.prints.push(m[a0 as usize + 1..=end].to_vec());
+            }
+            0x41 => match self.input.pop_front() {
+                Some(byte) => self.acc = byte,
+                None => next = a0,
+            },
+            0x50 => return Ok(Status::Halted),
+            0x60 | 0x62 => {
+                if !(0xe0..=0xef).contains(&self.sp) {
+                    return self.fault(Fault::StackOverflow { pc }, len);
+                }
+                m[self.sp as usize] = if opcode == 0x60 { self.acc } else { next };
+                self.sp -= 1;
+                if opcode == 0x62 {
+                    next = a0;
+                }
+            }
+            0x61 | 0x63 => {
+                if !(0xdf..0xef).contains(&self.sp) {
+                    return self.fault(Fault::StackUnderflow { pc }, len);
+                }
+                self.sp += 1;
+                let value = m[self.sp as usize];
+                if opcode == 0x61 {
+                    self.acc = value;
+                } else {
+                    next = value;
+                }
+            }
+            _ => unreachable!("opcode {:02x} was checked above", opcode),
+        }
+
+        self.pc = next;
+        Ok(Status::Running)
+    }
+
+    fn fault(&mut self, fault: Fault, len: u8) -> Result<Status, Fault> {
+        match self.config.policy {
+            FaultPolicy::Halt => Err(fault),
+            FaultPolicy::Trap => Ok(Status::Trapped(fault)),
+            FaultPolicy::Nop => {
+                self.pc = self.pc.wrapping_add(len);
+                Ok(Status::Running)
+            }
+        }
+    }
+}
+
+/// Runs `image` with `engine` and the reference implementation and checks
+/// that both always are in the same state.
+fn compare(image: &[u8], config: &Config, engine: Engine) -> Result<(), TestCaseError> {
+    let (mut machine, output) = machine(image, config, engine);
+    let mut reference = Reference::new(image, config);
+
+    for step in 0..STEPS {
+        let status = machine.step();
+        prop_assert_eq!(status, reference.step(), "status after step {}", step);
+        prop_assert_eq!(
+            (machine.pc(), machine.acc(), machine.carry(), machine.sp()),
+            (reference.pc, reference.acc, reference.carry, reference.sp),
+            "registers after step {}",
+            step,
+        );
+        prop_assert_eq!(
+            machine.bus().as_bytes(),
+            &reference.memory[..],
+            "memory after step {}",
+            step,
+        );
+
+        // Halted and trapped machines just repeat the same step
+        if !matches!(status, Ok(Status::Running)) {
+            break;
+        }
+    }
+    prop_assert_eq!(output.prints(), reference.prints);
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(200))]
+
+    #[test]
+    fn cache_matches_reference(image in image(), config in config()) {
+        compare(&image, &config, Engine::Cached)?;
+    }
+
+    #[test]
+    fn interpreter_matches_reference(image in image(), config in config()) {
+        compare(&image, &config, Engine::Uncached)?;
+    }
+
+    #[test]
+    fn description_matches_reference(image in image(), config in config()) {
+        compare(&image, &config, Engine::Described)?;
+    }
+}
@@ -0,0 +1,135 @@
+//! Talks to the GDB stub over a local TCP connection.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use shit_cpu_emu::{Buffer, Bus, Console, GdbStub, Machine, MappedBus, Memory, Script};
+
+
+/// A minimal client for the remote serial protocol.
+struct Client(TcpStream);
+
+impl Client {
+    /// Starts a stub for magic-1 and connects to it.
+    fn connect() -> (Self, thread::JoinHandle<()>) {
+        Self::connect_to(|| {
+            let mut machine = Machine::from_program(include_bytes!("../programs/magic-1.bin"));
+            machine.set_output(Buffer::new());
+            machine
+        })
+    }
+
+    /// Starts a stub for the machine created by `machine` and connects to it.
+    fn connect_to<B: Bus>(
+        machine: impl FnOnce() -> Machine<B> + Send + 'static,
+    ) -> (Self, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let machine = machine();
+            let (stream, _) = listener.accept().unwrap();
+            GdbStub::new(machine).session(stream).unwrap();
+        });
+
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        (Client(stream), server)
+    }
+
+    /// Sends a packet and returns the reply.
+    fn request(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.0, "${}#{:02x}", data, checksum).unwrap();
+
+        assert_eq!(self.read_byte(), b'+');
+        assert_eq!(self.read_byte(), b'$');
+        let mut reply = vec![];
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                b => reply.push(b),
+            }
+        }
+        let mut checksum = [0; 2];
+        self.0.read_exact(&mut checksum).unwrap();
+        self.0.write_all(b"+").unwrap();
+
+        String::from_utf8(reply).unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.0.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+}
+
+#[test]
+fn session() {
+    let (mut gdb, server) = Client::connect();
+
+    assert!(gdb.request("qSupported:swbreak+").contains("qXfer:features:read+"));
+    assert!(gdb.request("qXfer:features:read:target.xml:0,fff").starts_with("l<?xml"));
+    assert_eq!(gdb.request("vMustReplyEmpty"), "");
+    assert_eq!(gdb.request("?"), "S05");
+
+    // Registers
+    assert_eq!(gdb.request("g"), "000000ef");
+    assert_eq!(gdb.request("s"), "S05");
+    assert_eq!(gdb.request("g"), "026100ef");
+    assert_eq!(gdb.request("P1=62"), "OK");
+    assert_eq!(gdb.request("p1"), "62");
+    assert_eq!(gdb.request("p7"), "E01");
+
+    // Memory
+    assert_eq!(gdb.request("m0,4"), "11611212");
+    assert_eq!(gdb.request("M12,1:41"), "OK");
+    assert_eq!(gdb.request("m12,1"), "41");
+    assert_eq!(gdb.request("mff,2"), "E01");
+
+    // Breakpoints
+    assert_eq!(gdb.request("Z0,10,1"), "OK");
+    assert_eq!(gdb.request("c"), "T05swbreak:;");
+    assert_eq!(gdb.request("g"), "100000ef");
+    assert_eq!(gdb.request("z0,10,1"), "OK");
+    assert_eq!(gdb.request("Z2,12,1"), "");
+    assert_eq!(gdb.request("c"), "W00");
+
+    assert_eq!(gdb.request("D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn bad_checksum() {
+    let (mut gdb, server) = Client::connect();
+
+    gdb.0.write_all(b"$g#00").unwrap();
+    assert_eq!(gdb.read_byte(), b'-');
+    assert_eq!(gdb.request("g"), "000000ef");
+
+    drop(gdb);
+    server.join().unwrap();
+}
+
+#[test]
+fn devices_are_not_written() {
+    let (mut gdb, server) = Client::connect_to(|| {
+        let mut bus = MappedBus::new(Memory::from_program(&[0x50]));
+        bus.map(0xfc..=0xfc, Console::new(Script::default(), Buffer::new()));
+        Machine::with_bus(bus)
+    });
+
+    assert_eq!(gdb.request("Mfb,1:41"), "OK");
+    assert_eq!(gdb.request("Mfc,1:41"), "E0e");
+    assert_eq!(gdb.request("mfb,2"), "4100");
+
+    // A write that includes a device register doesn't write anything
+    assert_eq!(gdb.request("Mfa,3:424344"), "E0e");
+    assert_eq!(gdb.request("mfa,2"), "0041");
+
+    assert_eq!(gdb.request("D"), "OK");
+    server.join().unwrap();
+}
@@ -0,0 +1,79 @@
+//! Checks undoing steps with `History`.
+
+use shit_cpu_emu::{
+    Buffer, Console, History, Machine, MappedBus, Memory, Script, Snapshot, Status,
+};
+
+
+const MAGIC_1: &[u8] = include_bytes!("../programs/magic-1.bin");
+
+#[test]
+fn undo_every_step() {
+    let mut machine = Machine::from_program(MAGIC_1);
+    machine.set_output(Buffer::new());
+    let mut history = History::default();
+
+    let mut snapshots = vec![];
+    loop {
+        snapshots.push(Snapshot::capture(&machine));
+        if machine.step_traced(&mut history) != Ok(Status::Running) {
+            break;
+        }
+    }
+
+    // The final `stop` didn't change anything, so it isn't recorded
+    snapshots.pop();
+    assert_eq!(history.len(), snapshots.len());
+    while let Some(snapshot) = snapshots.pop() {
+        assert!(history.undo(&mut machine));
+        assert_eq!(Snapshot::capture(&machine), snapshot);
+    }
+    assert!(!history.undo(&mut machine));
+}
+
+#[test]
+fn last_write() {
+    let mut machine = Machine::from_program(MAGIC_1);
+    machine.set_output(Buffer::new());
+    let mut history = History::default();
+    machine.run_traced(&mut history).unwrap();
+
+    // The last `st [CHAR]` wrote 'z', then print, subi and jz followed
+    let char_addr = 0x12;
+    assert_eq!(machine.bus()[char_addr], b'z');
+    assert_eq!(history.last_write(char_addr), Some(4));
+    assert_eq!(history.last_write(0x00), None);
+
+    for _ in 0..4 {
+        history.undo(&mut machine);
+    }
+    assert_eq!(machine.bus()[char_addr], b'y');
+    assert_eq!(machine.acc(), b'z');
+}
+
+#[test]
+fn limit() {
+    let mut machine = Machine::from_program(MAGIC_1);
+    machine.set_output(Buffer::new());
+    let mut history = History::new(3);
+    machine.run_traced(&mut history).unwrap();
+
+    assert_eq!(history.len(), 3);
+}
+
+#[test]
+fn devices_are_not_written_again() {
+    // ldi $41; st [$fc]; st [$30]; stop
+    let program = [0x11, 0x41, 0x12, 0xfc, 0x12, 0x30, 0x50];
+    let console = Buffer::new();
+    let mut bus = MappedBus::new(Memory::from_program(&program));
+    bus.map(0xfc..=0xfc, Console::new(Script::default(), console.clone()));
+    let mut machine = Machine::with_bus(bus);
+    let mut history = History::default();
+    machine.run_traced(&mut history).unwrap();
+
+    while history.undo(&mut machine) {}
+    assert_eq!(machine.pc(), 0x00);
+    assert_eq!(machine.bus().ram()[0x30], 0x00);
+    assert_eq!(console.bytes(), b"A");
+}
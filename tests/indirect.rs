@@ -0,0 +1,69 @@
+//! Checks the indirect extension.
+
+use shit_cpu_emu::{Buffer, Extension, Fault, Machine, Status};
+
+
+/// Creates a machine for `program` with the indirect extension.
+fn machine(program: &[u8]) -> Machine {
+    let mut machine = Machine::from_program(program);
+    machine.enable_extension(Extension::Indirect);
+    machine
+}
+
+#[test]
+fn disabled_by_default() {
+    // ld [[$00]]
+    let mut machine = Machine::from_program(&[0x15, 0x00]);
+    assert_eq!(machine.run(), Err(Fault::IllegalOpcode { pc: 0x00, opcode: 0x15 }));
+}
+
+#[test]
+fn load_through_pointer() {
+    // ld [[$04]]; stop; nop; .byte $06; .byte $2a
+    let mut machine = machine(&[0x15, 0x04, 0x50, 0x00, 0x06, 0x00, 0x2a]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.acc(), 0x2a);
+}
+
+#[test]
+fn store_through_pointer() {
+    // ldi $2a; st [[$06]]; stop; nop; .byte $10
+    let mut machine = machine(&[0x11, 0x2a, 0x16, 0x06, 0x50, 0x00, 0x10]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.bus()[0x10], 0x2a);
+    assert_eq!(machine.bus()[0x06], 0x10);
+}
+
+#[test]
+fn jump_through_pointer() {
+    // jmp [$03]; stop; .byte $05; ldi $01; stop
+    let mut machine = machine(&[0x25, 0x03, 0x50, 0x05, 0x00, 0x11, 0x01, 0x50]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!((machine.pc(), machine.acc()), (0x07, 0x01));
+}
+
+#[test]
+fn string_loop() {
+    // Copies a zero terminated string to $f0 and prints it from there:
+    //
+    //     .loop: ld [[SRC]]; st [[DST]]; jz .end
+    //            ld [SRC]; addi $01; st [SRC]
+    //            ld [DST]; addi $01; st [DST]; jmp .loop
+    //     .end:  ld [DST]; subi $f0; st [$ef]; print [$ef]; stop
+    //     SRC: .byte .str; DST: .byte $f0
+    //     .str: "hi!" 0
+    let mut program = vec![
+        0x15, 0x20, 0x16, 0x21, 0x21, 0x14,
+        0x10, 0x20, 0x31, 0x01, 0x12, 0x20,
+        0x10, 0x21, 0x31, 0x01, 0x12, 0x21, 0x20, 0x00,
+        0x10, 0x21, 0x33, 0xf0, 0x12, 0xef, 0x40, 0xef, 0x50,
+    ];
+    program.resize(0x20, 0);
+    program.extend_from_slice(&[0x22, 0xf0, b'h', b'i', b'!', 0]);
+
+    let output = Buffer::new();
+    let mut machine = machine(&program);
+    machine.set_output(output.clone());
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(output.text(), "hi!\n");
+}
@@ -0,0 +1,38 @@
+//! Checks that programs can read input with `in`.
+
+use shit_cpu_emu::{Buffer, Machine, Script, Status};
+
+
+#[test]
+fn echo_input() {
+    let out = Buffer::new();
+    let mut machine = Machine::from_program(include_bytes!("../programs/echo.bin"));
+    machine.set_input(Script::new(&b"hi!"[..]));
+    machine.set_output(out.clone());
+
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(out.prints(), [b"h".to_vec(), b"i".to_vec(), b"!".to_vec()]);
+}
+
+#[test]
+fn end_of_input_keeps_acc() {
+    // in $04; stop; $04: ldi $ff; stop
+    let program = [0x41, 0x04, 0x50, 0x00, 0x11, 0xff, 0x50];
+    let mut machine = Machine::from_program(&program);
+    machine.set_acc(0x2a);
+
+    assert_eq!(machine.step(), Ok(Status::Running));
+    assert_eq!(machine.pc(), 0x04);
+    assert_eq!(machine.acc(), 0x2a);
+}
+
+#[test]
+fn read_byte() {
+    let program = [0x41, 0x04, 0x50, 0x00, 0x50];
+    let mut machine = Machine::from_program(&program);
+    machine.set_input(Script::new(vec![0x00]));
+
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.pc(), 0x02);
+    assert_eq!(machine.acc(), 0x00);
+}
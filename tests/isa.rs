@@ -0,0 +1,135 @@
+//! Checks executing an instruction set described in a file.
+
+use shit_cpu_emu::{
+    disasm, Buffer, Extension, Fault, FaultPolicy, InstructionSet, Machine, Profile, Script,
+    Status, Symbols,
+};
+
+
+/// An instruction set that only has a few instructions in common with the
+/// SHiT CPU.
+const ISA: &str = r#"
+[[instruction]]
+opcode = 0x01
+mnemonic = "load"
+operands = ["immediate"]
+semantics = ["mov acc, a0"]
+
+[[instruction]]
+opcode = 0x02
+mnemonic = "swap"
+operands = ["address"]
+semantics = ["mov t, [a0]", "mov [a0], acc", "mov acc, t"]
+
+[[instruction]]
+opcode = 0x03
+mnemonic = "dbnz"
+operands = ["address", "target"]
+semantics = ["sub [a0], $01", "mov acc, [a0]", "jnz a1"]
+
+[[instruction]]
+opcode = 0x04
+mnemonic = "save"
+operands = ["address"]
+extension = "stack"
+semantics = ["mov [a0], acc", "push acc"]
+
+[[instruction]]
+opcode = 0x05
+mnemonic = "puts"
+operands = ["address"]
+semantics = ["print a0"]
+
+[[instruction]]
+opcode = 0x06
+mnemonic = "getc"
+operands = ["target"]
+semantics = ["in acc, a0", "pop t"]
+
+[[instruction]]
+opcode = 0xff
+mnemonic = "hlt"
+semantics = ["halt"]
+"#;
+
+/// Creates a machine for `program` with the instruction set above.
+fn machine(program: &[u8]) -> Machine {
+    let mut machine = Machine::from_program(program);
+    machine.set_instruction_set(InstructionSet::parse(ISA).unwrap());
+    machine
+}
+
+#[test]
+fn custom_instructions() {
+    // load $07; swap [$0a]; .loop: dbnz [$0a] .loop; hlt; ... .byte $03
+    let mut program = vec![0x01, 0x07, 0x02, 0x0a, 0x03, 0x0a, 0x04, 0xff];
+    program.resize(0x0a, 0);
+    program.push(0x03);
+
+    let mut machine = machine(&program);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.pc(), 0x07);
+    assert_eq!(machine.acc(), 0x00);
+    assert_eq!(machine.bus()[0x0a], 0x00);
+}
+
+#[test]
+fn builtin_opcodes_are_illegal() {
+    // ldi $01
+    let mut machine = machine(&[0x11, 0x01]);
+    assert_eq!(machine.run(), Err(Fault::IllegalOpcode { pc: 0x00, opcode: 0x11 }));
+
+    // save [$10] without the stack extension
+    let mut machine = self::machine(&[0x04, 0x10]);
+    assert_eq!(machine.run(), Err(Fault::IllegalOpcode { pc: 0x00, opcode: 0x04 }));
+}
+
+#[test]
+fn faults_undo_the_instruction() {
+    // load $2a; save [$10]
+    let mut machine = machine(&[0x01, 0x2a, 0x04, 0x10]);
+    machine.enable_extension(Extension::Stack);
+    machine.set_fault_policy(FaultPolicy::Trap);
+    machine.set_stack_region(0x80..=0x80);
+    machine.set_sp(0x7f);
+
+    let trapped = Status::Trapped(Fault::StackOverflow { pc: 0x02 });
+    assert_eq!(machine.run(), Ok(trapped));
+    assert_eq!(machine.bus()[0x10], 0x00);
+    assert_eq!(machine.sp(), 0x7f);
+
+    machine.set_sp(0x80);
+    assert_eq!(machine.run_for(1), Ok(Status::Running));
+    assert_eq!((machine.bus()[0x10], machine.bus()[0x80]), (0x2a, 0x2a));
+}
+
+#[test]
+fn faults_keep_the_input() {
+    // getc $00, where the pop after reading the input finds the stack empty
+    let mut machine = machine(&[0x06, 0x00]);
+    machine.set_input(Script::new(*b"x"));
+    machine.set_fault_policy(FaultPolicy::Trap);
+    let trapped = Status::Trapped(Fault::StackUnderflow { pc: 0x00 });
+    assert_eq!(machine.step(), Ok(trapped));
+    assert_eq!(machine.acc(), 0x00);
+
+    // The same byte is read again
+    machine.set_sp(0xee);
+    assert_eq!(machine.step(), Ok(Status::Running));
+    assert_eq!((machine.acc(), machine.pc()), (b'x', 0x02));
+}
+
+#[test]
+fn tools_use_the_instruction_set() {
+    // puts [$04]; hlt; .byte $01 .byte 'x'
+    let output = Buffer::new();
+    let mut machine = machine(&[0x05, 0x04, 0xff, 0x00, 0x01, b'x']);
+    machine.set_output(output.clone());
+    let mut profile = Profile::new();
+    assert_eq!(machine.run_traced(&mut profile), Ok(Status::Halted));
+    assert_eq!(output.text(), "x\n");
+
+    let text = disasm::disassemble(machine.bus(), 0, &Symbols::new(), machine.instruction_set()).text;
+    assert_eq!(text, "puts [$04]");
+    assert!(profile.report(&Symbols::new()).contains("puts"));
+}
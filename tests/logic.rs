@@ -0,0 +1,56 @@
+//! Checks the logical instructions, one test per opcode.
+
+use shit_cpu_emu::{Machine, Status};
+
+
+/// Runs `program` until it halts and returns the accumulator.
+fn run(program: &[u8]) -> u8 {
+    let mut machine = Machine::from_program(program);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    machine.acc()
+}
+
+#[test]
+fn and() {
+    // ldi $5c; and [$05]; stop; .byte $f0
+    assert_eq!(run(&[0x11, 0x5c, 0x36, 0x05, 0x50, 0xf0]), 0x50);
+}
+
+#[test]
+fn andi() {
+    // ldi $5c; andi $0f; stop
+    assert_eq!(run(&[0x11, 0x5c, 0x37, 0x0f, 0x50]), 0x0c);
+}
+
+#[test]
+fn or() {
+    // ldi $5c; or [$05]; stop; .byte $f0
+    assert_eq!(run(&[0x11, 0x5c, 0x38, 0x05, 0x50, 0xf0]), 0xfc);
+}
+
+#[test]
+fn ori() {
+    // ldi $5c; ori $03; stop
+    assert_eq!(run(&[0x11, 0x5c, 0x39, 0x03, 0x50]), 0x5f);
+}
+
+#[test]
+fn xor() {
+    // ldi $5c; xor [$05]; stop; .byte $f0
+    assert_eq!(run(&[0x11, 0x5c, 0x3a, 0x05, 0x50, 0xf0]), 0xac);
+}
+
+#[test]
+fn xori() {
+    // ldi $5c; xori $5c; stop
+    assert_eq!(run(&[0x11, 0x5c, 0x3b, 0x5c, 0x50]), 0x00);
+}
+
+#[test]
+fn not() {
+    // ldi $5c; not; stop
+    assert_eq!(run(&[0x11, 0x5c, 0x3c, 0x50]), 0xa3);
+
+    // not; not; stop
+    assert_eq!(run(&[0x3c, 0x3c, 0x50]), 0x00);
+}
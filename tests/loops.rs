@@ -0,0 +1,117 @@
+//! Checks detecting infinite loops with `LoopDetector`.
+
+use shit_cpu_emu::{
+    Buffer, InstructionSet, Loop, LoopDetector, Machine, MappedBus, Memory, Script, Snapshot,
+    Status, Timer,
+};
+
+
+/// Runs `machine` for at most `steps` steps or until a loop is detected.
+fn detect(machine: &mut Machine, steps: usize) -> Option<Loop> {
+    let mut detector = LoopDetector::new();
+    for _ in 0..steps {
+        if machine.step_traced(&mut detector) != Ok(Status::Running) {
+            return None;
+        }
+        if detector.found().is_some() {
+            return detector.locate(machine);
+        }
+    }
+
+    None
+}
+
+#[test]
+fn jump_to_itself() {
+    // ldi $01; jmp $02
+    let mut machine = Machine::from_program(&[0x11, 0x01, 0x20, 0x02]);
+    assert_eq!(detect(&mut machine, 100), Some(Loop { entry: 0x02, length: 1 }));
+}
+
+#[test]
+fn counting_loop() {
+    // ldi $05; subi $01; jmp $02
+    //
+    // The accumulator wraps around, so the loop only repeats after counting
+    // through all 256 values.
+    let mut machine = Machine::from_program(&[0x11, 0x05, 0x33, 0x01, 0x20, 0x02]);
+    assert_eq!(detect(&mut machine, 10_000), Some(Loop { entry: 0x02, length: 512 }));
+}
+
+#[test]
+fn locate_keeps_state() {
+    // ldi $05; subi $01; jmp $02
+    let mut machine = Machine::from_program(&[0x11, 0x05, 0x33, 0x01, 0x20, 0x02]);
+    let mut detector = LoopDetector::new();
+    while detector.found().is_none() {
+        machine.step_traced(&mut detector).unwrap();
+    }
+
+    let before = Snapshot::capture(&machine);
+    assert!(detector.locate(&mut machine).is_some());
+    assert_eq!(Snapshot::capture(&machine), before);
+}
+
+#[test]
+fn failed_replay_is_not_located() {
+    // ldi $01; jmp $02
+    let mut machine = Machine::from_program(&[0x11, 0x01, 0x20, 0x02]);
+    let mut detector = LoopDetector::new();
+    while detector.found().is_none() {
+        machine.step_traced(&mut detector).unwrap();
+    }
+
+    // Without `jmp`, replaying the loop faults
+    let isa = "[[instruction]]\nopcode = 0x11\nmnemonic = \"ldi\"\noperands = [\"immediate\"]\n\
+        semantics = [\"mov acc, a0\"]\n";
+    machine.set_instruction_set(InstructionSet::parse(isa).unwrap());
+    let before = Snapshot::capture(&machine);
+    assert_eq!(detector.locate(&mut machine), None);
+    assert_eq!(Snapshot::capture(&machine), before);
+}
+
+#[test]
+fn ticking_devices_are_not_part_of_the_state() {
+    // jmp $00, with a timer that counts every step
+    let mut bus = MappedBus::new(Memory::from_program(&[0x20, 0x00]));
+    bus.map(0xfd..=0xfd, Timer::new());
+    let mut machine = Machine::with_bus(bus);
+
+    let mut detector = LoopDetector::new();
+    for _ in 0..10 {
+        machine.step_traced(&mut detector).unwrap();
+    }
+    assert_eq!(detector.found(), Some(1));
+    assert_eq!(detector.locate(&mut machine), Some(Loop { entry: 0x00, length: 1 }));
+}
+
+#[test]
+fn replay_prints_nothing() {
+    // print [$04]; jmp $00; .byte 1 .byte 'x'
+    let output = Buffer::new();
+    let mut machine = Machine::from_program(&[0x40, 0x04, 0x20, 0x00, 0x01, b'x']);
+    machine.set_output(output.clone());
+
+    let found = detect(&mut machine, 100).unwrap();
+    assert_eq!(found.length, 2);
+    let prints = output.prints().len();
+    machine.step().unwrap();
+    machine.step().unwrap();
+    assert_eq!(output.prints().len(), prints + 1);
+}
+
+#[test]
+fn input_is_not_part_of_the_state() {
+    // in $00; jmp $00
+    let mut machine = Machine::from_program(&[0x41, 0x00, 0x20, 0x00]);
+    machine.set_input(Script::new(vec![b'a'; 100]));
+    assert_eq!(detect(&mut machine, 1000), None);
+}
+
+#[test]
+fn halting_program() {
+    let mut machine = Machine::from_program(include_bytes!("../programs/magic-1.bin"));
+    machine.set_output(Buffer::new());
+    assert_eq!(detect(&mut machine, 100_000), None);
+    assert_eq!(machine.step(), Ok(Status::Halted));
+}
@@ -0,0 +1,60 @@
+//! Checks stepping the `Machine` and the `Status` it reports.
+
+use shit_cpu_emu::{Fault, FaultPolicy, Machine, Status};
+
+
+/// ldi $01; addi $01; jmp $02
+const COUNTER: &[u8] = &[0x11, 0x01, 0x31, 0x01, 0x20, 0x02];
+
+#[test]
+fn run_for_stops_after_exactly_n_steps() {
+    let mut machine = Machine::from_program(COUNTER);
+    assert_eq!(machine.run_for(5), Ok(Status::Running));
+    assert_eq!(machine.pc(), 0x02);
+    assert_eq!(machine.acc(), 0x03);
+
+    assert_eq!(machine.run_for(1), Ok(Status::Running));
+    assert_eq!(machine.pc(), 0x04);
+    assert_eq!(machine.acc(), 0x04);
+
+    // No steps leave the machine alone
+    assert_eq!(machine.run_for(0), Ok(Status::Running));
+    assert_eq!(machine.pc(), 0x04);
+}
+
+#[test]
+fn run_for_returns_early_when_halted() {
+    // ldi $2a; stop
+    let mut machine = Machine::from_program(&[0x11, 0x2a, 0x50]);
+    assert_eq!(machine.run_for(10), Ok(Status::Halted));
+    assert_eq!(machine.pc(), 0x02);
+}
+
+#[test]
+fn halted_machine_stays_halted() {
+    // ldi $2a; stop
+    let mut machine = Machine::from_program(&[0x11, 0x2a, 0x50]);
+    assert_eq!(machine.step(), Ok(Status::Running));
+    for _ in 0..3 {
+        assert_eq!(machine.step(), Ok(Status::Halted));
+        assert_eq!(machine.pc(), 0x02);
+        assert_eq!(machine.acc(), 0x2a);
+    }
+}
+
+#[test]
+fn trapped_leaves_pc_unchanged() {
+    // ldi $2a; .byte $ff
+    let mut machine = Machine::from_program(&[0x11, 0x2a, 0xff]);
+    machine.set_fault_policy(FaultPolicy::Trap);
+    assert_eq!(machine.step(), Ok(Status::Running));
+
+    let fault = Fault::IllegalOpcode { pc: 0x02, opcode: 0xff };
+    for _ in 0..3 {
+        assert_eq!(machine.step(), Ok(Status::Trapped(fault)));
+        assert_eq!(machine.pc(), 0x02);
+        assert_eq!(machine.acc(), 0x2a);
+    }
+    assert_eq!(machine.run(), Ok(Status::Trapped(fault)));
+    assert_eq!(machine.pc(), 0x02);
+}
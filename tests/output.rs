@@ -0,0 +1,30 @@
+//! Checks that program output can be captured with `Buffer`.
+
+use shit_cpu_emu::{Buffer, Machine, Status};
+
+
+#[test]
+fn capture_prints() {
+    let out = Buffer::new();
+    let mut machine = Machine::from_program(include_bytes!("../programs/magic-1.bin"));
+    machine.set_output(out.clone());
+
+    assert_eq!(machine.run(), Ok(Status::Halted));
+
+    let expected = (b'a'..=b'z').map(|c| vec![c]).collect::<Vec<_>>();
+    assert_eq!(out.prints(), expected);
+    assert_eq!(out.bytes(), (b'a'..=b'z').collect::<Vec<_>>());
+}
+
+#[test]
+fn print_is_not_lossy() {
+    // Prints the three bytes after the length byte at $04
+    let program = [0x40, 0x04, 0x50, 0x00, 0x03, 0xff, 0x00, 0x0a];
+    let out = Buffer::new();
+    let mut machine = Machine::from_program(&program);
+    machine.set_output(out.clone());
+
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(out.bytes(), [0xff, 0x00, 0x0a]);
+    assert_eq!(out.text(), "\u{fffd}\u{0}\n\n");
+}
@@ -0,0 +1,48 @@
+//! Checks the execution profile.
+
+use shit_cpu_emu::{Buffer, Machine, Profile, SourceMap, Status, Symbols};
+use shit_isa::Opcode;
+
+
+fn profile_magic_1() -> Profile {
+    let mut machine = Machine::from_program(include_bytes!("../programs/magic-1.bin"));
+    machine.set_output(Buffer::new());
+    let mut profile = Profile::new();
+    assert_eq!(machine.run_traced(&mut profile), Ok(Status::Halted));
+    profile
+}
+
+#[test]
+fn counts() {
+    let profile = profile_magic_1();
+
+    // `ldi`, 26 letters with 4 instructions, 25 loops back with 3 more, `stop`
+    assert_eq!(profile.total(), 1 + 26 * 4 + 25 * 3 + 1);
+    assert_eq!(profile.hits(0x00), 1);
+    assert_eq!(profile.hits(0x02), 26);
+    assert_eq!(profile.hits(0x0e), 25);
+    assert_eq!(profile.hits(0x11), 0);
+    assert_eq!(profile.opcode_hits(Opcode::Print), 26);
+    assert_eq!(profile.opcode_hits(Opcode::Stop), 1);
+
+    let symbols = Symbols::parse("02 start\n").unwrap();
+    let report = profile.report(&symbols);
+    assert!(report.starts_with("Executed 181 instructions"));
+    assert!(report.contains("  02    .start              26   14.4%"));
+}
+
+#[test]
+fn annotate() {
+    let profile = profile_magic_1();
+    let map = SourceMap::parse("file magic-1.s\n00 1\n02 3\n10 4\n").unwrap();
+    assert_eq!(map.source().to_str(), Some("magic-1.s"));
+
+    let annotated = profile.annotate("    ldi $61\n.start:\n    st [CHAR]\n    stop\n", &map);
+    let expected = [
+        "        1 |     ldi $61",
+        "          | .start:",
+        "       26 |     st [CHAR]",
+        "        1 |     stop",
+    ];
+    assert_eq!(annotated.lines().collect::<Vec<_>>(), expected);
+}
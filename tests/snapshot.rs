@@ -0,0 +1,99 @@
+//! Checks saving and restoring machine snapshots.
+
+use shit_cpu_emu::{
+    Buffer, Led, Machine, MappedBus, Memory, Rng, Snapshot, SnapshotError, Status, Timer,
+};
+
+
+const MAGIC_1: &[u8] = include_bytes!("../programs/magic-1.bin");
+
+#[test]
+fn resume_from_snapshot() {
+    let mut machine = Machine::from_program(MAGIC_1);
+    machine.set_output(Buffer::new());
+    assert_eq!(machine.run_for(50), Ok(Status::Running));
+
+    let bytes = Snapshot::capture(&machine).to_bytes();
+    let snapshot = Snapshot::from_bytes(&bytes).unwrap();
+
+    let out = Buffer::new();
+    let mut resumed = Machine::from_program(&[]);
+    resumed.set_output(out.clone());
+    snapshot.restore(&mut resumed).unwrap();
+    assert_eq!(resumed.pc(), machine.pc());
+    assert_eq!(resumed.acc(), machine.acc());
+    assert_eq!(resumed.bus().as_bytes(), machine.bus().as_bytes());
+
+    // magic-1 prints a..z, 7 instructions per letter
+    assert_eq!(resumed.run(), Ok(Status::Halted));
+    assert_eq!(out.bytes().first(), Some(&b'h'));
+    assert_eq!(out.bytes().last(), Some(&b'z'));
+}
+
+#[test]
+fn device_state() {
+    // ld [$fe]; st [$ff]; jmp $00
+    let program = [0x10, 0xfe, 0x12, 0xff, 0x20, 0x00];
+    let mapped = |led: Led| {
+        let mut bus = MappedBus::new(Memory::from_program(&program));
+        bus.map(0xfd..=0xfd, Timer::new());
+        bus.map(0xfe..=0xfe, Rng::new(1));
+        bus.map(0xff..=0xff, led);
+        Machine::with_bus(bus)
+    };
+
+    let led = Led::new();
+    let mut machine = mapped(led.clone());
+    machine.run_for(30).unwrap();
+    let snapshot = Snapshot::capture(&machine);
+
+    let restored_led = Led::new();
+    let mut restored = mapped(restored_led.clone());
+    snapshot.restore(&mut restored).unwrap();
+    assert_eq!(restored_led.value(), led.value());
+    assert_eq!(Snapshot::capture(&restored), snapshot);
+
+    machine.run_for(30).unwrap();
+    restored.run_for(30).unwrap();
+    assert_eq!(restored_led.value(), led.value());
+    assert_eq!(Snapshot::capture(&restored), Snapshot::capture(&machine));
+}
+
+#[test]
+fn mismatching_bus() {
+    let snapshot = Snapshot::capture(&Machine::from_program(MAGIC_1));
+
+    let mut bus = MappedBus::new(Memory::from_program(&[]));
+    bus.map(0xff..=0xff, Timer::new());
+    let mut machine = Machine::with_bus(bus);
+    machine.set_pc(0x42);
+
+    assert_eq!(snapshot.restore(&mut machine), Err(SnapshotError::BusMismatch));
+    assert_eq!(machine.pc(), 0x42);
+    assert_eq!(machine.bus().ram()[0], 0);
+}
+
+#[test]
+fn invalid_files() {
+    let bytes = Snapshot::capture(&Machine::from_program(MAGIC_1)).to_bytes();
+
+    assert_eq!(Snapshot::from_bytes(b"hello"), Err(SnapshotError::NotASnapshot));
+    assert_eq!(Snapshot::from_bytes(&bytes[..9]), Err(SnapshotError::Truncated));
+
+    let mut future = bytes;
+    future[8] = 99;
+    assert_eq!(Snapshot::from_bytes(&future), Err(SnapshotError::UnsupportedVersion(99)));
+}
+
+#[test]
+fn version_1() {
+    // Version 1 had no flags byte
+    let mut bytes = b"SHITSNAP\x01\x10\x42".to_vec();
+    bytes.extend_from_slice(&[0; 256]);
+
+    let mut machine = Machine::from_program(MAGIC_1);
+    machine.set_carry(true);
+    Snapshot::from_bytes(&bytes).unwrap().restore(&mut machine).unwrap();
+    assert_eq!((machine.pc(), machine.acc(), machine.carry()), (0x10, 0x42, false));
+    assert_eq!(machine.bus()[0], 0);
+}
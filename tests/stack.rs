@@ -0,0 +1,85 @@
+//! Checks the stack extension.
+
+use shit_cpu_emu::{Extension, Fault, FaultPolicy, Machine, Snapshot, Status, DEFAULT_STACK_REGION};
+
+
+/// Creates a machine for `program` with the stack extension.
+fn machine(program: &[u8]) -> Machine {
+    let mut machine = Machine::from_program(program);
+    machine.enable_extension(Extension::Stack);
+    machine
+}
+
+#[test]
+fn disabled_by_default() {
+    // push
+    let mut machine = Machine::from_program(&[0x60]);
+    assert_eq!(machine.run(), Err(Fault::IllegalOpcode { pc: 0x00, opcode: 0x60 }));
+}
+
+#[test]
+fn push_and_pop() {
+    // ldi $01; push; ldi $02; push; pop; st [$20]; pop; stop
+    let mut machine = machine(&[0x11, 0x01, 0x60, 0x11, 0x02, 0x60, 0x61, 0x12, 0x20, 0x61, 0x50]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!(machine.bus()[0x20], 0x02);
+    assert_eq!(machine.acc(), 0x01);
+    assert_eq!(machine.sp(), *DEFAULT_STACK_REGION.end());
+    assert_eq!(machine.bus().read_range(0xee..=0xef), [0x02, 0x01]);
+}
+
+#[test]
+fn call_and_ret() {
+    // call .f; call .f; stop; .f: addi $01; ret
+    let mut machine = machine(&[0x62, 0x05, 0x62, 0x05, 0x50, 0x31, 0x01, 0x63]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+    assert_eq!((machine.pc(), machine.acc()), (0x04, 0x02));
+    assert_eq!(machine.bus()[0xef], 0x04);
+}
+
+#[test]
+fn overflow() {
+    // .loop: push; jmp .loop
+    let mut machine = machine(&[0x60, 0x20, 0x00]);
+    machine.set_stack_region(0xf0..=0xf1);
+    assert_eq!(machine.run(), Err(Fault::StackOverflow { pc: 0x00 }));
+    assert_eq!(machine.sp(), 0xef);
+    assert_eq!(machine.bus()[0xef], 0x00);
+}
+
+#[test]
+fn underflow() {
+    // ret
+    let mut machine = machine(&[0x63]);
+    assert_eq!(machine.run(), Err(Fault::StackUnderflow { pc: 0x00 }));
+    assert_eq!(machine.sp(), *DEFAULT_STACK_REGION.end());
+
+    // A stack pointer outside of the region is rejected as well
+    let mut machine = self::machine(&[0x61]);
+    machine.set_sp(0x10);
+    assert_eq!(machine.run(), Err(Fault::StackUnderflow { pc: 0x00 }));
+}
+
+#[test]
+fn trap_on_overflow() {
+    // ldi $2a; push; push; stop
+    let mut machine = machine(&[0x11, 0x2a, 0x60, 0x60, 0x50]);
+    machine.set_stack_region(0x80..=0x80);
+    machine.set_fault_policy(FaultPolicy::Trap);
+    assert_eq!(machine.run(), Ok(Status::Trapped(Fault::StackOverflow { pc: 0x03 })));
+    assert_eq!(machine.pc(), 0x03);
+    assert_eq!(machine.bus()[0x80], 0x2a);
+}
+
+#[test]
+fn snapshot_keeps_stack_pointer() {
+    // ldi $01; push; stop
+    let mut machine = machine(&[0x11, 0x01, 0x60, 0x50]);
+    assert_eq!(machine.run(), Ok(Status::Halted));
+
+    let snapshot = Snapshot::capture(&machine);
+    let mut restored = self::machine(&[]);
+    snapshot.restore(&mut restored).unwrap();
+    assert_eq!(restored.sp(), 0xee);
+    assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+}
@@ -0,0 +1,91 @@
+//! Checks the lines written by `TraceWriter` in both formats.
+
+use shit_cpu_emu::{
+    Extension, FaultPolicy, InstructionSet, Machine, Symbols, TraceFormat, TraceWriter,
+};
+
+
+/// ld [$0a]; st [$20]; call $09; stop; .byte $00 $00 $ff $2a
+const PROGRAM: &[u8] = &[0x10, 0x0a, 0x12, 0x20, 0x62, 0x09, 0x50, 0x00, 0x00, 0xff, 0x2a];
+
+/// A call that also saves the accumulator on the stack, so that it writes
+/// two bytes.
+const ISA: &str = r#"
+[[instruction]]
+opcode = 0x01
+mnemonic = "callsave"
+operands = ["target"]
+semantics = ["push acc", "push pc", "jmp a0"]
+"#;
+
+/// Executes `steps` instructions of `machine` and returns the trace.
+fn trace(mut machine: Machine, format: TraceFormat, steps: usize) -> Vec<String> {
+    let mut out = vec![];
+    let mut writer = TraceWriter::new(&mut out, format, Symbols::new());
+    for _ in 0..steps {
+        let _ = machine.step_traced(&mut writer);
+    }
+    writer.finish().unwrap();
+    String::from_utf8(out).unwrap().lines().map(str::to_owned).collect()
+}
+
+/// Returns a machine running `PROGRAM` with the stack extension, which traps
+/// at the illegal opcode.
+fn machine() -> Machine {
+    let mut machine = Machine::from_program(PROGRAM);
+    machine.enable_extension(Extension::Stack);
+    machine.set_fault_policy(FaultPolicy::Trap);
+    machine
+}
+
+/// Returns a machine that executes `callsave $04` with the instruction set
+/// above and `acc` set to $2a.
+fn callsave() -> Machine {
+    let mut machine = Machine::from_program(&[0x01, 0x04]);
+    machine.set_instruction_set(InstructionSet::parse(ISA).unwrap());
+    machine.set_acc(0x2a);
+    machine
+}
+
+#[test]
+fn text() {
+    assert_eq!(trace(machine(), TraceFormat::Text, 4), [
+        "00:  10 0a      ld [$0a]          acc 00 -> 2a",
+        "02:  12 20      st [$20]          acc 2a -> 2a  [20] <- 2a",
+        "04:  62 09      call $09          acc 2a -> 2a  [ef] <- 06",
+        "09:  ff         .byte $ff         acc 2a -> 2a  fault: illegal opcode ff at 09",
+    ]);
+
+    assert_eq!(trace(callsave(), TraceFormat::Text, 1), [
+        "00:  01 04      callsave $04      acc 2a -> 2a  [ef] <- 2a  [ee] <- 02",
+    ]);
+}
+
+#[test]
+fn json() {
+    assert_eq!(trace(machine(), TraceFormat::Json, 4), [
+        concat!(
+            r#"{"pc":0,"bytes":[16,10],"instr":"ld [$0a]","acc_before":0,"acc_after":42,"#,
+            r#""writes":[]}"#,
+        ),
+        concat!(
+            r#"{"pc":2,"bytes":[18,32],"instr":"st [$20]","acc_before":42,"acc_after":42,"#,
+            r#""writes":[{"addr":32,"value":42}]}"#,
+        ),
+        concat!(
+            r#"{"pc":4,"bytes":[98,9],"instr":"call $09","acc_before":42,"acc_after":42,"#,
+            r#""writes":[{"addr":239,"value":6}]}"#,
+        ),
+        concat!(
+            r#"{"pc":9,"bytes":[255],"instr":".byte $ff","acc_before":42,"acc_after":42,"#,
+            r#""writes":[],"fault":"illegal opcode ff at 09"}"#,
+        ),
+    ]);
+
+    assert_eq!(trace(callsave(), TraceFormat::Json, 1), [
+        concat!(
+            r#"{"pc":0,"bytes":[1,4],"instr":"callsave $04","acc_before":42,"acc_after":42,"#,
+            r#""writes":[{"addr":239,"value":42},{"addr":238,"value":2}]}"#,
+        ),
+    ]);
+}